@@ -109,19 +109,40 @@ declare namespace senc {
      * @params out_path The path of the output file, relative to the output dir. Only one of out_path or out_ext can be
      *                  set.
      * @params out_ext The extension of the output file, including the preceding `.` (e.g., `.json`).
-     * @params out_type The type of the output file. Either JSON or YAML.
+     * @params out_type The type of the output file. One of JSON, YAML, YAML stream, TOML, HCL, dotenv,
+     *                  properties, INI, XML, plist, text, or binary.
      * @params out_prefix An optional string to prepend to the file output. This is useful for adding comments such as a
      *                    license header or a note about the file being autogenerated.
+     * @params out_mode An optional file mode to set on the output file after it is written, as an octal string (e.g.,
+     *                  "0755") or number (e.g., 0o755). This is useful for scripts that must be executable.
      * @params schema_path An optional path to a schema file to use for validating the rendered data. The path is
      *                     relative to the directory of the entrypoint. Currently only supports jsonschema.
-     * @params data The data to output to the output file. This can be any JSON/YAML serializable object.
+     * @params key_order The order of the object keys in the rendered output. Defaults to sorted (or the value of the
+     *                   --key-order CLI option). Use schema to follow the order of the properties in the schema.
+     * @params format Optional formatting options for the rendered output, overriding the --format CLI option.
+     * @params data The data to output to the output file. This can be any JSON/YAML serializable object, a string for
+     *              text outputs, or a Uint8Array/ArrayBuffer for binary outputs.
      */
     constructor(attrs: {
       out_path?: string;
       out_ext?: string;
-      out_type: "yaml" | "json";
+      out_type: "yaml" | "yaml_stream" | "json" | "toml" | "hcl" | "dotenv" | "properties" | "ini" | "xml" | "plist" | "text" | "binary";
       out_prefix?: string;
+      out_mode?: string | number;
       schema_path?: string;
+      key_order?: "insertion" | "sorted" | "schema";
+      format?: {
+        indent?: number;
+        tabs?: boolean;
+        compact?: boolean;
+        canonical?: boolean;
+        ascii?: boolean;
+        trailing_newline?: boolean;
+        line_ending?: "lf" | "crlf";
+        yaml_quote?: "auto" | "single" | "double";
+        yaml_block_strings?: boolean;
+        yaml_anchors?: boolean;
+      };
       data: any;
     });
 
@@ -157,7 +178,8 @@ declare namespace senc {
 
   /**
    * Import the given file path as a YAML object. This equivalent to loading the file from disk and parsing it using
-   * YAML.parse.
+   * YAML.parse. The file must have a single document, unless all_documents is set, in which case the file is always
+   * imported as an array of its documents (separated by `---`).
    *
    * NOTE:
    * - The provided path must be an absolute path. Use `__dirname` to construct the import path.
@@ -165,7 +187,74 @@ declare namespace senc {
    *
    * ```js
    * const cfg = await import_yaml(`${__dirname}/someconfig.yaml`);
+   * const manifests = await import_yaml(`${__dirname}/manifests.yaml`, { all_documents: true });
    * ```
    */
-  function import_yaml(p: string): Promise<any>;
+  function import_yaml(p: string, opts?: { all_documents?: boolean }): Promise<any>;
+
+  /**
+   * Helpers for building the data of HCL outputs (`out_type: "hcl"`).
+   *
+   * ```js
+   * const data = {
+   *   resource: senc.hcl.block(["aws_s3_bucket", "assets"], {
+   *     bucket: senc.hcl.expr("var.bucket_name"),
+   *   }),
+   * };
+   * ```
+   */
+  namespace hcl {
+    /**
+     * Render the given string as a raw HCL expression (e.g., `var.x` or `"${local.prefix}-app"`), instead of a string
+     * literal.
+     */
+    function expr(e: string): any;
+
+    /**
+     * Render a block with the given labels and body. The block type is the key that the block is assigned to. Assign
+     * an array of blocks to a key to render multiple blocks of the same type.
+     */
+    function block(labels: string[], body?: { [key: string]: any }): any;
+  }
+
+  /**
+   * Helpers for building the data of YAML outputs (`out_type: "yaml"` or `"yaml_stream"`).
+   *
+   * ```js
+   * const checkout = senc.yaml.anchor("checkout", { uses: "actions/checkout@v4" });
+   * const data = { build: { steps: [checkout] }, test: { steps: [checkout] } };
+   * ```
+   */
+  namespace yaml {
+    /**
+     * Render the value with the anchor `&name` the first time it is emitted, and as the alias `*name` everywhere else.
+     * All the values marked with the same name must be equal. Other output types render the value as is.
+     */
+    function anchor<T>(name: string, value: T): T;
+  }
+
+  /**
+   * Attach a comment to the value, which is rendered above the key or array item the value is assigned to in `yaml`,
+   * `yaml_stream`, `toml`, and `hcl` outputs. Other output types render the value as is.
+   *
+   * ```js
+   * const data = { "runs-on": senc.comment("ubuntu-latest", "Runs on every push") };
+   * ```
+   */
+  function comment<T>(value: T, comment: string): T;
+
+  /**
+   * The exported functions of the WebAssembly plugins declared for the project with the `--plugin` CLI option, keyed
+   * by the plugin name.
+   *
+   * ```js
+   * const size = senc.plugins.cidr.subnet_size(24);
+   * ```
+   */
+  const plugins: { [name: string]: { [fn: string]: (...args: any[]) => any } };
+
+  /**
+   * The custom extensions registered by the Rust program embedding senc, keyed by the extension name.
+   */
+  namespace ext {}
 }
@@ -0,0 +1,183 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::yamlanchors::{child_pointer, display_pointer, Anchors, ANCHOR_MARKER};
+
+// The key of the marker objects returned by senc.comment.
+const COMMENT_MARKER: &str = "__senc_comment";
+
+// The annotations that are attached to the nodes of the output data with marker objects (e.g.,
+// senc.comment), keyed by the JSON pointer of the annotated node. The annotations are not part of
+// the data, and are only rendered by the serializers of the output types that support them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Annotations {
+    anchors: Anchors,
+    comments: BTreeMap<String, String>,
+}
+
+impl Annotations {
+    // Replace the marker objects in the data with their values, returning the annotations that were
+    // attached. This is done for all output types so that the markers never end up in the rendered
+    // outputs, and so that the data is validated without them.
+    //
+    // NOTE
+    // Markers can wrap each other (e.g., a commented anchor), so the comments are extracted first,
+    // looking through the senc.yaml.anchor markers, and the anchors are extracted after.
+    pub fn extract(data: &mut serde_json::Value) -> Result<Annotations> {
+        let mut comments = BTreeMap::new();
+        extract_comments_at(data, "", &mut comments)?;
+        let anchors = Anchors::extract(data)?;
+        return Ok(Annotations { anchors, comments });
+    }
+
+    // The annotations within the i-th element of the data, relative to that element (e.g., for the
+    // documents of a yaml_stream output).
+    pub fn for_document(&self, i: usize) -> Annotations {
+        let prefix = format!("/{i}");
+        let comments = self
+            .comments
+            .iter()
+            .filter_map(|(p, c)| {
+                let rest = p.strip_prefix(&prefix)?;
+                if rest.is_empty() || rest.starts_with('/') {
+                    Some((rest.to_string(), c.clone()))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        Annotations {
+            anchors: self.anchors.for_document(i),
+            comments,
+        }
+    }
+
+    // The anchors marked with senc.yaml.anchor.
+    pub fn anchors(&self) -> &Anchors {
+        &self.anchors
+    }
+
+    // The comment attached to the node at |p| with senc.comment.
+    pub fn comment(&self, p: &str) -> Option<&str> {
+        self.comments.get(p).map(|c| c.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty() && self.comments.is_empty()
+    }
+}
+
+fn extract_comments_at(
+    v: &mut serde_json::Value,
+    p: &str,
+    comments: &mut BTreeMap<String, String>,
+) -> Result<()> {
+    loop {
+        if let Some(marker) = v.get_mut(COMMENT_MARKER) {
+            let mut marker = marker.take();
+            let comment = match marker.get("comment") {
+                Some(serde_json::Value::String(c)) => c.clone(),
+                _ => return Err(anyhow!("[{}] comment must be a string", display_pointer(p))),
+            };
+            *v = marker_value(&mut marker);
+            // Multiple comments on the same node are rendered one after the other.
+            comments
+                .entry(p.to_string())
+                .and_modify(|c| *c = format!("{c}\n{comment}"))
+                .or_insert(comment);
+        } else if let Some(serde_json::Value::Object(marker)) = v.get_mut(ANCHOR_MARKER) {
+            // The anchored value is at the same path as the anchor marker once it is extracted.
+            if let Some(anchored) = marker.get_mut("value") {
+                extract_comments_at(anchored, p, comments)?;
+            }
+            return Ok(());
+        } else {
+            break;
+        }
+    }
+
+    match v {
+        serde_json::Value::Object(obj) => {
+            for (k, item) in obj.iter_mut() {
+                extract_comments_at(item, &child_pointer(p, k), comments)?;
+            }
+        }
+        serde_json::Value::Array(arr) => {
+            for (i, item) in arr.iter_mut().enumerate() {
+                extract_comments_at(item, &format!("{p}/{i}"), comments)?;
+            }
+        }
+        _ => {}
+    }
+    return Ok(());
+}
+
+fn marker_value(marker: &mut serde_json::Value) -> serde_json::Value {
+    match marker.get_mut("value") {
+        Some(v) => v.take(),
+        None => serde_json::Value::Null,
+    }
+}
+
+// Render the comment as lines of line comments with the given prefix and indentation (e.g., `# `
+// for YAML).
+pub fn comment_lines(comment: &str, indent: &str, prefix: &str) -> String {
+    let mut out = String::new();
+    for line in comment.lines() {
+        let line = format!("{indent}{prefix} {line}");
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_annotations_extracts_markers() {
+        let step = json!({
+            "__senc_comment": {
+                "comment": "Check out the repository",
+                "value": {
+                    "__senc_yaml_anchor": {"name": "checkout", "value": {"uses": "actions/checkout@v4"}},
+                },
+            },
+        });
+        let mut data = json!([{"steps": [step.clone()]}, {"steps": [step]}]);
+        let annotations = Annotations::extract(&mut data).unwrap();
+        assert_eq!(
+            data,
+            json!([
+                {"steps": [{"uses": "actions/checkout@v4"}]},
+                {"steps": [{"uses": "actions/checkout@v4"}]},
+            ])
+        );
+        assert!(!annotations.for_document(1).anchors().is_empty());
+        assert_eq!(
+            annotations.for_document(0).comment("/steps/0"),
+            Some("Check out the repository")
+        );
+        assert!(annotations.for_document(2).is_empty());
+
+        let mut data = json!({"a": {"__senc_comment": {"comment": 1, "value": 1}}});
+        let err = Annotations::extract(&mut data).unwrap_err();
+        assert_eq!(err.to_string(), "[/a] comment must be a string");
+    }
+
+    #[test]
+    fn test_annotations_renders_comment_lines() {
+        assert_eq!(
+            comment_lines("Managed by senc.\n\nDo not edit.", "  ", "#"),
+            "  # Managed by senc.\n  #\n  # Do not edit.\n"
+        );
+    }
+}
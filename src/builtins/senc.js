@@ -6,6 +6,11 @@
 // - senc.OutDataArray
 // - senc.import_json
 // - senc.import_yaml
+// - senc.hcl (helpers for building the data of hcl outputs)
+// - senc.yaml (helpers for building the data of yaml outputs)
+// - senc.comment
+// - senc.ext (namespace for custom extensions registered by embedders)
+// - senc.plugins (namespace for WebAssembly plugins declared for the project)
 
 ((globalThis) => {
   const is_senc_out_data = (a) => {
@@ -30,7 +35,10 @@
       this.out_path = attrs.out_path;
       this.out_ext = attrs.out_ext;
       this.out_prefix = attrs.out_prefix
+      this.out_mode = attrs.out_mode
       this.schema_path = attrs.schema_path
+      this.key_order = attrs.key_order
+      this.format = attrs.format
       this.data = attrs.data;
     }
 
@@ -67,10 +75,61 @@
     return (await import(`file://${p}`, { with: { type: "json" } })).default;
   }
 
+  // Dynamically import a yaml file as json. The all_documents option is passed to the module loader as a query, so
+  // that the file is always loaded as an array of its documents.
+  const import_yaml = async (p, opts) => {
+    const query = opts && opts.all_documents ? "?all_documents" : "";
+    return (await import(`file://${p}${query}`, { with: { type: "json" } })).default;
+  }
+
+  // Helpers for building the data of hcl outputs. These return marker objects that the hcl serializer renders as
+  // raw expressions and blocks respectively.
+  const hcl = {
+    expr: (e) => {
+      if (typeof e !== "string") {
+        throw new Error("senc.hcl.expr must be called with a string");
+      }
+      return { __senc_hcl_expr: e };
+    },
+    block: (labels, body) => {
+      if (!Array.isArray(labels)) {
+        throw new Error("senc.hcl.block labels must be an array of strings");
+      }
+      return { __senc_hcl_block: { labels: labels, body: body || {} } };
+    },
+  };
+
+  // Helpers for building the data of yaml outputs. senc.yaml.anchor returns a marker object that the yaml serializer
+  // renders with an anchor the first time it is emitted, and as an alias everywhere else. Other serializers render the
+  // value as is.
+  const yaml = {
+    anchor: (name, value) => {
+      if (typeof name !== "string") {
+        throw new Error("senc.yaml.anchor name must be a string");
+      }
+      return { __senc_yaml_anchor: { name: name, value: value } };
+    },
+  };
+
+  // Attach a comment to the value. This returns a marker object that the serializers of the output types that
+  // support comments (yaml, toml, and hcl) render as a comment above the key or array item the value is assigned to.
+  // Other serializers render the value as is.
+  const comment = (value, c) => {
+    if (typeof c !== "string") {
+      throw new Error("senc.comment must be called with a string comment");
+    }
+    return { __senc_comment: { comment: c, value: value } };
+  }
+
   globalThis.senc = {
     OutData: OutData,
     OutDataArray: OutDataArray,
     import_json: import_json,
-    import_yaml: import_json,
+    import_yaml: import_yaml,
+    hcl: hcl,
+    yaml: yaml,
+    comment: comment,
+    ext: {},
+    plugins: {},
   };
 })(globalThis);
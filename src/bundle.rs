@@ -0,0 +1,500 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections;
+use std::fs;
+use std::io::BufRead;
+use std::path;
+use std::pin;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use deno_core::futures::FutureExt;
+use deno_core::*;
+use serde::{Deserialize, Serialize};
+
+use crate::engine;
+use crate::files;
+use crate::formatting;
+use crate::header;
+use crate::keyorder;
+use crate::plugins;
+
+// The first line of every bundle, used to identify bundles and the version of the bundle format.
+pub const BUNDLE_MARKER: &str = "// senc bundle v1";
+
+// The virtual directory that the project is mounted at when running a bundle. The paths of the
+// bundled modules (and the builtin path constants like __dirname) are all rooted here, so that the
+// bundle renders the same way regardless of where it is run.
+pub const VIRTUAL_ROOT: &str = "/__senc_bundle__";
+
+// The virtual directory that modules from a node_modules directory outside the project root are
+// mounted at, relative to the virtual root.
+const VIRTUAL_NODE_MODULES_DIR: &str = "__node_modules__";
+
+// A project bundled into a single self-contained artifact. This contains the transpiled code of
+// the entrypoint, all the modules it imports (including node_modules packages and JSON/YAML data),
+// the schemas the outputs are validated against, and the WebAssembly plugins declared for the
+// project, so that the entrypoint can be rendered without the original project tree.
+//
+// The bundle is stored as an ES module, with the bundle data as the default export.
+#[derive(Serialize, Deserialize)]
+pub struct Bundle {
+    // The path of the entrypoint, relative to the project root.
+    pub entrypoint: String,
+
+    // The modules in the bundle, keyed by the virtual module specifier.
+    pub modules: collections::BTreeMap<String, BundledModule>,
+
+    // The node_modules resolutions of bare specifiers, keyed by the virtual specifier of the
+    // referrer and then the bare specifier.
+    pub resolutions: collections::BTreeMap<String, collections::BTreeMap<String, String>>,
+
+    // Other files that were read to render the entrypoint (e.g., schemas), keyed by the virtual
+    // path.
+    pub files: collections::BTreeMap<String, String>,
+
+    // The WebAssembly plugins declared when the bundle was created, keyed by the plugin name. The
+    // modules are base64 encoded.
+    #[serde(default)]
+    pub plugins: collections::BTreeMap<String, String>,
+
+    // The render settings that the bundle was created with, which are used when rendering the
+    // bundle from a standalone executable.
+    #[serde(default)]
+    pub settings: RenderSettings,
+}
+
+// The settings that change how the outputs are rendered, as opposed to which outputs are rendered
+// and where they are written.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderSettings {
+    pub key_order: keyorder::KeyOrder,
+    pub format: formatting::FormatOptions,
+    pub generated_header: Option<header::GeneratedHeader>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BundledModule {
+    // The module type, which is either javascript or json.
+    #[serde(rename = "type")]
+    pub module_type: String,
+    pub code: String,
+}
+
+impl Bundle {
+    // Load the bundle at the given path.
+    pub fn load(p: &path::Path) -> Result<Bundle> {
+        let contents = fs::read_to_string(p)?;
+        Bundle::parse(&contents).map_err(|e| anyhow!("could not load bundle {}: {e}", p.display()))
+    }
+
+    // Parse the bundle from the contents of a bundle file.
+    pub fn parse(contents: &str) -> Result<Bundle> {
+        if contents.lines().next() != Some(BUNDLE_MARKER) {
+            return Err(anyhow!("not a senc bundle"));
+        }
+        let data = contents
+            .lines()
+            .find_map(|l| l.strip_prefix("export default "))
+            .and_then(|l| l.strip_suffix(';'))
+            .ok_or_else(|| anyhow!("bundle is missing the default export"))?;
+        Ok(serde_json::from_str(data)?)
+    }
+
+    // Render the bundle as an ES module.
+    pub fn to_module(&self) -> Result<String> {
+        let data = serde_json::to_string(self)?;
+        return Ok(format!(
+            "{BUNDLE_MARKER}\n// Generated by `senc bundle` from {}. Run with `senc <path to this file>`.\nexport default {data};\n",
+            self.entrypoint
+        ));
+    }
+
+    // The virtual path of the entrypoint.
+    pub fn entrypoint_path(&self) -> path::PathBuf {
+        path::Path::new(VIRTUAL_ROOT).join(&self.entrypoint)
+    }
+
+    // The request to render the entrypoint of the bundle into the given output directory. The
+    // outputs are rendered to the same paths relative to the output directory as they would be
+    // when rendering the original project.
+    pub fn run_request(&self, out_dir: &path::Path) -> Result<engine::RunRequest> {
+        let in_file = self.entrypoint_path();
+        let out_file_stem =
+            files::get_out_file_stem(&in_file, out_dir, path::Path::new(VIRTUAL_ROOT))?;
+        Ok(engine::RunRequest {
+            in_file: String::from(in_file.to_string_lossy()),
+            out_file_stem,
+        })
+    }
+
+    // Decode the WebAssembly plugins embedded in the bundle.
+    pub fn wasm_plugins(&self) -> Result<Vec<plugins::WasmPlugin>> {
+        let mut out = Vec::with_capacity(self.plugins.len());
+        for (name, encoded) in self.plugins.iter() {
+            let code = STANDARD
+                .decode(encoded)
+                .map_err(|e| anyhow!("plugin {name} in the bundle is not valid base64: {e}"))?;
+            let p = path::Path::new(VIRTUAL_ROOT).join(format!("{name}.wasm"));
+            out.push(plugins::WasmPlugin::from_code(name, &p, code)?);
+        }
+        return Ok(out);
+    }
+
+    // Read one of the non-module files in the bundle by its virtual path.
+    pub fn read_file(&self, p: &path::Path) -> Result<&str> {
+        let p = path_clean::clean(p);
+        match self.files.get(p.to_string_lossy().as_ref()) {
+            Some(contents) => Ok(contents),
+            None => Err(anyhow!("{} is not included in the bundle", p.display())),
+        }
+    }
+}
+
+// Whether the file at the given path is a senc bundle.
+pub fn is_bundle(p: &path::Path) -> bool {
+    let f = match fs::File::open(p) {
+        Ok(f) => f,
+        Err(_) => return false,
+    };
+    let mut first_line = String::new();
+    match std::io::BufReader::new(f).read_line(&mut first_line) {
+        Ok(_) => first_line.trim_end() == BUNDLE_MARKER,
+        Err(_) => false,
+    }
+}
+
+// Bundle the given entrypoint. The entrypoint is rendered once with the top-level args of the
+// context to record the modules it loads, so only the modules that are imported in that render are
+// included. In particular, dynamic imports that depend on the top-level args may be missing.
+pub fn create(ctx: &engine::Context, entrypoint: &path::Path) -> Result<Bundle> {
+    let recorder = std::sync::Arc::new(ModuleRecorder::default());
+    let mut ctx = ctx.clone();
+    ctx.write_to_disk = false;
+    ctx.module_recorder = Some(recorder.clone());
+
+    if !entrypoint.is_file() {
+        return Err(anyhow!(
+            "{} must be a single .sen.js or .sen.ts file to bundle",
+            entrypoint.display()
+        ));
+    }
+    let req = &ctx.run_requests_from_path(entrypoint)?[0];
+    let run_output = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(ctx.run_with_inputs(req))?;
+
+    let mapper = VirtualPathMapper {
+        projectroot: &ctx.projectroot,
+        node_modules_dir: ctx.node_modules_dir.as_deref(),
+    };
+    let recorded = recorder.recorded.lock().unwrap();
+
+    let mut module_paths = collections::HashSet::new();
+    let mut modules = collections::BTreeMap::new();
+    for (specifier, m) in recorded.modules.iter() {
+        module_paths.insert(m.path.clone());
+        let module = BundledModule {
+            module_type: m.module_type.clone(),
+            code: m.code.clone(),
+        };
+        modules.insert(mapper.to_virtual_specifier(specifier)?, module);
+    }
+
+    let mut resolutions = collections::BTreeMap::new();
+    for ((referrer, specifier), resolved) in recorded.resolutions.iter() {
+        resolutions
+            .entry(mapper.to_virtual_specifier(referrer)?)
+            .or_insert_with(collections::BTreeMap::new)
+            .insert(specifier.clone(), mapper.to_virtual_specifier(resolved)?);
+    }
+
+    let mut files = collections::BTreeMap::new();
+    for p in run_output.inputs.iter() {
+        if module_paths.contains(p) {
+            continue;
+        }
+        let virtual_path = mapper.to_virtual_path(p)?;
+        files.insert(
+            String::from(virtual_path.to_string_lossy()),
+            fs::read_to_string(p)?,
+        );
+    }
+
+    let plugins = ctx
+        .wasm_plugins
+        .iter()
+        .map(|p| (p.name.clone(), STANDARD.encode(p.code())))
+        .collect();
+
+    let entrypoint = path::Path::new(&req.in_file).strip_prefix(&ctx.projectroot)?;
+    Ok(Bundle {
+        entrypoint: String::from(entrypoint.to_string_lossy()),
+        modules,
+        resolutions,
+        files,
+        plugins,
+        settings: RenderSettings {
+            key_order: ctx.key_order,
+            format: ctx.format.clone(),
+            generated_header: ctx.generated_header.clone(),
+        },
+    })
+}
+
+// Maps the real paths of the files loaded while bundling to virtual paths in the bundle.
+struct VirtualPathMapper<'a> {
+    projectroot: &'a path::Path,
+    node_modules_dir: Option<&'a path::Path>,
+}
+
+impl<'a> VirtualPathMapper<'a> {
+    fn to_virtual_path(&self, p: &path::Path) -> Result<path::PathBuf> {
+        let root = path::Path::new(VIRTUAL_ROOT);
+        if let Ok(rel) = p.strip_prefix(self.projectroot) {
+            return Ok(root.join(rel));
+        }
+        if let Some(rel) = self.node_modules_dir.and_then(|d| p.strip_prefix(d).ok()) {
+            return Ok(root.join(VIRTUAL_NODE_MODULES_DIR).join(rel));
+        }
+        Err(anyhow!(
+            "{} is outside the project root and node_modules directory",
+            p.display()
+        ))
+    }
+
+    fn to_virtual_specifier(&self, specifier: &str) -> Result<String> {
+        let real_path = ModuleSpecifier::parse(specifier)?
+            .to_file_path()
+            .map_err(|_| anyhow!("{specifier} is not a file module"))?;
+        let virtual_path = self.to_virtual_path(&real_path)?;
+        let virtual_specifier = ModuleSpecifier::from_file_path(&virtual_path)
+            .map_err(|_| anyhow!("{} is not a valid module path", virtual_path.display()))?;
+        Ok(virtual_specifier.to_string())
+    }
+}
+
+// Records the modules that are loaded by the module loader, along with the node_modules
+// resolutions of bare specifiers, so that they can be bundled.
+#[derive(Default)]
+pub struct ModuleRecorder {
+    recorded: Mutex<RecordedModules>,
+}
+
+#[derive(Default)]
+struct RecordedModules {
+    // The loaded modules, keyed by the module specifier.
+    modules: collections::BTreeMap<String, RecordedModule>,
+    // The resolved specifiers, keyed by the referrer and the bare specifier.
+    resolutions: collections::BTreeMap<(String, String), String>,
+}
+
+struct RecordedModule {
+    path: path::PathBuf,
+    module_type: String,
+    code: String,
+}
+
+impl ModuleRecorder {
+    pub fn record_module(
+        &self,
+        specifier: &ModuleSpecifier,
+        p: &path::Path,
+        module_type: &ModuleType,
+        code: &str,
+    ) {
+        let module_type = match module_type {
+            ModuleType::Json => "json",
+            _ => "javascript",
+        };
+        let m = RecordedModule {
+            path: p.to_path_buf(),
+            module_type: String::from(module_type),
+            code: String::from(code),
+        };
+        self.recorded
+            .lock()
+            .unwrap()
+            .modules
+            .insert(specifier.to_string(), m);
+    }
+
+    pub fn record_resolution(&self, referrer: &str, specifier: &str, resolved: &ModuleSpecifier) {
+        self.recorded.lock().unwrap().resolutions.insert(
+            (String::from(referrer), String::from(specifier)),
+            resolved.to_string(),
+        );
+    }
+}
+
+// The module loader for running a bundle. All modules are served from the bundle, so nothing is
+// read from disk.
+pub struct BundleModuleLoader {
+    bundle: std::sync::Arc<Bundle>,
+}
+
+impl BundleModuleLoader {
+    pub fn new(bundle: std::sync::Arc<Bundle>) -> BundleModuleLoader {
+        BundleModuleLoader { bundle }
+    }
+}
+
+impl ModuleLoader for BundleModuleLoader {
+    fn resolve(
+        &self,
+        specifier: &str,
+        referrer: &str,
+        _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, error::AnyError> {
+        let recorded = self
+            .bundle
+            .resolutions
+            .get(referrer)
+            .and_then(|r| r.get(specifier));
+        match recorded {
+            Some(resolved) => Ok(ModuleSpecifier::parse(resolved)?),
+            None => resolve_import(specifier, referrer).map_err(|e| e.into()),
+        }
+    }
+
+    fn load(
+        &self,
+        module_specifier: &ModuleSpecifier,
+        _maybe_referrer: Option<&ModuleSpecifier>,
+        _is_dyn_import: bool,
+    ) -> pin::Pin<Box<ModuleSourceFuture>> {
+        let result = match self.bundle.modules.get(module_specifier.as_str()) {
+            Some(m) => {
+                let module_type = match m.module_type.as_str() {
+                    "json" => ModuleType::Json,
+                    _ => ModuleType::JavaScript,
+                };
+                let code = FastString::from(m.code.clone());
+                Ok(ModuleSource::new(module_type, code, module_specifier))
+            }
+            None => Err(anyhow!(
+                "can not import {module_specifier}: module is not included in the bundle"
+            )),
+        };
+        futures::future::ready(result).boxed_local()
+    }
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_bundle_renders_without_project_tree() {
+        for fixture in ["with_lodash.js", "import_yaml.js", "jsonschema/pass.js"] {
+            let ctx = engine::Context::builder(get_fixture_path(""))
+                .node_modules_dir(Some(get_fixture_path("node_modules")))
+                .write_to_disk(false)
+                .build()
+                .expect("error building context");
+            let b = create(&ctx, &get_fixture_path(fixture)).expect("error creating bundle");
+            assert_eq!(b.entrypoint, fixture);
+
+            // Round trip the bundle through the module format, and make sure that the outputs of
+            // the bundle are the same as rendering the original entrypoint.
+            let b = Bundle::parse(&b.to_module().expect("error rendering bundle"))
+                .expect("error parsing bundle");
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("error creating tokio runtime");
+            let expected = runtime
+                .block_on(ctx.render(&get_fixture_path(fixture)))
+                .expect("error rendering entrypoint");
+
+            let mut bundle_ctx = ctx.clone();
+            bundle_ctx.node_modules_dir = None;
+            bundle_ctx.bundle = Some(std::sync::Arc::new(b));
+            let req = bundle_ctx
+                .run_requests_from_path(&get_fixture_path(""))
+                .expect("error getting bundle request")
+                .remove(0);
+            assert_eq!(req.out_file_stem, expected[0].request.out_file_stem);
+            let actual = runtime
+                .block_on(bundle_ctx.run(&req))
+                .expect("error rendering bundle");
+            assert_eq!(actual.len(), 1);
+            assert_eq!(actual[0].data(), expected[0].out_data[0].data());
+        }
+    }
+
+    #[test]
+    fn test_bundle_embeds_wasm_plugins() {
+        let ctx = engine::Context::builder(get_fixture_path(""))
+            .node_modules_dir(None)
+            .write_to_disk(false)
+            .wasm_plugin("math", "plugins/add.wasm")
+            .build()
+            .expect("error building context");
+        let b = create(&ctx, &get_fixture_path("wasm_plugin.js")).expect("error creating bundle");
+        let b = Bundle::parse(&b.to_module().expect("error rendering bundle"))
+            .expect("error parsing bundle");
+        let bundle_path = env::temp_dir().join(format!("{}.bundle.js", uuid::Uuid::new_v4()));
+        fs::write(&bundle_path, b.to_module().expect("error rendering bundle"))
+            .expect("error writing bundle");
+
+        // The plugin is loaded from the bundle, so it does not need to be declared again.
+        let bundle_ctx = engine::Context::builder(get_fixture_path(""))
+            .node_modules_dir(None)
+            .write_to_disk(false)
+            .bundle(&bundle_path)
+            .build();
+        fs::remove_file(&bundle_path).expect("error removing bundle");
+        let bundle_ctx = bundle_ctx.expect("error building bundle context");
+        let req = bundle_ctx
+            .run_requests_from_path(&get_fixture_path(""))
+            .expect("error getting bundle request")
+            .remove(0);
+        let actual = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("error creating tokio runtime")
+            .block_on(bundle_ctx.run(&req))
+            .expect("error rendering bundle");
+        let actual_output: serde_json::Value =
+            serde_json::from_str(actual[0].data()).expect("error unpacking js data");
+        assert_eq!(actual_output, serde_json::json!({"sum": 42}));
+    }
+
+    #[test]
+    fn test_bundle_records_render_settings() {
+        let ctx = engine::Context::builder(get_fixture_path(""))
+            .node_modules_dir(None)
+            .write_to_disk(false)
+            .key_order(keyorder::KeyOrder::Insertion)
+            .generated_header(Some(header::GeneratedHeader {
+                include_hash: true,
+                json_comment: false,
+            }))
+            .build()
+            .expect("error building context");
+        let b = create(&ctx, &get_fixture_path("key_order.js")).expect("error creating bundle");
+        let b = Bundle::parse(&b.to_module().expect("error rendering bundle"))
+            .expect("error parsing bundle");
+        assert_eq!(b.settings.key_order, keyorder::KeyOrder::Insertion);
+        assert_eq!(b.settings.generated_header, ctx.generated_header);
+        assert_eq!(b.settings.format, formatting::FormatOptions::default());
+    }
+
+    fn get_fixture_path(relpath: &str) -> path::PathBuf {
+        let mut p = path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("tests/fixtures");
+        if relpath != "" {
+            p.push(relpath);
+        }
+        return p;
+    }
+}
@@ -0,0 +1,354 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::fs;
+use std::io;
+use std::path;
+use std::sync::{atomic, Arc};
+
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::*;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::engine;
+use crate::jsonrpc;
+use crate::threadpool;
+
+#[derive(Deserialize)]
+struct RenderParams {
+    // The path to the entrypoint, or a directory containing entrypoints, to render.
+    path: path::PathBuf,
+    // Top-level args to pass to the main function. Overrides the args the daemon was started with.
+    tla: Option<Vec<serde_json::Value>>,
+    // Whether the outputs should be written to disk. Defaults to true.
+    write: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ListEntrypointsParams {
+    // The path to search for entrypoints. Defaults to the project root.
+    path: Option<path::PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct InvalidateParams {
+    // The files or directories to invalidate in the caches. When omitted, all caches are cleared.
+    paths: Option<Vec<path::PathBuf>>,
+}
+
+// A long running daemon that renders entrypoints on request over JSON-RPC. The daemon keeps the
+// thread pool and module cache warm between requests, so that editors and build tools don't pay the
+// startup cost of senc on every render.
+//
+// The daemon supports the following methods:
+// - render: Render the entrypoints in the given path, returning the outputs.
+// - check: Same as render, but never writes the outputs to disk and only reports the errors.
+// - listEntrypoints: List the entrypoints in the given path.
+// - invalidate: Invalidate the cached modules for the given paths.
+// - shutdown: Stop the daemon.
+pub struct Daemon {
+    ctx: engine::Context,
+    pool: threadpool::ThreadPool,
+}
+
+impl Daemon {
+    pub fn new(
+        ctx: engine::Context,
+        parallelism: usize,
+        has_quit: Arc<atomic::AtomicBool>,
+    ) -> Daemon {
+        let pool = threadpool::ThreadPool::new(ctx.clone(), parallelism, has_quit);
+        Daemon { ctx, pool }
+    }
+
+    // Serve JSON-RPC requests over stdin and stdout until the client sends the shutdown request or
+    // closes stdin. Messages are framed with the `Content-Length` header.
+    pub fn serve(&mut self) -> Result<()> {
+        let stdin = io::stdin();
+        let mut reader = stdin.lock();
+        let mut stdout = io::stdout();
+
+        while let Some(msg) = jsonrpc::read_message(&mut reader)? {
+            let req = match msg.and_then(|body| jsonrpc::parse_request(&body)) {
+                Ok(r) => r,
+                Err(e) => {
+                    let resp = jsonrpc::response(serde_json::Value::Null, Err(e));
+                    jsonrpc::write_message(&mut stdout, &resp)?;
+                    continue;
+                }
+            };
+            debug!("daemon received request {}", req.method);
+
+            let result = self.handle(&req);
+            if let Some(id) = req.id {
+                jsonrpc::write_message(&mut stdout, &jsonrpc::response(id, result))?;
+            }
+            if req.method == "shutdown" {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, req: &jsonrpc::Request) -> Result<serde_json::Value, jsonrpc::RpcError> {
+        match req.method.as_str() {
+            "render" => {
+                let params: RenderParams = req.parse_params()?;
+                let outputs = self.render(&params, params.write.unwrap_or(true))?;
+                Ok(json!({ "entrypoints": outputs }))
+            }
+            "check" => {
+                let params: RenderParams = req.parse_params()?;
+                let outputs = self.render(&params, false)?;
+                let ok = outputs.iter().all(|o| o["error"].is_null());
+                let entrypoints: Vec<serde_json::Value> = outputs
+                    .iter()
+                    .map(|o| json!({"in_file": o["in_file"], "error": o["error"]}))
+                    .collect();
+                Ok(json!({ "ok": ok, "entrypoints": entrypoints }))
+            }
+            "listEntrypoints" => {
+                let params: ListEntrypointsParams = req.parse_params()?;
+                let p = params.path.unwrap_or_else(|| self.ctx.projectroot.clone());
+                let entrypoints: Vec<serde_json::Value> = self
+                    .ctx
+                    .run_requests_from_path(&p)?
+                    .iter()
+                    .map(|r| json!({"in_file": r.in_file, "out_file_stem": r.out_file_stem}))
+                    .collect();
+                Ok(json!({ "entrypoints": entrypoints }))
+            }
+            "invalidate" => {
+                let params: InvalidateParams = req.parse_params()?;
+                let removed = match &self.ctx.module_cache {
+                    None => 0,
+                    Some(cache) => match params.paths {
+                        None => cache.clear(),
+                        Some(paths) => {
+                            let paths: Vec<path::PathBuf> =
+                                paths.iter().map(|p| self.resolve_path(p)).collect();
+                            cache.invalidate(&paths)
+                        }
+                    },
+                };
+                Ok(json!({ "invalidated": removed }))
+            }
+            "shutdown" => Ok(serde_json::Value::Null),
+            m => Err(jsonrpc::RpcError::new(
+                jsonrpc::METHOD_NOT_FOUND,
+                &format!("method {m} is not supported"),
+            )),
+        }
+    }
+
+    // Resolve the path from a request to the canonical path, which is how the module cache tracks
+    // the modules. Relative paths are resolved relative to the project root.
+    //
+    // NOTE
+    // Paths that can not be canonicalized (e.g., files that were deleted) are only cleaned, so that
+    // the modules for deleted files can still be invalidated.
+    fn resolve_path(&self, p: &path::Path) -> path::PathBuf {
+        let joined = self.ctx.projectroot.join(p);
+        match fs::canonicalize(&joined) {
+            Ok(p) => p,
+            Err(_e) => path_clean::clean(joined),
+        }
+    }
+
+    // Render the entrypoints in the requested path on the thread pool, returning the outputs (or
+    // error) for each entrypoint sorted by the entrypoint path.
+    fn render(&mut self, params: &RenderParams, write: bool) -> Result<Vec<serde_json::Value>> {
+        let mut ctx = self.ctx.clone();
+        ctx.write_to_disk = write;
+        if let Some(tla) = &params.tla {
+            ctx.tla_jsons = Some(tla.iter().map(|a| a.to_string()).collect());
+        }
+        let ctx = Arc::new(ctx);
+
+        for r in ctx.run_requests_from_path(&params.path)? {
+            self.pool.run_with_context(ctx.clone(), r)?;
+        }
+        self.pool.wait()?;
+        // The outputs are taken before checking the result of the write, so that they are not
+        // returned with the next render.
+        let write_result = self.pool.write_outputs();
+        let mut task_outputs = self.pool.take_outputs();
+        write_result?;
+        task_outputs.sort_by(|a, b| a.request.in_file.cmp(&b.request.in_file));
+
+        let mut outputs = Vec::with_capacity(task_outputs.len());
+        for o in task_outputs {
+            let entrypoint = match o.result {
+                Ok(out_data) => {
+                    let mut files = Vec::with_capacity(out_data.len());
+                    for d in out_data {
+                        let p = d.out_file_path(&ctx.out_dir, &o.request.out_file_stem)?;
+                        // Binary outputs are returned base64 encoded, since JSON strings can only
+                        // hold text.
+                        if d.is_binary() {
+                            let encoded = STANDARD.encode(d.contents_bytes());
+                            files.push(json!({"path": p, "contents_base64": encoded}));
+                        } else {
+                            files.push(json!({"path": p, "contents": d.contents()}));
+                        }
+                    }
+                    json!({"in_file": o.request.in_file, "outputs": files, "error": null})
+                }
+                Err(e) => {
+                    json!({"in_file": o.request.in_file, "outputs": [], "error": format!("{e:#}")})
+                }
+            };
+            outputs.push(entrypoint);
+        }
+        Ok(outputs)
+    }
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daemon_render() {
+        let mut daemon = get_daemon();
+        let simple = get_fixture_path("daemon/simple.sen.js");
+        let result = daemon
+            .handle(&request(
+                "render",
+                json!({"path": simple, "tla": ["prod"], "write": false}),
+            ))
+            .expect("error handling render");
+        let entrypoints = result["entrypoints"]
+            .as_array()
+            .expect("missing entrypoints");
+        assert_eq!(entrypoints.len(), 1);
+        assert_eq!(entrypoints[0]["in_file"], json!(simple));
+        assert!(entrypoints[0]["error"].is_null());
+
+        let contents = entrypoints[0]["outputs"][0]["contents"]
+            .as_str()
+            .expect("missing contents");
+        let actual: serde_json::Value =
+            serde_json::from_str(contents).expect("error parsing contents");
+        assert_eq!(actual, json!({"foo": "bar", "env": "prod"}));
+    }
+
+    #[test]
+    fn test_daemon_check() {
+        let mut daemon = get_daemon();
+        let result = daemon
+            .handle(&request(
+                "check",
+                json!({"path": get_fixture_path("daemon")}),
+            ))
+            .expect("error handling check");
+        assert_eq!(result["ok"], json!(false));
+
+        // The entrypoints are sorted by path, so the broken entrypoint is first.
+        let entrypoints = result["entrypoints"]
+            .as_array()
+            .expect("missing entrypoints");
+        assert_eq!(entrypoints.len(), 2);
+        let err = entrypoints[0]["error"].as_str().expect("missing error");
+        assert!(err.contains("broken on purpose"), "unexpected error {err}");
+        assert!(entrypoints[1]["error"].is_null());
+        assert!(entrypoints[1].get("outputs").is_none());
+    }
+
+    #[test]
+    fn test_daemon_list_entrypoints() {
+        let mut daemon = get_daemon();
+        let result = daemon
+            .handle(&request(
+                "listEntrypoints",
+                json!({"path": get_fixture_path("daemon")}),
+            ))
+            .expect("error handling listEntrypoints");
+        let mut in_files: Vec<&str> = result["entrypoints"]
+            .as_array()
+            .expect("missing entrypoints")
+            .iter()
+            .map(|e| e["in_file"].as_str().expect("missing in_file"))
+            .collect();
+        in_files.sort();
+        assert_eq!(
+            in_files,
+            vec![
+                get_fixture_path("daemon/broken.sen.js").to_string_lossy(),
+                get_fixture_path("daemon/simple.sen.js").to_string_lossy(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_daemon_invalidate() {
+        let mut daemon = get_daemon();
+        daemon
+            .handle(&request(
+                "render",
+                json!({"path": get_fixture_path("daemon/simple.sen.js"), "write": false}),
+            ))
+            .expect("error handling render");
+
+        // Relative paths are resolved relative to the project root.
+        let result = daemon
+            .handle(&request(
+                "invalidate",
+                json!({"paths": ["daemon/simple.sen.js"]}),
+            ))
+            .expect("error handling invalidate");
+        assert_eq!(result, json!({"invalidated": 1}));
+
+        let result = daemon
+            .handle(&request(
+                "invalidate",
+                json!({"paths": ["daemon/simple.sen.js"]}),
+            ))
+            .expect("error handling invalidate");
+        assert_eq!(result, json!({"invalidated": 0}));
+    }
+
+    #[test]
+    fn test_daemon_unknown_method() {
+        let mut daemon = get_daemon();
+        let err = daemon
+            .handle(&request("explode", serde_json::Value::Null))
+            .err()
+            .expect("expected unknown method error");
+        assert_eq!(err.code, jsonrpc::METHOD_NOT_FOUND);
+        assert_eq!(err.message, "method explode is not supported");
+    }
+
+    fn get_daemon() -> Daemon {
+        let ctx = engine::Context::builder(get_fixture_path(""))
+            .node_modules_dir(None)
+            .write_to_disk(false)
+            .module_cache(true)
+            .project_snapshot(false)
+            .build()
+            .expect("error building context");
+        Daemon::new(ctx, 1, Arc::new(atomic::AtomicBool::new(false)))
+    }
+
+    fn request(method: &str, params: serde_json::Value) -> jsonrpc::Request {
+        jsonrpc::Request {
+            id: Some(json!(1)),
+            method: String::from(method),
+            params,
+        }
+    }
+
+    fn get_fixture_path(relpath: &str) -> path::PathBuf {
+        let mut p = path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("tests/fixtures");
+        if relpath != "" {
+            p.push(relpath);
+        }
+        return p;
+    }
+}
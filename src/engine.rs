@@ -2,25 +2,45 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use std::borrow::{Borrow, Cow};
+use std::cell::{Cell, RefCell};
 use std::collections;
 use std::env;
 use std::fs;
 use std::io::Write;
 use std::path;
 use std::rc::Rc;
+use std::sync;
+use std::sync::Arc;
+use std::time;
 use std::vec;
 
 use anyhow::{anyhow, Result};
+use deno_core::futures::FutureExt;
 use deno_core::*;
+use log::*;
 
+use crate::annotations::{self, Annotations};
+use crate::bundle;
+use crate::extensions;
 use crate::files;
+use crate::formatting;
+use crate::hclwriter;
+use crate::header;
+use crate::keyorder;
 use crate::module_loader;
 use crate::ops;
+use crate::plugins;
+use crate::serializer;
+use crate::snapshot;
+use crate::staging;
+use crate::timings;
 use crate::validator;
 use crate::validator::DataSchema;
+use crate::xmlwriter;
 
 // Load and embed the runtime snapshot built from the build script.
-static RUNTIME_SNAPSHOT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/SENC_SNAPSHOT.bin"));
+pub(crate) static RUNTIME_SNAPSHOT: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/SENC_SNAPSHOT.bin"));
 
 // The runtime context, containing various metadata that is used by the builtin operations.
 #[derive(Clone)]
@@ -29,9 +49,416 @@ pub struct Context {
     pub projectroot: path::PathBuf,
     pub out_dir: path::PathBuf,
     pub tla_jsons: Option<vec::Vec<String>>,
+
+    // When set, a V8 CPU profile is recorded for each entrypoint and written to this directory as
+    // a `.cpuprofile` file, mirroring the path of the entrypoint relative to the project root.
+    pub profile_cpu_dir: Option<path::PathBuf>,
+
+    // Whether the rendered outputs should be written to the output directory. When false, the
+    // outputs are only returned in memory.
+    pub write_to_disk: bool,
+
+    // Whether the rendered outputs should be written through a staging directory, so that they are
+    // only moved in to place once all the entrypoints have been rendered successfully. Files that
+    // are unchanged are left untouched to preserve their mtime.
+    pub atomic: bool,
+
+    // Custom extensions registered by the embedder, exposed to scripts under `senc.ext`.
+    pub extensions: vec::Vec<extensions::CustomExtension>,
+
+    // WebAssembly plugins declared for the project, exposed to scripts under `senc.plugins`.
+    pub wasm_plugins: vec::Vec<plugins::WasmPlugin>,
+
+    // A cache of transpiled modules that is shared across runs. This is useful for long running
+    // processes (e.g., the daemon) that render the same entrypoints repeatedly.
+    pub module_cache: Option<Arc<module_loader::ModuleCache>>,
+
+    // The bundle to render, if set. When rendering a bundle, all modules and schemas are loaded
+    // from the bundle instead of the project tree.
+    pub bundle: Option<Arc<bundle::Bundle>>,
+
+    // Records the modules that are loaded while rendering, if set. This is used to create bundles.
+    pub module_recorder: Option<Arc<bundle::ModuleRecorder>>,
+
+    // The project snapshot built with `senc snapshot build`, containing the builtins and the
+    // project prelude modules. When set, this is used in place of the builtin runtime snapshot.
+    pub project_snapshot: Option<&'static [u8]>,
+
+    // The order of the object keys in the rendered outputs, for outputs that don't set key_order.
+    pub key_order: keyorder::KeyOrder,
+
+    // The formatting options of the rendered outputs. These can be overridden per output with the
+    // format option of OutData.
+    pub format: formatting::FormatOptions,
+
+    // When set, a header marking the outputs as generated from the entrypoint is prepended to the
+    // rendered outputs.
+    pub generated_header: Option<header::GeneratedHeader>,
+}
+
+impl Context {
+    // Start building a new Context for the project rooted at |projectroot|.
+    pub fn builder<P: AsRef<path::Path>>(projectroot: P) -> ContextBuilder {
+        ContextBuilder::new(projectroot.as_ref())
+    }
+
+    // Collect the run requests for the given path, which can either be a single .sen.js/.sen.ts
+    // file or a directory that is searched recursively for entrypoints.
+    //
+    // When rendering a bundle, this always returns the request for the entrypoint of the bundle.
+    pub fn run_requests_from_path(&self, p: &path::Path) -> Result<vec::Vec<RunRequest>> {
+        if let Some(b) = &self.bundle {
+            return Ok(vec![b.run_request(&self.out_dir)?]);
+        }
+        let p = fs::canonicalize(p)?;
+        files::get_run_requests_from_path(&p, &self.out_dir, &self.projectroot)
+    }
+
+    // Run a single request, returning the rendered outputs. The outputs are also written to disk
+    // if the Context is configured to do so.
+    //
+    // NOTE
+    // The returned future is not Send, as it drives a V8 isolate. Use a current thread tokio
+    // runtime (e.g., with block_on) to run it.
+    pub async fn run(&self, req: &RunRequest) -> Result<vec::Vec<OutData>> {
+        let mut t = timings::Timings::new(&req.in_file);
+        run_js_and_write(self, req, &mut t).await
+    }
+
+    // Same as run, but also returns the paths of the files that were read to render the outputs
+    // (the entrypoint, the modules it imports, and the schemas it is validated against). This is
+    // useful for callers that cache the outputs until the inputs change.
+    pub async fn run_with_inputs(&self, req: &RunRequest) -> Result<RunOutput> {
+        let mut t = timings::Timings::new(&req.in_file);
+        let out = self.render_with_inputs(req, &mut t).await?;
+        write_out_data(self, req, &out.out_data, &mut t)?;
+        return Ok(out);
+    }
+
+    // Render all the entrypoints in the given path sequentially, returning the outputs for each
+    // entrypoint.
+    //
+    // NOTE
+    // The outputs are only written to disk once all the entrypoints have been rendered, so that
+    // the output paths can be checked for collisions across the entrypoints first.
+    pub async fn render(&self, p: &path::Path) -> Result<vec::Vec<RunOutput>> {
+        let mut outputs = vec::Vec::new();
+        for req in self.run_requests_from_path(p)? {
+            let mut t = timings::Timings::new(&req.in_file);
+            outputs.push(self.render_with_inputs(&req, &mut t).await?);
+        }
+
+        if self.write_to_disk {
+            let all: vec::Vec<(&Context, &RunRequest, &[OutData])> = outputs
+                .iter()
+                .map(|o| (self, &o.request, o.out_data.as_slice()))
+                .collect();
+            if self.atomic {
+                write_out_data_atomically(&all)?;
+            } else {
+                check_out_path_collisions(&all)?;
+                for o in outputs.iter() {
+                    let mut t = timings::Timings::new(&o.request.in_file);
+                    write_out_data(self, &o.request, &o.out_data, &mut t)?;
+                }
+            }
+        }
+        return Ok(outputs);
+    }
+
+    // Same as run_with_inputs, but without writing the outputs to disk.
+    async fn render_with_inputs(
+        &self,
+        req: &RunRequest,
+        t: &mut timings::Timings,
+    ) -> Result<RunOutput> {
+        let inputs = Rc::new(RefCell::new(vec::Vec::new()));
+        let out_data = run_js_tracking_inputs(self, req, t, inputs.clone()).await?;
+
+        let mut inputs = inputs.take();
+        inputs.sort();
+        inputs.dedup();
+        return Ok(RunOutput {
+            request: req.clone(),
+            out_data,
+            inputs,
+        });
+    }
+}
+
+// A builder for the runtime Context. This resolves all the paths to absolute paths and looks up
+// the node_modules directory if it is not explicitly configured.
+pub struct ContextBuilder {
+    projectroot: path::PathBuf,
+    out_dir: Option<path::PathBuf>,
+    node_modules_dir: Option<Option<path::PathBuf>>,
+    tla_jsons: Option<vec::Vec<String>>,
+    profile_cpu_dir: Option<path::PathBuf>,
+    write_to_disk: bool,
+    atomic: bool,
+    extensions: vec::Vec<extensions::CustomExtension>,
+    wasm_plugins: vec::Vec<(String, path::PathBuf)>,
+    module_cache: bool,
+    bundle: Option<BundleSource>,
+    project_snapshot: bool,
+    key_order: keyorder::KeyOrder,
+    format: formatting::FormatOptions,
+    generated_header: Option<header::GeneratedHeader>,
+}
+
+impl ContextBuilder {
+    fn new(projectroot: &path::Path) -> ContextBuilder {
+        ContextBuilder {
+            projectroot: projectroot.to_path_buf(),
+            out_dir: None,
+            node_modules_dir: None,
+            tla_jsons: None,
+            profile_cpu_dir: None,
+            write_to_disk: true,
+            atomic: false,
+            extensions: vec::Vec::new(),
+            wasm_plugins: vec::Vec::new(),
+            module_cache: false,
+            bundle: None,
+            project_snapshot: true,
+            key_order: keyorder::KeyOrder::default(),
+            format: formatting::FormatOptions::default(),
+            generated_header: None,
+        }
+    }
+
+    // The directory where the outputs are rendered. Defaults to `generated` in the project root.
+    pub fn out_dir<P: AsRef<path::Path>>(mut self, out_dir: P) -> ContextBuilder {
+        self.out_dir = Some(out_dir.as_ref().to_path_buf());
+        self
+    }
+
+    // The node_modules directory to resolve npm packages from. Pass None to disable npm package
+    // imports. When not called, the node_modules directory is searched for in the parent tree of
+    // the project root.
+    pub fn node_modules_dir(mut self, node_modules_dir: Option<path::PathBuf>) -> ContextBuilder {
+        self.node_modules_dir = Some(node_modules_dir);
+        self
+    }
+
+    // Append a top-level arg that is passed to the main function as a positional arg.
+    pub fn tla(mut self, tla: &serde_json::Value) -> ContextBuilder {
+        self.tla_jsons
+            .get_or_insert_with(vec::Vec::new)
+            .push(tla.to_string());
+        self
+    }
+
+    // Set the top-level args as JSON encoded strings, replacing any previously set args.
+    pub fn tla_jsons(mut self, tla_jsons: Option<vec::Vec<String>>) -> ContextBuilder {
+        self.tla_jsons = tla_jsons;
+        self
+    }
+
+    pub fn profile_cpu_dir(mut self, profile_cpu_dir: Option<path::PathBuf>) -> ContextBuilder {
+        self.profile_cpu_dir = profile_cpu_dir;
+        self
+    }
+
+    // Whether the rendered outputs should be written to disk. Defaults to true.
+    pub fn write_to_disk(mut self, write_to_disk: bool) -> ContextBuilder {
+        self.write_to_disk = write_to_disk;
+        self
+    }
+
+    // Whether the rendered outputs should be staged and only moved in to place once all the
+    // entrypoints have been rendered successfully. Defaults to false.
+    pub fn atomic(mut self, atomic: bool) -> ContextBuilder {
+        self.atomic = atomic;
+        self
+    }
+
+    // Register a custom extension that exposes additional ops to scripts under
+    // `senc.ext.<name>`.
+    pub fn extension(mut self, ext: extensions::CustomExtension) -> ContextBuilder {
+        self.extensions.push(ext);
+        self
+    }
+
+    // Declare a WebAssembly plugin that is exposed to scripts under `senc.plugins.<name>`. Relative
+    // paths are resolved relative to the project root.
+    pub fn wasm_plugin<P: AsRef<path::Path>>(mut self, name: &str, p: P) -> ContextBuilder {
+        self.wasm_plugins
+            .push((String::from(name), p.as_ref().to_path_buf()));
+        self
+    }
+
+    // Whether transpiled modules should be cached across runs. Defaults to false.
+    pub fn module_cache(mut self, module_cache: bool) -> ContextBuilder {
+        self.module_cache = module_cache;
+        self
+    }
+
+    // Render the bundle at the given path (created with `senc bundle`) instead of the entrypoints
+    // in the project tree.
+    pub fn bundle<P: AsRef<path::Path>>(mut self, p: P) -> ContextBuilder {
+        self.bundle = Some(BundleSource::Path(p.as_ref().to_path_buf()));
+        self
+    }
+
+    // Same as bundle, but for a bundle that is already loaded (e.g., the bundle embedded in a
+    // standalone executable).
+    pub fn loaded_bundle(mut self, b: bundle::Bundle) -> ContextBuilder {
+        self.bundle = Some(BundleSource::Loaded(b));
+        self
+    }
+
+    // Whether the project snapshot (built with `senc snapshot build`) should be used when it is
+    // present and fresh. Defaults to true.
+    //
+    // NOTE
+    // The snapshot is only checked for freshness when the Context is built, so long running
+    // processes should disable it to pick up changes to the prelude modules.
+    pub fn project_snapshot(mut self, project_snapshot: bool) -> ContextBuilder {
+        self.project_snapshot = project_snapshot;
+        self
+    }
+
+    // The order of the object keys in the rendered outputs. Defaults to sorted. This can be
+    // overridden per output with the key_order option of OutData.
+    pub fn key_order(mut self, key_order: keyorder::KeyOrder) -> ContextBuilder {
+        self.key_order = key_order;
+        self
+    }
+
+    // The formatting options of the rendered outputs (e.g., the indent of JSON outputs). This can be
+    // overridden per output with the format option of OutData.
+    pub fn format(mut self, format: formatting::FormatOptions) -> ContextBuilder {
+        self.format = format;
+        self
+    }
+
+    // Prepend a header marking the outputs as generated (e.g., "Generated by senc from main.sen.ts
+    // — DO NOT EDIT.") to the rendered outputs, using the comment syntax of each output type.
+    // Defaults to no header.
+    pub fn generated_header(
+        mut self,
+        generated_header: Option<header::GeneratedHeader>,
+    ) -> ContextBuilder {
+        self.generated_header = generated_header;
+        self
+    }
+
+    // Build the Context. This also initializes the V8 platform if it hasn't been initialized yet.
+    pub fn build(self) -> Result<Context> {
+        let mut ext_names = collections::HashSet::new();
+        for ext in self.extensions.iter() {
+            ext.validate()?;
+            if !ext_names.insert(ext.name()) {
+                return Err(anyhow!(
+                    "custom extension {} is registered more than once",
+                    ext.name()
+                ));
+            }
+        }
+
+        let projectroot = fs::canonicalize(&self.projectroot)?;
+        let out_dir_raw = self
+            .out_dir
+            .unwrap_or_else(|| projectroot.join("generated"));
+        let out_dir = match fs::canonicalize(&out_dir_raw) {
+            Ok(d) => d,
+            Err(_e) if self.write_to_disk => {
+                fs::create_dir_all(&out_dir_raw)?;
+                fs::canonicalize(&out_dir_raw)?
+            }
+            Err(_e) => path_clean::clean(env::current_dir()?.join(&out_dir_raw)),
+        };
+
+        let node_modules_dir = match self.node_modules_dir {
+            Some(d) => d,
+            // Find the node_modules directory, if it exists. Otherwise, set to None.
+            None => match files::find_node_modules_dir(projectroot.as_path()) {
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
+                Ok(p) => Some(p),
+            },
+        };
+
+        let bundle = match self.bundle {
+            Some(BundleSource::Path(p)) => Some(Arc::new(bundle::Bundle::load(&p)?)),
+            Some(BundleSource::Loaded(b)) => Some(Arc::new(b)),
+            None => None,
+        };
+
+        // NOTE
+        // The plugins of a bundle are embedded in the bundle when it is created, so that the bundle
+        // renders without the project tree.
+        let mut wasm_plugins = match &bundle {
+            Some(b) if !self.wasm_plugins.is_empty() => {
+                return Err(anyhow!(
+                    "plugins can not be declared when rendering a bundle. The plugins declared when bundling {} are embedded in the bundle.",
+                    b.entrypoint
+                ));
+            }
+            Some(b) => b.wasm_plugins()?,
+            None => vec::Vec::with_capacity(self.wasm_plugins.len()),
+        };
+        let mut plugin_names = collections::HashSet::new();
+        for (name, p) in self.wasm_plugins.iter() {
+            if !plugin_names.insert(name) {
+                return Err(anyhow!("plugin {name} is declared more than once"));
+            }
+            let plugin = plugins::WasmPlugin::load(name, p, &projectroot)
+                .map_err(|e| anyhow!("could not load plugin {name}: {e}"))?;
+            wasm_plugins.push(plugin);
+        }
+
+        let profile_cpu_dir = match self.profile_cpu_dir {
+            Some(d) => {
+                fs::create_dir_all(&d)?;
+                Some(fs::canonicalize(&d)?)
+            }
+            None => None,
+        };
+
+        let project_snapshot = if self.project_snapshot && bundle.is_none() {
+            snapshot::load_if_fresh(&projectroot)?
+        } else {
+            None
+        };
+
+        init_v8();
+        Ok(Context {
+            node_modules_dir,
+            projectroot,
+            out_dir,
+            tla_jsons: self.tla_jsons,
+            profile_cpu_dir,
+            write_to_disk: self.write_to_disk,
+            atomic: self.atomic,
+            extensions: self.extensions,
+            wasm_plugins,
+            module_cache: if self.module_cache {
+                Some(Arc::new(module_loader::ModuleCache::new()))
+            } else {
+                None
+            },
+            bundle,
+            module_recorder: None,
+            project_snapshot,
+            key_order: self.key_order,
+            format: self.format,
+            generated_header: self.generated_header,
+        })
+    }
+}
+
+// The bundle to render, set on the ContextBuilder.
+enum BundleSource {
+    Path(path::PathBuf),
+    Loaded(bundle::Bundle),
 }
 
 // A request to run a single JS/TS file through.
+#[derive(Clone)]
 pub struct RunRequest {
     pub in_file: String,
     pub out_file_stem: String,
@@ -47,6 +474,15 @@ impl std::fmt::Display for RunRequest {
     }
 }
 
+// The rendered outputs of a single entrypoint.
+pub struct RunOutput {
+    pub request: RunRequest,
+    pub out_data: vec::Vec<OutData>,
+
+    // The paths of the files that were read to render the outputs, sorted.
+    pub inputs: vec::Vec<path::PathBuf>,
+}
+
 // The data to be written to disk, including the file extension to use.
 pub struct OutData {
     // The output file path. If set, this will override the default output file path that is based
@@ -66,53 +502,346 @@ pub struct OutData {
     // Prefix to append to the output before writing to file.
     out_prefix: Option<String>,
 
-    // The full, raw string contents of the output file.
+    // The permission bits (e.g., 0o755) to set on the output file after it is written. When unset,
+    // the file is created with the default permissions of the process.
+    out_mode: Option<u32>,
+
+    // The full, raw string contents of the output file. This is empty for binary outputs.
     data: String,
+
+    // The raw bytes of the output file, for binary outputs.
+    binary_data: Option<vec::Vec<u8>>,
+}
+
+impl OutData {
+    pub fn out_path(&self) -> Option<&str> {
+        self.out_path.as_deref()
+    }
+
+    pub fn out_ext(&self) -> Option<&str> {
+        self.out_ext.as_deref()
+    }
+
+    pub fn out_prefix(&self) -> Option<&str> {
+        self.out_prefix.as_deref()
+    }
+
+    pub fn out_mode(&self) -> Option<u32> {
+        self.out_mode
+    }
+
+    // The rendered data, without the prefix. Use contents_bytes for binary outputs.
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.binary_data.is_some()
+    }
+
+    // The full contents of the output file, with the prefix prepended. Binary outputs are not valid
+    // UTF-8, so use contents_bytes for those instead.
+    pub fn contents(&self) -> String {
+        if let Some(b) = &self.binary_data {
+            return String::from_utf8_lossy(b).to_string();
+        }
+        let mut tmp = self.data.clone();
+        if let Some(pre) = &self.out_prefix {
+            tmp.insert_str(0, &pre);
+        };
+        tmp
+    }
+
+    // The full contents of the output file as raw bytes. This works for both binary and text
+    // outputs.
+    pub fn contents_bytes(&self) -> vec::Vec<u8> {
+        match &self.binary_data {
+            Some(b) => b.clone(),
+            None => self.contents().into_bytes(),
+        }
+    }
+
+    // Resolve the path of the output file for the given output directory and file stem. Refer to
+    // write_data for more information on how the path is determined.
+    pub fn out_file_path(
+        &self,
+        out_dir: &path::Path,
+        out_file_stem: &str,
+    ) -> Result<path::PathBuf> {
+        let mut out_file_path_str = String::new();
+        if let Some(out_path) = &self.out_path {
+            let mut out_file_stem_dir = path::PathBuf::from(out_file_stem)
+                .parent()
+                .unwrap()
+                .to_owned();
+            out_file_stem_dir.push(&out_path);
+            out_file_path_str.push_str(&out_file_stem_dir.to_string_lossy());
+        } else {
+            out_file_path_str.push_str(out_file_stem);
+            out_file_path_str.push_str(&self.out_ext.clone().unwrap());
+        }
+        let out_file_path = path_clean::clean(path::PathBuf::from(out_file_path_str));
+        files::assert_file_path_in_projectroot(&out_file_path, out_dir)?;
+        return Ok(out_file_path);
+    }
 }
 
 // The output types supported
 enum OutputType {
     JSON,
     YAML,
+    YAMLStream,
+    TOML,
+    HCL,
+    Dotenv,
+    Properties,
+    INI,
+    XML,
+    Plist,
+    Text,
+    Binary,
 }
 
+static INIT_V8: sync::Once = sync::Once::new();
+
 // Initialize the v8 platform. This should be called in the main thread before any subthreads are
-// launched.
+// launched. It is safe to call this multiple times, as the platform is only initialized once.
 pub fn init_v8() {
-    let platform = v8::new_default_platform(0, false).make_shared();
-    JsRuntime::init_platform(Some(platform));
+    INIT_V8.call_once(|| {
+        let platform = v8::new_default_platform(0, false).make_shared();
+        JsRuntime::init_platform(Some(platform));
+    });
 }
 
 // Process the request to run the JavaScript or TypeScript file to render the output in to the
 // configured output dir. This will run the script and then write the output to the computed
-// destination in one step.
-pub async fn run_js_and_write(ctx: &Context, req: &RunRequest) -> Result<()> {
-    let out_data_vec = run_js(ctx, req).await?;
-    for d in out_data_vec {
+// destination in one step. The write is skipped if the context is not configured to write to disk.
+//
+// The time spent in each phase of the run is recorded in |timings|.
+pub async fn run_js_and_write(
+    ctx: &Context,
+    req: &RunRequest,
+    timings: &mut timings::Timings,
+) -> Result<vec::Vec<OutData>> {
+    let out_data_vec = run_js_timed(ctx, req, timings).await?;
+    write_out_data(ctx, req, &out_data_vec, timings)?;
+    return Ok(out_data_vec);
+}
+
+// Write the rendered outputs of the request to the output directory, if the context is configured
+// to write to disk. This fails without writing any files if multiple outputs of the request resolve
+// to the same file path.
+pub fn write_out_data(
+    ctx: &Context,
+    req: &RunRequest,
+    out_data_vec: &[OutData],
+    timings: &mut timings::Timings,
+) -> Result<()> {
+    if !ctx.write_to_disk {
+        return Ok(());
+    }
+    let write_start = time::Instant::now();
+    if ctx.atomic {
+        write_out_data_atomically(&[(ctx, req, out_data_vec)])?;
+        timings.add_since(timings::Phase::Write, write_start);
+        return Ok(());
+    }
+    check_out_path_collisions(&[(ctx, req, out_data_vec)])?;
+    for d in out_data_vec.iter() {
         // TODO
         // collect the errors and return one big error instead of failing fast
-        write_data(ctx.out_dir.as_path(), &req.out_file_stem, &d)?;
+        write_data(ctx.out_dir.as_path(), &req.out_file_stem, d)?;
+    }
+    timings.add_since(timings::Phase::Write, write_start);
+    return Ok(());
+}
+
+// Write the rendered outputs of all the given requests through a staging directory, and only move
+// them in to place once all of them were written successfully. Files that already exist with the
+// same contents are not touched. Refer to staging::Stage for more information.
+pub fn write_out_data_atomically(outputs: &[(&Context, &RunRequest, &[OutData])]) -> Result<()> {
+    check_out_path_collisions(outputs)?;
+
+    let mut stages: vec::Vec<staging::Stage> = vec::Vec::new();
+    for (ctx, req, out_data_vec) in outputs.iter() {
+        if !ctx.write_to_disk {
+            continue;
+        }
+        let i = match stages.iter().position(|s| s.out_dir() == ctx.out_dir) {
+            Some(i) => i,
+            None => {
+                stages.push(staging::Stage::new(&ctx.out_dir)?);
+                stages.len() - 1
+            }
+        };
+        for d in out_data_vec.iter() {
+            let out_file_path = d.out_file_path(&ctx.out_dir, &req.out_file_stem)?;
+            stages[i].add(&out_file_path, &d.contents_bytes(), d.out_mode)?;
+        }
+    }
+    for stage in stages {
+        stage.commit()?;
     }
     return Ok(());
 }
 
+// Check that none of the given outputs resolve to the same file path, since the last one to be
+// written would silently win. All the collisions are reported in the error, along with the
+// entrypoints that produced them.
+pub fn check_out_path_collisions(outputs: &[(&Context, &RunRequest, &[OutData])]) -> Result<()> {
+    let mut producers: collections::HashMap<path::PathBuf, String> = collections::HashMap::new();
+    let mut collisions: vec::Vec<String> = vec::Vec::new();
+    for (ctx, req, out_data_vec) in outputs.iter() {
+        for (i, d) in out_data_vec.iter().enumerate() {
+            let out_file_path = d.out_file_path(&ctx.out_dir, &req.out_file_stem)?;
+            let producer = if out_data_vec.len() > 1 {
+                format!("{} (output {i})", req.in_file)
+            } else {
+                req.in_file.clone()
+            };
+            match producers.entry(out_file_path) {
+                collections::hash_map::Entry::Occupied(e) => collisions.push(format!(
+                    "{} is written by both {} and {producer}",
+                    e.key().display(),
+                    e.get()
+                )),
+                collections::hash_map::Entry::Vacant(e) => {
+                    e.insert(producer);
+                }
+            }
+        }
+    }
+    if collisions.is_empty() {
+        return Ok(());
+    }
+    return Err(anyhow!(
+        "multiple outputs resolve to the same file path:\n{}",
+        collisions.join("\n")
+    ));
+}
+
 // Run the javascript or typescript file available at the given file path through the Deno runtime.
-async fn run_js(ctx: &Context, req: &RunRequest) -> Result<vec::Vec<OutData>> {
+// This does not write the outputs to disk.
+pub async fn run_js(ctx: &Context, req: &RunRequest) -> Result<vec::Vec<OutData>> {
+    let mut timings = timings::Timings::new(&req.in_file);
+    run_js_timed(ctx, req, &mut timings).await
+}
+
+// Same as run_js, but records the time spent in each phase of the run in |timings|.
+pub async fn run_js_timed(
+    ctx: &Context,
+    req: &RunRequest,
+    timings: &mut timings::Timings,
+) -> Result<vec::Vec<OutData>> {
+    let inputs = Rc::new(RefCell::new(vec::Vec::new()));
+    run_js_tracking_inputs(ctx, req, timings, inputs).await
+}
+
+// Same as run_js_timed, but records the paths of the files that were read while rendering in |inputs|.
+async fn run_js_tracking_inputs(
+    ctx: &Context,
+    req: &RunRequest,
+    timings: &mut timings::Timings,
+    inputs: Rc<RefCell<vec::Vec<path::PathBuf>>>,
+) -> Result<vec::Vec<OutData>> {
     let script_path = path::Path::new(&req.in_file);
-    let script_dir = script_path.parent().unwrap();
+    let transpile_time = Rc::new(Cell::new(time::Duration::ZERO));
+
+    let start = time::Instant::now();
+    let mut js_runtime = new_runtime(ctx, req, transpile_time.clone(), inputs.clone())?;
+    timings.add_since(timings::Phase::RuntimeCreation, start);
+
+    let maybe_profiler_session = match ctx.profile_cpu_dir {
+        Some(_) => Some(start_cpu_profile(&mut js_runtime).await?),
+        None => None,
+    };
+
+    let result = load_and_call_main_fn(ctx, req, &mut js_runtime, timings, &transpile_time).await;
+
+    // NOTE
+    // The profile is written before checking the result, since a profile of a failing script (e.g.,
+    // one that threw) is as useful as one for a script that succeeded. Errors writing the profile
+    // are only logged when the script failed, so that they don't mask the error of the script.
+    if let Some(session) = maybe_profiler_session {
+        if let Err(e) = stop_and_write_cpu_profile(ctx, req, &mut js_runtime, session).await {
+            if result.is_ok() {
+                return Err(e);
+            }
+            warn!("could not write the CPU profile for {}: {e}", req.in_file);
+        }
+    }
+
+    let result = result?;
+    return load_result(ctx, script_path, &mut js_runtime, result, timings, &inputs);
+}
+
+// Load the entrypoint module and call the main function, returning the global handle to the
+// returned value.
+async fn load_and_call_main_fn(
+    ctx: &Context,
+    req: &RunRequest,
+    js_runtime: &mut JsRuntime,
+    timings: &mut timings::Timings,
+    transpile_time: &Cell<time::Duration>,
+) -> Result<v8::Global<v8::Value>> {
+    let start = time::Instant::now();
+    let mod_id = load_main_module(js_runtime, &req.in_file).await?;
+    let main_fn = load_main_fn(js_runtime, mod_id)?;
+    // The transpile time is tracked separately by the module loader, so remove it from the module
+    // load phase to avoid double counting.
+    timings.add(
+        timings::Phase::ModuleLoad,
+        start.elapsed().saturating_sub(transpile_time.get()),
+    );
+    timings.add(timings::Phase::Transpile, transpile_time.get());
 
-    let mut js_runtime = new_runtime(ctx, req)?;
-    let mod_id = load_main_module(&mut js_runtime, &req.in_file).await?;
-    let main_fn = load_main_fn(&mut js_runtime, mod_id)?;
-    let result = call_main_fn(ctx, &mut js_runtime, main_fn).await?;
-    return load_result(&script_dir, &mut js_runtime, result);
+    let start = time::Instant::now();
+    let result = call_main_fn(ctx, js_runtime, main_fn).await?;
+    timings.add_since(timings::Phase::MainCall, start);
+    return Ok(result);
 }
 
 // Initialize a new JsRuntime object (which represents an Isolate) with all the extensions loaded.
-fn new_runtime(ctx: &Context, req: &RunRequest) -> Result<JsRuntime> {
-    let modloader =
-        module_loader::TsModuleLoader::new(ctx.projectroot.clone(), ctx.node_modules_dir.clone());
-    let opext = Extension {
+fn new_runtime(
+    ctx: &Context,
+    req: &RunRequest,
+    transpile_time: Rc<Cell<time::Duration>>,
+    loaded_files: Rc<RefCell<vec::Vec<path::PathBuf>>>,
+) -> Result<JsRuntime> {
+    let modloader: Rc<dyn ModuleLoader> = match &ctx.bundle {
+        Some(b) => Rc::new(bundle::BundleModuleLoader::new(b.clone())),
+        None => Rc::new(module_loader::TsModuleLoader::new(
+            ctx.projectroot.clone(),
+            ctx.node_modules_dir.clone(),
+            transpile_time,
+            ctx.module_cache.clone(),
+            loaded_files,
+            ctx.module_recorder.clone(),
+        )),
+    };
+    let tmplext = load_templated_builtins(ctx, req)?;
+    let mut exts = vec![new_builtin_ops_extension(), tmplext];
+    if !ctx.wasm_plugins.is_empty() {
+        exts.push(plugins::new_extension(&ctx.wasm_plugins)?);
+    }
+    for ext in ctx.extensions.iter() {
+        exts.push(ext.to_extension());
+    }
+    let opts = RuntimeOptions {
+        module_loader: Some(modloader),
+        extensions: exts,
+        startup_snapshot: Some(Snapshot::Static(startup_snapshot(ctx))),
+        // The inspector is only needed to collect CPU profiles.
+        inspector: ctx.profile_cpu_dir.is_some(),
+        ..Default::default()
+    };
+    Ok(JsRuntime::new(opts))
+}
+
+// The extension with the builtin ops. This must be the first extension that is loaded into the
+// runtime, so that the ops line up with the ops in the project snapshot.
+pub(crate) fn new_builtin_ops_extension() -> Extension {
+    Extension {
         name: "opbuiltins",
         ops: Cow::Borrowed(&[
             ops::op_log_trace::DECL,
@@ -127,18 +856,64 @@ fn new_runtime(ctx: &Context, req: &RunRequest) -> Result<JsRuntime> {
             _ => op,
         })),
         ..Default::default()
-    };
-    let tmplext = load_templated_builtins(ctx, req)?;
-    let opts = RuntimeOptions {
-        module_loader: Some(Rc::new(modloader)),
-        extensions: vec![opext, tmplext],
+    }
+}
+
+// The snapshot to start the runtime from. The project snapshot is not used when rendering a bundle
+// (which has its own copy of the modules), or when recording modules to create a bundle (since the
+// modules in the snapshot are never loaded through the module loader).
+fn startup_snapshot(ctx: &Context) -> &'static [u8] {
+    match ctx.project_snapshot {
+        Some(s) if ctx.bundle.is_none() && ctx.module_recorder.is_none() => s,
         // NOTE
         // This snapshot contains the builtins/*.js scripts and is constructed in the build.rs
         // script.
-        startup_snapshot: Some(Snapshot::Static(RUNTIME_SNAPSHOT)),
-        ..Default::default()
+        _ => RUNTIME_SNAPSHOT,
+    }
+}
+
+// Start the V8 CPU profiler on the given runtime through a local inspector session. The runtime
+// must have been created with the inspector enabled.
+async fn start_cpu_profile(js_runtime: &mut JsRuntime) -> Result<LocalInspectorSession> {
+    // NOTE
+    // RefCell::borrow is called explicitly since the Borrow trait is in scope.
+    let inspector = js_runtime.inspector();
+    let mut session = std::cell::RefCell::borrow(&inspector).create_local_session();
+    session.post_message::<()>("Profiler.enable", None).await?;
+    session.post_message::<()>("Profiler.start", None).await?;
+    return Ok(session);
+}
+
+// Stop the V8 CPU profiler and write the collected profile to the configured profile directory.
+// The profile is written in the `.cpuprofile` format, which can be loaded in Chrome DevTools.
+async fn stop_and_write_cpu_profile(
+    ctx: &Context,
+    req: &RunRequest,
+    js_runtime: &mut JsRuntime,
+    mut session: LocalInspectorSession,
+) -> Result<()> {
+    let opts = PollEventLoopOptions {
+        wait_for_inspector: false,
+        pump_v8_message_loop: true,
     };
-    Ok(JsRuntime::new(opts))
+    let result = js_runtime
+        .with_event_loop_future(
+            session
+                .post_message::<()>("Profiler.stop", None)
+                .boxed_local(),
+            opts,
+        )
+        .await?;
+
+    let profile_dir = ctx.profile_cpu_dir.as_ref().unwrap();
+    let in_file_rel = path::Path::new(&req.in_file).strip_prefix(projectroot(ctx))?;
+    let mut profile_path = profile_dir.join(in_file_rel).into_os_string();
+    profile_path.push(".cpuprofile");
+    let profile_path = path::PathBuf::from(profile_path);
+
+    fs::create_dir_all(profile_path.parent().unwrap())?;
+    fs::write(&profile_path, serde_json::to_string(&result["profile"])?)?;
+    return Ok(());
 }
 
 // Load the main module. The main module is the main entrypoint that is being executed by senc.
@@ -198,10 +973,14 @@ async fn call_main_fn(
 // Load the result from the main function as a vector of OutData that can be outputed to disk. Each
 // OutData represents a single file that should be outputed.
 fn load_result(
-    script_dir: &path::Path,
+    ctx: &Context,
+    script_path: &path::Path,
     js_runtime: &mut JsRuntime,
     result: v8::Global<v8::Value>,
+    timings: &mut timings::Timings,
+    inputs: &RefCell<vec::Vec<path::PathBuf>>,
 ) -> Result<vec::Vec<OutData>> {
+    let script_dir = script_path.parent().unwrap();
     let mut out: vec::Vec<OutData> = vec::Vec::new();
 
     let mut scope = &mut js_runtime.handle_scope();
@@ -209,31 +988,110 @@ fn load_result(
 
     // Determine if the raw JS object from the runtime is an out data list object, in which case
     // each element needs to be cycled and converted.
+    let mut items: vec::Vec<v8::Local<v8::Value>> = vec::Vec::new();
     if result_is_sencjs_out_data_array(&mut scope, result_local)? {
         let result_arr: v8::Local<v8::Array> = result_local.try_into()?;
         let result_arr_raw: &v8::Array = result_arr.borrow();
         let sz = result_arr_raw.length();
         for i in 0..sz {
-            let item = result_arr_raw.get_index(&mut scope, i).unwrap();
-            let single_out = load_one_result(script_dir, &mut scope, item)?;
-            out.push(single_out);
+            items.push(result_arr_raw.get_index(&mut scope, i).unwrap());
         }
     } else {
-        let single_out = load_one_result(script_dir, &mut scope, result_local)?;
+        items.push(result_local);
+    }
+
+    let header = generated_header(ctx, script_path, &mut scope, &items, inputs)?;
+    for item in items {
+        let single_out = load_one_result(
+            ctx,
+            script_dir,
+            &mut scope,
+            item,
+            timings,
+            inputs,
+            header.as_deref(),
+        )?;
         out.push(single_out);
     }
 
     return Ok(out);
 }
 
+// The text of the generated header of the outputs, if the context is configured to add one.
+//
+// NOTE
+// The header is computed once all the modules are loaded, but before the outputs are loaded, since
+// it is rendered in to each output. To make sure the input hash covers everything that the outputs
+// depend on, the schemas of the outputs are resolved here ahead of validation, and the WASM plugins
+// and the prelude modules of the project snapshot (which are not loaded through the module loader)
+// are added to the modules and data files that the entrypoint imported.
+fn generated_header<'a>(
+    ctx: &Context,
+    script_path: &path::Path,
+    scope: &mut v8::HandleScope<'a>,
+    items: &[v8::Local<'a, v8::Value>],
+    inputs: &RefCell<vec::Vec<path::PathBuf>>,
+) -> Result<Option<String>> {
+    let h = match &ctx.generated_header {
+        Some(h) => h,
+        None => return Ok(None),
+    };
+
+    let mut hashed_inputs = inputs.borrow().clone();
+    if h.include_hash && ctx.bundle.is_none() {
+        let script_dir = script_path.parent().unwrap();
+        for item in items.iter() {
+            if let Some(schema_path) = load_out_data_schema_path(scope, *item)? {
+                // Schemas that can not be resolved are reported when the output is validated.
+                if let Ok(p) = fs::canonicalize(script_dir.join(schema_path)) {
+                    hashed_inputs.push(p);
+                }
+            }
+        }
+        hashed_inputs.extend(ctx.wasm_plugins.iter().map(|p| p.path.clone()));
+        if ctx.project_snapshot.is_some() {
+            hashed_inputs.extend(snapshot::loaded_files(&ctx.projectroot));
+        }
+    }
+    let msg = h.message(
+        &ctx.projectroot,
+        script_path,
+        &hashed_inputs,
+        ctx.bundle.as_deref(),
+    )?;
+    return Ok(Some(msg));
+}
+
+// The schema_path of the result item, if it is a JS OutData object that sets one.
+fn load_out_data_schema_path<'a>(
+    scope: &mut v8::HandleScope<'a>,
+    item: v8::Local<'a, v8::Value>,
+) -> Result<Option<String>> {
+    if !result_is_sencjs_out_data(scope, item)? {
+        return Ok(None);
+    }
+    let item_obj: v8::Local<v8::Object> = item.try_into()?;
+    let schema_path_key: v8::Local<v8::Value> =
+        v8::String::new(scope, "schema_path").unwrap().into();
+    let maybe_schema_path: v8::Local<v8::Value> = item_obj.get(scope, schema_path_key).unwrap();
+    if !maybe_schema_path.is_string() {
+        return Ok(None);
+    }
+    return Ok(Some(maybe_schema_path.to_rust_string_lossy(scope)));
+}
+
 // Load a single result data item. This can handle either of the following:
 // - An OutData object (in JS, not to be confused by the OutData struct defined in this file). This
 //   allows customization of the output behavior on a file by file basis.
 // - Anything else would be treated as raw object to be serialized to JSON.
 fn load_one_result<'a>(
+    ctx: &Context,
     script_dir: &path::Path,
     scope: &mut v8::HandleScope<'a>,
     orig_result_local: v8::Local<'a, v8::Value>,
+    timings: &mut timings::Timings,
+    inputs: &RefCell<vec::Vec<path::PathBuf>>,
+    header: Option<&str>,
 ) -> Result<OutData> {
     let mut result_local = orig_result_local.clone();
 
@@ -242,38 +1100,224 @@ fn load_one_result<'a>(
     let mut out_ext = Some(String::from(".json"));
     let mut out_type = OutputType::JSON;
     let mut out_prefix: Option<String> = None;
+    let mut out_mode: Option<u32> = None;
     let mut schema_path: Option<String> = None;
+    let mut key_order = ctx.key_order;
+    let mut format = ctx.format.clone();
+    let mut outdata_format: Option<formatting::FormatOptions> = None;
 
     // Determine if the raw JS object from the runtime is an out data object, and if it is, process
     // it.
     if result_is_sencjs_out_data(scope, result_local)? {
-        let (op, oe, ot, opre, sp, rs) = load_one_sencjs_out_data_result(scope, result_local)?;
+        let (op, oe, ot, opre, om, sp, ko, fmt, rs) =
+            load_one_sencjs_out_data_result(scope, result_local)?;
         out_path = op;
         out_ext = oe;
         out_type = ot;
         out_prefix = opre;
+        out_mode = om;
         schema_path = sp;
+        if let Some(ko) = ko {
+            key_order = ko;
+        }
+        if let Some(fmt) = fmt {
+            format = format.merge(&fmt);
+            outdata_format = Some(fmt);
+        }
         result_local = rs;
     }
 
-    let deserialized_result = serde_v8::from_v8::<serde_json::Value>(scope, result_local)?;
-    validate_result(script_dir, schema_path, &deserialized_result)?;
+    // NOTE
+    // Binary outputs are copied directly from the Uint8Array (or ArrayBuffer) in the runtime, since
+    // the bytes can not be represented as a serde_json::Value.
+    if let OutputType::Binary = out_type {
+        if out_prefix.is_some() || schema_path.is_some() {
+            return Err(anyhow!(
+                "out_prefix and schema_path are not supported for binary OutData objects"
+            ));
+        }
+        let start = time::Instant::now();
+        let binary_data = load_binary_data(scope, result_local)?;
+        timings.add_since(timings::Phase::Serialize, start);
+        return Ok(OutData {
+            out_path,
+            out_ext,
+            out_prefix,
+            out_mode,
+            data: String::new(),
+            binary_data: Some(binary_data),
+        });
+    }
 
+    // NOTE
+    // Text outputs are written verbatim, so the string is taken directly from the runtime instead of
+    // going through serde.
+    if let OutputType::Text = out_type {
+        if !result_local.is_string() {
+            return Err(anyhow!("data of text OutData object must be a string"));
+        }
+        // NOTE
+        // The project wide format options (--format) are not applied to text outputs, since
+        // rewriting the line endings of e.g. shell scripts can break them. Only the format option
+        // of the OutData object itself is applied.
+        let format = outdata_format.unwrap_or_default();
+        let out_prefix = out_prefix.map(|p| formatting::apply_line_ending(p, &format));
+        let data = result_local.to_rust_string_lossy(scope);
+        if schema_path.is_some() {
+            let deserialized_result = serde_json::Value::String(data.clone());
+            let start = time::Instant::now();
+            validate_result(ctx, script_dir, schema_path, &deserialized_result, inputs)?;
+            timings.add_since(timings::Phase::Validate, start);
+        }
+        return Ok(OutData {
+            out_path,
+            out_ext,
+            out_prefix,
+            out_mode,
+            data: formatting::finish(data, &format),
+            binary_data: None,
+        });
+    }
+
+    let start = time::Instant::now();
+    let mut deserialized_result = serde_v8::from_v8::<serde_json::Value>(scope, result_local)?;
+    // NOTE
+    // The marker objects of senc.yaml.anchor and senc.comment are replaced with their values before
+    // the data is validated, and the annotations are only rendered by the serializers that support
+    // them.
+    let annotations = Annotations::extract(&mut deserialized_result)?;
+    timings.add_since(timings::Phase::Serialize, start);
+
+    let start = time::Instant::now();
+    let schema = validate_result(ctx, script_dir, schema_path, &deserialized_result, inputs)?;
+    timings.add_since(timings::Phase::Validate, start);
+
+    let start = time::Instant::now();
+    // NOTE
+    // The keys are in the order they were inserted in the JS object, so they are reordered here
+    // before serializing. The serializers render the keys in the order of the data.
+    keyorder::apply(&mut deserialized_result, key_order, schema.as_ref())?;
+    if let (Some(h), OutputType::JSON) = (header, &out_type) {
+        if ctx
+            .generated_header
+            .as_ref()
+            .is_some_and(|g| g.json_comment)
+        {
+            if !add_json_comment_key(&mut deserialized_result, h) {
+                warn!(
+                    "not adding the generated header to {}: the top level JSON value is not an object",
+                    timings.in_file
+                );
+            }
+        }
+    }
     let data = match out_type {
         // NOTE
         // Both serde_json and serde_yaml have consistent outputs, so we don't need to do anything
-        // special
-        OutputType::JSON => serde_json::to_string_pretty(&deserialized_result)?.to_string(),
-        OutputType::YAML => serde_yaml::to_string(&deserialized_result)?.to_string(),
+        // special beyond applying the format options.
+        OutputType::JSON => formatting::to_json(&deserialized_result, &format)?,
+        OutputType::YAML => formatting::to_yaml(&deserialized_result, &format, &annotations)?,
+        OutputType::TOML => serializer::to_toml(&deserialized_result, &annotations)?,
+        OutputType::YAMLStream => {
+            serializer::to_yaml_stream(&deserialized_result, &format, &annotations)?
+        }
+        OutputType::HCL => hclwriter::to_hcl(&deserialized_result, &annotations)?,
+        OutputType::Dotenv => serializer::to_dotenv(&deserialized_result)?,
+        OutputType::Properties => serializer::to_properties(&deserialized_result)?,
+        OutputType::INI => serializer::to_ini(&deserialized_result)?,
+        OutputType::XML => xmlwriter::to_xml(&deserialized_result)?,
+        OutputType::Plist => xmlwriter::to_plist(&deserialized_result)?,
+        OutputType::Text | OutputType::Binary => {
+            unreachable!("text and binary outputs are handled before deserialization")
+        }
+    };
+    let data = match header {
+        Some(h) => add_generated_header(&out_type, data, h),
+        None => data,
     };
+    let data = formatting::finish(data, &format);
+    let out_prefix = out_prefix.map(|p| formatting::apply_line_ending(p, &format));
+    timings.add_since(timings::Phase::Serialize, start);
     return Ok(OutData {
         out_path,
         out_ext,
         out_prefix,
+        out_mode,
         data,
+        binary_data: None,
     });
 }
 
+// Prepend the generated header to the rendered output, using the comment syntax of the output type.
+// Output types without comments (JSON, and text and binary outputs whose syntax is unknown) are
+// left as is.
+fn add_generated_header(out_type: &OutputType, data: String, header: &str) -> String {
+    let comment = match out_type {
+        OutputType::YAML
+        | OutputType::YAMLStream
+        | OutputType::TOML
+        | OutputType::HCL
+        | OutputType::Dotenv
+        | OutputType::Properties => annotations::comment_lines(header, "", "#"),
+        OutputType::INI => annotations::comment_lines(header, "", ";"),
+        OutputType::XML | OutputType::Plist => {
+            // NOTE
+            // Comments can not come before the XML declaration, so the header is rendered right
+            // after it.
+            let comment = format!("<!--\n{}\n-->\n", xmlwriter::escape_comment(header));
+            return match data.split_once('\n') {
+                Some((decl, rest)) if decl.starts_with("<?xml") => {
+                    format!("{decl}\n{comment}{rest}")
+                }
+                _ => format!("{comment}{data}"),
+            };
+        }
+        OutputType::JSON | OutputType::Text | OutputType::Binary => return data,
+    };
+    format!("{comment}{data}")
+}
+
+// Add the generated header to the top level object of a JSON output, as the first key. Returns
+// false if the header could not be added, since there is nowhere to put the key when the top level
+// value is not an object (e.g., an array).
+fn add_json_comment_key(data: &mut serde_json::Value, header: &str) -> bool {
+    let obj = match data {
+        serde_json::Value::Object(obj) => obj,
+        _ => return false,
+    };
+    let mut with_comment = serde_json::Map::with_capacity(obj.len() + 1);
+    with_comment.insert(
+        String::from(header::JSON_COMMENT_KEY),
+        serde_json::Value::String(header.replace('\n', " ")),
+    );
+    with_comment.extend(std::mem::take(obj));
+    *obj = with_comment;
+    return true;
+}
+
+// Copy the bytes of the data of a binary OutData object, which must be a Uint8Array (or any other
+// typed array view) or an ArrayBuffer.
+fn load_binary_data<'a>(
+    scope: &mut v8::HandleScope<'a>,
+    data_local: v8::Local<'a, v8::Value>,
+) -> Result<vec::Vec<u8>> {
+    let view: v8::Local<v8::ArrayBufferView> = if data_local.is_array_buffer_view() {
+        data_local.try_into()?
+    } else if data_local.is_array_buffer() {
+        let buf: v8::Local<v8::ArrayBuffer> = data_local.try_into()?;
+        v8::Uint8Array::new(scope, buf, 0, buf.byte_length())
+            .ok_or_else(|| anyhow!("could not read ArrayBuffer data of binary OutData object"))?
+            .into()
+    } else {
+        return Err(anyhow!(
+            "data of binary OutData object must be a Uint8Array or ArrayBuffer"
+        ));
+    };
+    let mut out = vec![0u8; view.byte_length()];
+    view.copy_contents(&mut out);
+    return Ok(out);
+}
+
 // Load a single result from the main function that is a JS OutData object (not to be confused with
 // the OutData struct defined in this file).
 fn load_one_sencjs_out_data_result<'a>(
@@ -288,8 +1332,14 @@ fn load_one_sencjs_out_data_result<'a>(
     OutputType,
     // out_prefix
     Option<String>,
+    // out_mode
+    Option<u32>,
     // schema_path
     Option<String>,
+    // key_order
+    Option<keyorder::KeyOrder>,
+    // format
+    Option<formatting::FormatOptions>,
     // result_local
     v8::Local<'a, v8::Value>,
 )> {
@@ -297,7 +1347,10 @@ fn load_one_sencjs_out_data_result<'a>(
     let mut out_ext: Option<String> = Some(String::from(".json"));
     let mut out_type = OutputType::JSON;
     let mut out_prefix: Option<String> = None;
+    let mut out_mode: Option<u32> = None;
     let mut schema_path: Option<String> = None;
+    let mut key_order: Option<keyorder::KeyOrder> = None;
+    let mut format: Option<formatting::FormatOptions> = None;
 
     let result_obj: v8::Local<v8::Object> = result_local.try_into()?;
     let out_type_key: v8::Local<v8::Value> = v8::String::new(scope, "out_type").unwrap().into();
@@ -309,6 +1362,46 @@ fn load_one_sencjs_out_data_result<'a>(
             out_type = OutputType::YAML;
             out_ext = Some(String::from(".yaml"));
         }
+        "yaml_stream" => {
+            out_type = OutputType::YAMLStream;
+            out_ext = Some(String::from(".yaml"));
+        }
+        "toml" => {
+            out_type = OutputType::TOML;
+            out_ext = Some(String::from(".toml"));
+        }
+        "hcl" => {
+            out_type = OutputType::HCL;
+            out_ext = Some(String::from(".tf"));
+        }
+        "dotenv" => {
+            out_type = OutputType::Dotenv;
+            out_ext = Some(String::from(".env"));
+        }
+        "properties" => {
+            out_type = OutputType::Properties;
+            out_ext = Some(String::from(".properties"));
+        }
+        "ini" => {
+            out_type = OutputType::INI;
+            out_ext = Some(String::from(".ini"));
+        }
+        "xml" => {
+            out_type = OutputType::XML;
+            out_ext = Some(String::from(".xml"));
+        }
+        "plist" => {
+            out_type = OutputType::Plist;
+            out_ext = Some(String::from(".plist"));
+        }
+        "text" => {
+            out_type = OutputType::Text;
+            out_ext = Some(String::from(".txt"));
+        }
+        "binary" => {
+            out_type = OutputType::Binary;
+            out_ext = Some(String::from(".bin"));
+        }
         "" | "json" => {} // Use default
         s => return Err(anyhow!("out_type {s} in OutData object is not supported")),
     }
@@ -316,12 +1409,18 @@ fn load_one_sencjs_out_data_result<'a>(
     let out_path_key: v8::Local<v8::Value> = v8::String::new(scope, "out_path").unwrap().into();
     let out_ext_key: v8::Local<v8::Value> = v8::String::new(scope, "out_ext").unwrap().into();
     let out_prefix_key: v8::Local<v8::Value> = v8::String::new(scope, "out_prefix").unwrap().into();
+    let out_mode_key: v8::Local<v8::Value> = v8::String::new(scope, "out_mode").unwrap().into();
     let schema_path_key: v8::Local<v8::Value> =
         v8::String::new(scope, "schema_path").unwrap().into();
+    let key_order_key: v8::Local<v8::Value> = v8::String::new(scope, "key_order").unwrap().into();
+    let format_key: v8::Local<v8::Value> = v8::String::new(scope, "format").unwrap().into();
     let maybe_out_path: v8::Local<v8::Value> = result_obj.get(scope, out_path_key).unwrap();
     let maybe_out_ext: v8::Local<v8::Value> = result_obj.get(scope, out_ext_key).unwrap();
     let maybe_out_prefix: v8::Local<v8::Value> = result_obj.get(scope, out_prefix_key).unwrap();
+    let maybe_out_mode: v8::Local<v8::Value> = result_obj.get(scope, out_mode_key).unwrap();
     let maybe_schema_path: v8::Local<v8::Value> = result_obj.get(scope, schema_path_key).unwrap();
+    let maybe_key_order: v8::Local<v8::Value> = result_obj.get(scope, key_order_key).unwrap();
+    let maybe_format: v8::Local<v8::Value> = result_obj.get(scope, format_key).unwrap();
 
     if maybe_out_path.is_string() && maybe_out_ext.is_string() {
         return Err(anyhow!(
@@ -342,22 +1441,71 @@ fn load_one_sencjs_out_data_result<'a>(
         out_prefix = Some(out_prefix_local.to_rust_string_lossy(scope));
     }
 
+    // NOTE
+    // The mode can be set either as an octal string (e.g., "0755"), or as a number (e.g., 0o755 in
+    // JS). Note that a decimal number like 755 is not the same as "0755".
+    if maybe_out_mode.is_string() {
+        let out_mode_local: v8::Local<v8::String> = maybe_out_mode.try_into()?;
+        out_mode = Some(parse_out_mode(&out_mode_local.to_rust_string_lossy(scope))?);
+    } else if maybe_out_mode.is_number() {
+        let mode = maybe_out_mode.number_value(scope).unwrap();
+        if mode.fract() != 0.0 || !(0.0..=f64::from(MAX_OUT_MODE)).contains(&mode) {
+            return Err(anyhow!(
+                "out_mode {mode} in OutData object is not a valid file mode"
+            ));
+        }
+        out_mode = Some(mode as u32);
+    }
+
     if maybe_schema_path.is_string() {
         let schema_path_local: v8::Local<v8::String> = maybe_schema_path.try_into()?;
         schema_path = Some(schema_path_local.to_rust_string_lossy(scope));
     }
 
+    if maybe_key_order.is_string() {
+        let key_order_local: v8::Local<v8::String> = maybe_key_order.try_into()?;
+        key_order = Some(key_order_local.to_rust_string_lossy(scope).parse()?);
+    }
+
+    if maybe_format.is_object() {
+        let fmt = serde_v8::from_v8::<formatting::FormatOptions>(scope, maybe_format)
+            .map_err(|e| anyhow!("format in OutData object is not valid: {e}"))?;
+        format = Some(fmt);
+    }
+
     let out_data_key: v8::Local<v8::Value> = v8::String::new(scope, "data").unwrap().into();
     Ok((
         out_path,
         out_ext,
         out_type,
         out_prefix,
+        out_mode,
         schema_path,
+        key_order,
+        format,
         result_obj.get(scope, out_data_key).unwrap().try_into()?,
     ))
 }
 
+// The largest file mode that can be set with out_mode, which includes the setuid, setgid, and
+// sticky bits.
+const MAX_OUT_MODE: u32 = 0o7777;
+
+// Parse the out_mode of an OutData object set as an octal string, with or without the leading `0`
+// or `0o` (e.g., "0755", "755", or "0o755").
+fn parse_out_mode(s: &str) -> Result<u32> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    // NOTE
+    // from_str_radix accepts a leading sign, so the digits are checked explicitly.
+    let is_octal = !digits.is_empty() && digits.chars().all(|c| c.is_digit(8));
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if is_octal && mode <= MAX_OUT_MODE => Ok(mode),
+        _ => Err(anyhow!(
+            "out_mode {s} in OutData object is not a valid file mode. Must be an octal string (e.g., \"0755\")."
+        )),
+    }
+}
+
 // Checks whether the result from the main function is a JS OutData object from senc.js. It is a JS
 // OutData object if it is an Object and it has the `__is_senc_out_data` method.
 fn result_is_sencjs_out_data(
@@ -427,36 +1575,40 @@ fn result_is_sencjs_out_data_array(
 //   In the above example for `out_path`, the output file path for
 //   `./example/foo/bar/baz/main.sen.js` will be `./out/foo/bar/baz/main.json`
 //
-// This will create all necessary directories to write the output file.
+// This will create all necessary directories to write the output file. If `out_mode` is set, the
+// permissions of the file are set after it is written.
 fn write_data(out_dir: &path::Path, out_file_stem: &str, data: &OutData) -> Result<()> {
-    let mut out_file_path_str = String::new();
-    if let Some(out_path) = &data.out_path {
-        let mut out_file_stem_dir = path::PathBuf::from(out_file_stem)
-            .parent()
-            .unwrap()
-            .to_owned();
-        out_file_stem_dir.push(&out_path);
-        out_file_path_str.push_str(&out_file_stem_dir.to_string_lossy());
-    } else {
-        out_file_path_str.push_str(out_file_stem);
-        out_file_path_str.push_str(&data.out_ext.clone().unwrap());
-    }
-    let out_file_path = path_clean::clean(path::PathBuf::from(out_file_path_str));
-    files::assert_file_path_in_projectroot(&out_file_path, out_dir)?;
+    let out_file_path = data.out_file_path(out_dir, out_file_stem)?;
 
     let out_file_dir = out_file_path.parent().unwrap();
     fs::create_dir_all(out_file_dir)?;
-    let mut f = fs::File::create(out_file_path)?;
+    // NOTE
+    // The file from a previous run may have been made read only with out_mode, so it is removed
+    // first instead of being truncated. This is done even if the output no longer sets out_mode,
+    // since the file would otherwise be stuck as read only.
+    if let Ok(meta) = fs::metadata(&out_file_path) {
+        if meta.permissions().readonly() {
+            fs::remove_file(&out_file_path)?;
+        }
+    }
+    let mut f = fs::File::create(&out_file_path)?;
+    f.write_all(&data.contents_bytes())?;
 
-    let mut tmp = data.data.clone();
-    if let Some(pre) = &data.out_prefix {
-        tmp.insert_str(0, &pre);
-    };
-    f.write_all(tmp.as_bytes())?;
+    if let Some(mode) = data.out_mode {
+        files::set_file_mode(&out_file_path, mode)?;
+    }
 
     return Ok(());
 }
 
+// The project root as seen by the scripts. This is the virtual root when rendering a bundle.
+fn projectroot(ctx: &Context) -> &path::Path {
+    match &ctx.bundle {
+        Some(_) => path::Path::new(bundle::VIRTUAL_ROOT),
+        None => &ctx.projectroot,
+    }
+}
+
 // Load any runtime builtin functions that are templated. These are builtins that are dynamic to
 // the context of the runtime (e.g., the path of the current main file).
 fn load_templated_builtins(ctx: &Context, req: &RunRequest) -> Result<Extension> {
@@ -467,7 +1619,7 @@ fn load_templated_builtins(ctx: &Context, req: &RunRequest) -> Result<Extension>
     let mut hbdata = collections::BTreeMap::new();
     hbdata.insert(
         "projectroot".to_string(),
-        ctx.projectroot.to_string_lossy().to_string(),
+        projectroot(ctx).to_string_lossy().to_string(),
     );
     hbdata.insert("filename".to_string(), req.in_file.clone());
     hbdata.insert(
@@ -493,23 +1645,36 @@ fn load_templated_builtins(ctx: &Context, req: &RunRequest) -> Result<Extension>
 }
 
 // Validate the result data against a specified schema. If no schema is specified, this function
-// does nothing.
+// does nothing. The path of the schema is recorded in |inputs|. Returns the loaded schema, so that
+// the keys of the output can be ordered to follow it.
+//
+// When rendering a bundle, the schema is loaded from the bundle instead of the project tree.
 fn validate_result(
+    ctx: &Context,
     script_dir: &path::Path,
     maybe_schema_path: Option<String>,
     result: &serde_json::Value,
-) -> Result<()> {
+    inputs: &RefCell<vec::Vec<path::PathBuf>>,
+) -> Result<Option<serde_json::Value>> {
     let schema_path_str = match maybe_schema_path {
         None => {
-            return Ok(());
+            return Ok(None);
         }
         Some(d) => d,
     };
     let mut schema_path = path::PathBuf::from(script_dir);
     schema_path.push(schema_path_str);
-    let schema_path_abs = fs::canonicalize(schema_path)?;
-    let schema = validator::new_from_path(schema_path_abs.as_path())?;
-    return schema.validate(result);
+    let raw_schema: serde_json::Value = match &ctx.bundle {
+        Some(b) => serde_json::from_str(b.read_file(&schema_path)?)?,
+        None => {
+            schema_path = fs::canonicalize(schema_path)?;
+            inputs.borrow_mut().push(schema_path.clone());
+            serde_json::from_str(&fs::read_to_string(&schema_path)?)?
+        }
+    };
+    let schema = validator::new_from_value(&raw_schema, &schema_path)?;
+    schema.validate(result)?;
+    return Ok(Some(raw_schema));
 }
 
 // Test cases
@@ -549,6 +1714,30 @@ mod tests {
         check_single_json_output(EXPECTED_IMPORT_CONFIG_OUTPUT_JSON, "import_yaml.js").await;
     }
 
+    #[tokio::test]
+    async fn test_engine_runs_code_with_config_yaml_import_all_documents() {
+        check_single_json_output(
+            EXPECTED_IMPORT_CONFIG_OUTPUT_JSON,
+            "import_yaml_all_documents.js",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_engine_fails_code_with_multiple_documents_yaml_import() {
+        let p = get_fixture_path("import_yaml_multiple_documents.js");
+        let req = RunRequest {
+            in_file: String::from(p.as_path().to_string_lossy()),
+            out_file_stem: String::from(""),
+        };
+        let result = run_js(&get_context(&[]), &req).await;
+        let err = format!(
+            "{:#}",
+            result.err().expect("multiple documents were imported")
+        );
+        assert!(err.contains("manifests.yaml has multiple YAML documents"));
+    }
+
     #[tokio::test]
     async fn test_engine_runs_code_with_node_modules() {
         check_single_json_output(EXPECTED_LODASH_OUTPUT_JSON, "with_lodash.js").await;
@@ -732,6 +1921,432 @@ mod tests {
         let _ = step_result.expect("wrong output");
     }
 
+    #[tokio::test]
+    async fn test_engine_runs_code_with_toml_output() {
+        let od = run_single_out_data("toml.js").await;
+        assert_eq!(od.out_ext(), Some(".toml"));
+        assert_eq!(
+            od.data(),
+            "[build]\ncommand = \"npm run build\"\npublish = \"dist\"\n\n[[redirects]]\nfrom = \"/old\"\nstatus = 301\nto = \"/new\"\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_runs_code_with_hcl_output() {
+        let od = run_single_out_data("hcl.js").await;
+        assert_eq!(od.out_ext(), Some(".tf"));
+        assert_eq!(
+            od.data(),
+            r#"locals {
+  bucket_name = "assets-${var.env}"
+}
+
+resource "aws_s3_bucket" "assets" {
+  bucket = local.bucket_name
+  tags   = {
+    ManagedBy = "senc"
+  }
+}
+"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_runs_code_with_yaml_stream_output() {
+        let od = run_single_out_data("yaml_stream.js").await;
+        assert_eq!(od.out_ext(), Some(".yaml"));
+        assert_eq!(
+            od.data(),
+            "kind: Namespace\nmetadata:\n  name: app\n---\nkind: ServiceAccount\nmetadata:\n  name: app\n  namespace: app\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_runs_code_with_text_output() {
+        let od = run_single_out_data("text.js").await;
+        assert_eq!(od.out_path(), Some("Dockerfile"));
+        assert_eq!(
+            od.data(),
+            "FROM node:20-alpine\nRUN npm ci\nCMD [\"node\", \"index.js\"]\n"
+        );
+        assert_eq!(
+            od.contents(),
+            "# syntax=docker/dockerfile:1\nFROM node:20-alpine\nRUN npm ci\nCMD [\"node\", \"index.js\"]\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_runs_code_with_binary_output() {
+        let od = run_single_out_data("binary.js").await;
+        assert_eq!(od.out_path(), Some("magic.bin"));
+        assert!(od.is_binary());
+        assert_eq!(od.contents_bytes(), vec![0x1f, 0x8b, 0x00, 0xff]);
+    }
+
+    #[tokio::test]
+    async fn test_engine_runs_code_with_out_mode() {
+        let mut od = run_single_out_data("out_mode.js").await;
+        assert_eq!(od.out_mode(), Some(0o755));
+
+        // Write the out data to a temp dir, twice to make sure the file can be rewritten.
+        let temp_dir = env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let outf = temp_dir.join("pre-commit");
+        write_data(&temp_dir, &outf.to_string_lossy(), &od).expect("could not save output");
+        od.out_mode = Some(0o444);
+        write_data(&temp_dir, &outf.to_string_lossy(), &od).expect("could not save output");
+
+        let perms = fs::metadata(&outf)
+            .expect("did not write output file")
+            .permissions();
+
+        // The read only file is replaced even when the output no longer sets out_mode.
+        od.out_mode = None;
+        write_data(&temp_dir, &outf.to_string_lossy(), &od).expect("could not save output");
+        let perms_without_mode = fs::metadata(&outf)
+            .expect("did not write output file")
+            .permissions();
+        fs::remove_dir_all(&temp_dir).expect("could not remove output dir");
+        assert!(perms.readonly());
+        assert!(!perms_without_mode.readonly());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(perms.mode() & MAX_OUT_MODE, 0o444);
+        }
+
+        assert_eq!(parse_out_mode("0o700").unwrap(), 0o700);
+        assert_eq!(
+            parse_out_mode("0855").unwrap_err().to_string(),
+            "out_mode 0855 in OutData object is not a valid file mode. Must be an octal string (e.g., \"0755\")."
+        );
+        assert!(parse_out_mode("+755").is_err());
+        assert!(parse_out_mode("0o+755").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_engine_runs_code_with_key_order() {
+        let od = run_single_out_data("key_order.js").await;
+        assert_eq!(
+            od.data(),
+            "{\n  \"version\": 2.1,\n  \"jobs\": {\n    \"test\": {},\n    \"build\": {}\n  }\n}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_runs_code_with_format_options() {
+        let od = run_single_out_data("format.js").await;
+        assert_eq!(od.data(), "{\r\n\t\"name\": \"senc\"\r\n}\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_engine_applies_line_ending_to_out_prefix() {
+        let od = run_single_out_data("format_prefix.js").await;
+        assert_eq!(
+            od.contents(),
+            "# Managed by senc.\r\n# Do not edit.\r\nname: senc\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_does_not_apply_project_format_to_text_output() {
+        let p = get_fixture_path("text.js");
+        let req = RunRequest {
+            in_file: String::from(p.as_path().to_string_lossy()),
+            out_file_stem: String::from(""),
+        };
+        let mut ctx = get_context(&[]);
+        ctx.format = formatting::FormatOptions::from_json(r#"{"line_ending": "crlf"}"#)
+            .expect("error parsing format options");
+        let od_vec = run_js(&ctx, &req).await.expect("error running js");
+        assert_eq!(
+            od_vec[0].contents(),
+            "# syntax=docker/dockerfile:1\nFROM node:20-alpine\nRUN npm ci\nCMD [\"node\", \"index.js\"]\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_runs_code_with_comments() {
+        let od_vec = run_fixture(&get_context(&[]), "comments.js").await;
+        assert_eq!(od_vec.len(), 2);
+        assert_eq!(
+            od_vec[0].data(),
+            "jobs:\n  build:\n    # Runs on every push\n    runs-on: ubuntu-latest\n"
+        );
+        assert_eq!(
+            od_vec[1].data(),
+            "{\n  \"jobs\": {\n    \"build\": {\n      \"runs-on\": \"ubuntu-latest\"\n    }\n  }\n}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_runs_code_with_generated_header() {
+        let mut ctx = get_context(&[]);
+        ctx.generated_header = Some(header::GeneratedHeader {
+            include_hash: false,
+            json_comment: true,
+        });
+        let od_vec = run_fixture(&ctx, "comments.js").await;
+        assert_eq!(od_vec.len(), 2);
+        assert_eq!(
+            od_vec[0].data(),
+            "# Generated by senc from comments.js — DO NOT EDIT.\njobs:\n  build:\n    # Runs on every push\n    runs-on: ubuntu-latest\n"
+        );
+        assert_eq!(
+            od_vec[1].data(),
+            "{\n  \"$comment\": \"Generated by senc from comments.js — DO NOT EDIT.\",\n  \"jobs\": {\n    \"build\": {\n      \"runs-on\": \"ubuntu-latest\"\n    }\n  }\n}"
+        );
+    }
+
+    #[test]
+    fn test_engine_generated_header_escapes_comments() {
+        let header = "Generated by senc from a---b.js --- DO NOT EDIT.";
+        let data = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<a/>\n");
+        assert_eq!(
+            add_generated_header(&OutputType::XML, data, header),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!--\nGenerated by senc from a- - -b.js - - - DO NOT EDIT.\n-->\n<a/>\n"
+        );
+
+        // The header is dropped for JSON outputs that are not objects.
+        let mut data = serde_json::json!([1, 2]);
+        assert!(!add_json_comment_key(&mut data, header));
+        assert_eq!(data, serde_json::json!([1, 2]));
+    }
+
+    #[tokio::test]
+    async fn test_engine_generated_header_hash_includes_schema() {
+        let temp_dir = env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        fs::create_dir_all(&temp_dir).unwrap();
+        let schema_fixture = get_fixture_path("jsonschema/schema.json");
+        fs::copy(
+            get_fixture_path("jsonschema/pass.js"),
+            temp_dir.join("pass.js"),
+        )
+        .unwrap();
+        fs::copy(&schema_fixture, temp_dir.join("schema.json")).unwrap();
+
+        let req = RunRequest {
+            in_file: String::from(temp_dir.join("pass.js").to_string_lossy()),
+            out_file_stem: String::from(""),
+        };
+        let mut ctx = get_context(&[]);
+        ctx.projectroot = temp_dir.clone();
+        ctx.out_dir = temp_dir.clone();
+        ctx.generated_header = Some(header::GeneratedHeader {
+            include_hash: true,
+            json_comment: true,
+        });
+        let before = run_js(&ctx, &req).await.expect("error running js");
+
+        let schema = fs::read_to_string(&schema_fixture).unwrap();
+        fs::write(
+            temp_dir.join("schema.json"),
+            schema.replace("A product in the catalog", "A product"),
+        )
+        .unwrap();
+        let after = run_js(&ctx, &req).await.expect("error running js");
+
+        assert_ne!(before[0].data(), after[0].data());
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_engine_runs_code_with_yaml_anchors() {
+        let od = run_single_out_data("yaml_anchors.js").await;
+        assert_eq!(
+            od.data(),
+            "build:\n  environment: &env\n    CI: 'true'\ntest:\n  environment: *env\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_detects_out_path_collisions() {
+        let temp_dir = env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let mut ctx = get_context(&[]);
+        ctx.out_dir = temp_dir.clone();
+        ctx.write_to_disk = true;
+
+        // Within a single OutDataArray
+        let od_vec = run_fixture(&ctx, "out_path_collision.js").await;
+        let req = RunRequest {
+            in_file: String::from(get_fixture_path("out_path_collision.js").to_string_lossy()),
+            out_file_stem: String::from(temp_dir.join("out_path_collision").to_string_lossy()),
+        };
+        let err = write_out_data(
+            &ctx,
+            &req,
+            &od_vec,
+            &mut timings::Timings::new(&req.in_file),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "multiple outputs resolve to the same file path:\n{} is written by both {} (output 0) and {} (output 1)",
+                temp_dir.join("ci.yml").display(),
+                req.in_file,
+                req.in_file
+            )
+        );
+        assert!(!temp_dir.exists());
+
+        // Across entrypoints
+        let mut outputs = vec::Vec::new();
+        for fixture in ["simple.js", "simple.ts"] {
+            let od_vec = run_fixture(&ctx, fixture).await;
+            let req = RunRequest {
+                in_file: String::from(get_fixture_path(fixture).to_string_lossy()),
+                out_file_stem: String::from(temp_dir.join("simple").to_string_lossy()),
+            };
+            outputs.push((req, od_vec));
+        }
+        let all: vec::Vec<(&Context, &RunRequest, &[OutData])> = outputs
+            .iter()
+            .map(|(req, od_vec)| (&ctx, req, od_vec.as_slice()))
+            .collect();
+        let err = check_out_path_collisions(&all).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "multiple outputs resolve to the same file path:\n{} is written by both {} and {}",
+                temp_dir.join("simple.json").display(),
+                outputs[0].0.in_file,
+                outputs[1].0.in_file
+            )
+        );
+        assert!(check_out_path_collisions(&all[..1]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_engine_writes_out_data_atomically() {
+        let temp_dir = env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let mut ctx = get_context(&[]);
+        ctx.out_dir = temp_dir.clone();
+        ctx.write_to_disk = true;
+        ctx.atomic = true;
+
+        let p = get_fixture_path("out_mode.js");
+        let req = RunRequest {
+            in_file: String::from(p.as_path().to_string_lossy()),
+            out_file_stem: String::from(temp_dir.join("out_mode").to_string_lossy()),
+        };
+        let od_vec = run_js(&ctx, &req).await.expect("error running js");
+        write_out_data(
+            &ctx,
+            &req,
+            &od_vec,
+            &mut timings::Timings::new(&req.in_file),
+        )
+        .expect("could not save output");
+
+        let outf = temp_dir.join("pre-commit");
+        let contents = fs::read_to_string(&outf).expect("did not write output file");
+        let entries = fs::read_dir(&temp_dir).expect("no output dir").count();
+        let mode_is_set = files::file_mode_is(&outf, 0o755).expect("could not read file mode");
+        fs::remove_dir_all(&temp_dir).expect("could not remove output dir");
+        assert_eq!(contents, "#!/bin/sh\nnpm test\n");
+        assert!(mode_is_set);
+        // The staging directory is removed once the outputs are moved in to place.
+        assert_eq!(entries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_context_builder_runs_in_memory() {
+        let expected_output: serde_json::Value = serde_json::from_str(EXPECTED_ARGS_OUTPUT_JSON)
+            .expect("error unpacking args expected output json");
+
+        let ctx = Context::builder(get_fixture_path(""))
+            .node_modules_dir(Some(get_fixture_path("node_modules")))
+            .tla(&serde_json::json!(["hello world"]))
+            .tla(&serde_json::json!({"msg": "hello world"}))
+            .write_to_disk(false)
+            .build()
+            .expect("error building context");
+        let p = get_fixture_path("args.js");
+        let req = RunRequest {
+            in_file: String::from(p.as_path().to_string_lossy()),
+            out_file_stem: String::from(ctx.out_dir.join("args").to_string_lossy()),
+        };
+        let od_vec = ctx.run(&req).await.expect("error running js");
+        assert_eq!(od_vec.len(), 1);
+
+        let od = &od_vec[0];
+        assert_eq!(
+            od.out_file_path(&ctx.out_dir, &req.out_file_stem)
+                .expect("error resolving output path"),
+            ctx.out_dir.join("args.json")
+        );
+        assert!(!ctx.out_dir.join("args.json").exists());
+        let actual_output: serde_json::Value =
+            serde_json::from_str(od.data()).expect("error unpacking js data");
+        assert_eq!(actual_output, expected_output);
+    }
+
+    #[tokio::test]
+    async fn test_context_run_with_inputs_tracks_modules_and_schemas() {
+        let ctx = get_context(&[]);
+        for (fixture, expected_inputs) in [
+            ("import_json.js", vec!["import_json.js", "someconfig.json"]),
+            (
+                "jsonschema/pass.js",
+                vec!["jsonschema/pass.js", "jsonschema/schema.json"],
+            ),
+        ] {
+            let p = get_fixture_path(fixture);
+            let req = RunRequest {
+                in_file: String::from(p.as_path().to_string_lossy()),
+                out_file_stem: String::from(""),
+            };
+            let out = ctx.run_with_inputs(&req).await.expect("error running js");
+            let expected: Vec<path::PathBuf> = expected_inputs
+                .iter()
+                .map(|f| get_fixture_path(f))
+                .collect();
+            assert_eq!(out.inputs, expected);
+        }
+    }
+
+    #[op2(fast)]
+    fn op_test_double(x: u32) -> u32 {
+        x * 2
+    }
+
+    #[tokio::test]
+    async fn test_engine_runs_code_with_custom_extension() {
+        let ext = extensions::CustomExtension::new("testing")
+            .ops(|| vec![op_test_double::DECL])
+            .js("return { double: (x) => ops.op_test_double(x) };")
+            .dts("function double(x: number): number;");
+        let mut ctx = get_context(&[]);
+        ctx.extensions.push(ext);
+
+        let od_vec = run_fixture(&ctx, "custom_extension.js").await;
+        assert_eq!(od_vec.len(), 1);
+        let actual_output: serde_json::Value =
+            serde_json::from_str(od_vec[0].data()).expect("error unpacking js data");
+        assert_eq!(actual_output, serde_json::json!({"doubled": 42}));
+
+        assert_eq!(
+            extensions::generate_dts(&ctx.extensions),
+            "declare namespace senc.ext {\n  namespace testing {\n    function double(x: number): number;\n  }\n}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_runs_code_with_wasm_plugin() {
+        let projectroot = get_fixture_path("");
+        let plugin =
+            plugins::WasmPlugin::load("math", path::Path::new("plugins/add.wasm"), &projectroot)
+                .expect("error loading plugin");
+        let mut ctx = get_context(&[]);
+        ctx.wasm_plugins.push(plugin);
+
+        let od_vec = run_fixture(&ctx, "wasm_plugin.js").await;
+        assert_eq!(od_vec.len(), 1);
+        let actual_output: serde_json::Value =
+            serde_json::from_str(od_vec[0].data()).expect("error unpacking js data");
+        assert_eq!(actual_output, serde_json::json!({"sum": 42}));
+    }
+
     async fn check_single_json_output(output_json_str: &str, fixture_fname: &str) {
         check_single_json_output_with_args(output_json_str, fixture_fname, &[]).await
     }
@@ -763,6 +2378,29 @@ mod tests {
         assert_eq!(actual_output, expected_output);
     }
 
+    // Run the fixture with the given context, without writing the outputs to disk.
+    async fn run_fixture(ctx: &Context, fixture_fname: &str) -> vec::Vec<OutData> {
+        let p = get_fixture_path(fixture_fname);
+        let req = RunRequest {
+            in_file: String::from(p.as_path().to_string_lossy()),
+            out_file_stem: String::from(""),
+        };
+        return run_js(ctx, &req).await.expect("error running js");
+    }
+
+    async fn run_single_out_data(fixture_fname: &str) -> OutData {
+        let p = get_fixture_path(fixture_fname);
+        let req = RunRequest {
+            in_file: String::from(p.as_path().to_string_lossy()),
+            out_file_stem: String::from(""),
+        };
+        let mut od_vec = run_js(&get_context(&[]), &req)
+            .await
+            .expect("error running js");
+        assert_eq!(od_vec.len(), 1);
+        return od_vec.remove(0);
+    }
+
     fn get_context(args: &[&str]) -> Context {
         let tla_jsons: Option<vec::Vec<String>> = if args.len() == 0 {
             None
@@ -782,6 +2420,18 @@ mod tests {
             projectroot,
             out_dir,
             tla_jsons,
+            profile_cpu_dir: None,
+            write_to_disk: false,
+            atomic: false,
+            extensions: vec::Vec::new(),
+            wasm_plugins: vec::Vec::new(),
+            module_cache: None,
+            bundle: None,
+            module_recorder: None,
+            project_snapshot: None,
+            key_order: keyorder::KeyOrder::default(),
+            format: formatting::FormatOptions::default(),
+            generated_header: None,
         }
     }
 
@@ -0,0 +1,173 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use deno_core::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref EXT_NAME_RE: Regex = Regex::new(r"^[A-Za-z_$][A-Za-z0-9_$]*$").unwrap();
+    static ref INTERNED: Mutex<HashSet<&'static str>> = Mutex::new(HashSet::new());
+}
+
+// A custom extension registered by an embedder of senc. Each custom extension exposes a set of
+// deno_core ops to scripts under the `senc.ext.<name>` namespace.
+//
+// Since a new isolate is created for every entrypoint (potentially on different threads), the
+// extension is stored as a recipe for constructing the deno_core Extension rather than the
+// Extension itself.
+#[derive(Clone)]
+pub struct CustomExtension {
+    name: &'static str,
+    ext_name: &'static str,
+    specifier: &'static str,
+    ops: fn() -> Vec<OpDecl>,
+    js: String,
+    dts: Option<String>,
+    state: Option<Arc<dyn Fn(&mut OpState) + Send + Sync>>,
+}
+
+impl CustomExtension {
+    // Create a new custom extension that will be exposed as `senc.ext.<name>`. The name must be a
+    // valid JavaScript identifier.
+    pub fn new(name: &str) -> CustomExtension {
+        // NOTE
+        // deno_core requires the extension name and module specifier to be static strings. These
+        // are interned so that creating the same extension multiple times does not leak more memory.
+        let name = intern(name.to_string());
+        let ext_name = intern(format!("senc_ext_{name}"));
+        let specifier = intern(format!("ext:senc_ext/{name}.js"));
+        CustomExtension {
+            name,
+            ext_name,
+            specifier,
+            ops: Vec::new,
+            js: String::from("return {};"),
+            dts: None,
+            state: None,
+        }
+    }
+
+    // The ops to register for the extension. Since OpDecls can not be shared across threads, this
+    // is a function that constructs the list of ops. For example:
+    //
+    //   CustomExtension::new("accounts").ops(|| vec![op_resolve_account_id::DECL])
+    pub fn ops(mut self, ops: fn() -> Vec<OpDecl>) -> CustomExtension {
+        self.ops = ops;
+        self
+    }
+
+    // The JS shim that constructs the `senc.ext.<name>` namespace object. The shim is the body of a
+    // function that is called with the deno_core ops object as `ops`, and must return the
+    // namespace object. For example:
+    //
+    //   return { resolve: (name) => ops.op_resolve_account_id(name) };
+    pub fn js(mut self, js: &str) -> CustomExtension {
+        self.js = String::from(js);
+        self
+    }
+
+    // The TypeScript declarations for the members of the namespace object. This is used to generate
+    // the d.ts file for the custom extensions with generate_dts. For example:
+    //
+    //   function resolve(name: string): string;
+    pub fn dts(mut self, dts: &str) -> CustomExtension {
+        self.dts = Some(String::from(dts));
+        self
+    }
+
+    // A hook to initialize the OpState of each isolate, for storing state that the ops can access
+    // (e.g., the contents of a lookup file).
+    pub fn state<F>(mut self, f: F) -> CustomExtension
+    where
+        F: Fn(&mut OpState) + Send + Sync + 'static,
+    {
+        self.state = Some(Arc::new(f));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    // Check that the extension can be exposed to scripts.
+    pub fn validate(&self) -> Result<()> {
+        if !EXT_NAME_RE.is_match(self.name) {
+            return Err(anyhow!(
+                "custom extension name {} is not a valid JavaScript identifier",
+                self.name
+            ));
+        }
+        return Ok(());
+    }
+
+    // Construct the deno_core Extension for a new isolate.
+    pub(crate) fn to_extension(&self) -> Extension {
+        let code = format!(
+            "globalThis.senc.ext[{:?}] = ((ops) => {{\n{}\n}})(Deno.core.ops);\n",
+            self.name, self.js
+        );
+        let files = vec![ExtensionFileSource {
+            specifier: self.specifier,
+            code: ExtensionFileSourceCode::Computed(code.into()),
+        }];
+        let op_state_fn: Option<Box<dyn FnOnce(&mut OpState)>> = match &self.state {
+            Some(f) => {
+                let f = f.clone();
+                Some(Box::new(move |state: &mut OpState| f(state)))
+            }
+            None => None,
+        };
+        Extension {
+            name: self.ext_name,
+            ops: Cow::Owned((self.ops)()),
+            esm_entry_point: Some(self.specifier),
+            esm_files: Cow::Owned(files),
+            op_state_fn,
+            ..Default::default()
+        }
+    }
+}
+
+// Generate the TypeScript declarations for the `senc.ext` namespace from the given custom
+// extensions. Extensions without declarations are typed as `any`.
+//
+// NOTE
+// Custom extensions can only be registered when embedding senc, so the CLI never calls this.
+// Embedders are expected to write the declarations to a d.ts file in the project themselves.
+pub fn generate_dts(exts: &[CustomExtension]) -> String {
+    let mut out = String::from("declare namespace senc.ext {\n");
+    for ext in exts {
+        match &ext.dts {
+            Some(dts) => {
+                out.push_str(&format!("  namespace {} {{\n", ext.name));
+                for line in dts.lines() {
+                    out.push_str(format!("    {line}").trim_end());
+                    out.push('\n');
+                }
+                out.push_str("  }\n");
+            }
+            None => {
+                out.push_str(&format!("  const {}: any;\n", ext.name));
+            }
+        }
+    }
+    out.push_str("}\n");
+    return out;
+}
+
+// Returns a static copy of the string, leaking it only the first time it is interned.
+fn intern(s: String) -> &'static str {
+    let mut interned = INTERNED.lock().unwrap();
+    if let Some(existing) = interned.get(s.as_str()) {
+        return *existing;
+    }
+    let leaked: &'static str = Box::leak(s.into_boxed_str());
+    interned.insert(leaked);
+    return leaked;
+}
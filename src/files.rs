@@ -39,6 +39,11 @@ pub fn get_run_requests_from_path(
     return run_requests_from_file(file_path, outdir, projectroot);
 }
 
+// Whether the given file path is a senc entrypoint, based on the file name.
+pub fn is_entrypoint(file_path: &path::Path) -> bool {
+    FIND_SEN_RE.is_match(&file_path.to_string_lossy())
+}
+
 fn run_requests_from_file(
     file_path: &path::Path,
     outdir: &path::Path,
@@ -106,7 +111,43 @@ pub fn assert_file_path_in_projectroot(
     ));
 }
 
-fn get_out_file_stem(
+// Set the permission bits of the file (e.g., 0o755).
+#[cfg(unix)]
+pub fn set_file_mode(p: &path::Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(p, fs::Permissions::from_mode(mode))?;
+    return Ok(());
+}
+
+// NOTE
+// Other platforms have no permission bits, so the closest equivalent is to mark the file read only
+// when the mode has no write bits.
+#[cfg(not(unix))]
+pub fn set_file_mode(p: &path::Path, mode: u32) -> Result<()> {
+    let mut perms = fs::metadata(p)?.permissions();
+    perms.set_readonly(mode & 0o222 == 0);
+    fs::set_permissions(p, perms)?;
+    return Ok(());
+}
+
+// Whether the permission bits of the file are the given mode, following the same rules as
+// set_file_mode.
+#[cfg(unix)]
+pub fn file_mode_is(p: &path::Path, mode: u32) -> Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+
+    return Ok(fs::metadata(p)?.permissions().mode() & 0o7777 == mode);
+}
+
+#[cfg(not(unix))]
+pub fn file_mode_is(p: &path::Path, mode: u32) -> Result<bool> {
+    return Ok(fs::metadata(p)?.permissions().readonly() == (mode & 0o222 == 0));
+}
+
+// The path of the output file for the given entrypoint, without the extension. This mirrors the
+// path of the entrypoint relative to the project root in the output directory.
+pub fn get_out_file_stem(
     file_path: &path::Path,
     outdir: &path::Path,
     projectroot: &path::Path,
@@ -0,0 +1,624 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::annotations::{self, Annotations};
+use crate::yamlanchors;
+
+// The line endings of the rendered outputs.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    LF,
+    CRLF,
+}
+
+// How strings are quoted in YAML outputs.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum YAMLQuote {
+    // Only quote the strings that need it (e.g., strings that look like numbers).
+    Auto,
+    Single,
+    Double,
+}
+
+// The formatting options for the rendered outputs. These can be set for all the outputs of a
+// project (with the `--format` CLI option), and overridden for each output with the `format` option
+// of OutData. All options are optional, and unset options use the default formatting of each output
+// type.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormatOptions {
+    // The number of spaces to indent JSON and YAML outputs with. Defaults to 2.
+    pub indent: Option<usize>,
+
+    // Whether JSON outputs are indented with tabs instead of spaces.
+    pub tabs: Option<bool>,
+
+    // Whether JSON outputs are rendered on a single line, without any whitespace.
+    pub compact: Option<bool>,
+
+    // Whether JSON outputs are rendered in the canonical form defined by RFC 8785 (JSON
+    // Canonicalization Scheme). This implies compact, and sorts the keys regardless of key_order.
+    pub canonical: Option<bool>,
+
+    // Whether non ASCII characters in JSON outputs are escaped as `\uXXXX`.
+    pub ascii: Option<bool>,
+
+    // Whether the output should end with a newline. When unset, the output is left as rendered.
+    pub trailing_newline: Option<bool>,
+
+    // The line endings of the output. Defaults to LF.
+    pub line_ending: Option<LineEnding>,
+
+    // How strings are quoted in YAML outputs. Defaults to auto.
+    pub yaml_quote: Option<YAMLQuote>,
+
+    // Whether strings with newlines in YAML outputs are rendered as block scalars (`|`).
+    pub yaml_block_strings: Option<bool>,
+
+    // Whether repeated subtrees in YAML outputs are rendered once with an anchor (`&name`), and as
+    // aliases (`*name`) everywhere else.
+    pub yaml_anchors: Option<bool>,
+}
+
+impl FormatOptions {
+    // Parse the format options from JSON (e.g., the value of the `--format` CLI option).
+    pub fn from_json(s: &str) -> Result<FormatOptions> {
+        serde_json::from_str(s).map_err(|e| anyhow!("format options {s} are not valid: {e}"))
+    }
+
+    // Merge the options, with the options set in |overrides| taking precedence.
+    pub fn merge(&self, overrides: &FormatOptions) -> FormatOptions {
+        FormatOptions {
+            indent: overrides.indent.or(self.indent),
+            tabs: overrides.tabs.or(self.tabs),
+            compact: overrides.compact.or(self.compact),
+            canonical: overrides.canonical.or(self.canonical),
+            ascii: overrides.ascii.or(self.ascii),
+            trailing_newline: overrides.trailing_newline.or(self.trailing_newline),
+            line_ending: overrides.line_ending.or(self.line_ending),
+            yaml_quote: overrides.yaml_quote.or(self.yaml_quote),
+            yaml_block_strings: overrides.yaml_block_strings.or(self.yaml_block_strings),
+            yaml_anchors: overrides.yaml_anchors.or(self.yaml_anchors),
+        }
+    }
+
+    fn indent_width(&self) -> usize {
+        self.indent.unwrap_or(2)
+    }
+}
+
+// Serialize the data as JSON with the given format options. With the default options, this is
+// the same as serde_json::to_string_pretty.
+pub fn to_json(data: &serde_json::Value, opts: &FormatOptions) -> Result<String> {
+    let mut out = if opts.canonical.unwrap_or(false) {
+        let mut out = String::new();
+        write_canonical_json(&mut out, data)?;
+        out
+    } else if opts.compact.unwrap_or(false) {
+        serde_json::to_string(data)?
+    } else {
+        let indent = if opts.tabs.unwrap_or(false) {
+            String::from("\t")
+        } else {
+            " ".repeat(opts.indent_width())
+        };
+        let mut buf = Vec::new();
+        let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+        let mut ser = serde_json::Serializer::with_formatter(&mut buf, formatter);
+        data.serialize(&mut ser)?;
+        String::from_utf8(buf)?
+    };
+    if opts.ascii.unwrap_or(false) {
+        out = escape_non_ascii(&out);
+    }
+    return Ok(out);
+}
+
+// Write the data in the canonical form defined by RFC 8785: no whitespace, keys sorted by their
+// UTF-16 code units, and numbers in the shortest form used by ECMAScript.
+fn write_canonical_json(out: &mut String, data: &serde_json::Value) -> Result<()> {
+    match data {
+        serde_json::Value::Number(n) => {
+            if n.is_f64() {
+                out.push_str(&es_number(n.as_f64().unwrap())?);
+            } else {
+                out.push_str(&n.to_string());
+            }
+        }
+        serde_json::Value::Array(arr) => {
+            out.push('[');
+            for (i, item) in arr.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical_json(out, item)?;
+            }
+            out.push(']');
+        }
+        serde_json::Value::Object(obj) => {
+            let mut entries: Vec<(&String, &serde_json::Value)> = obj.iter().collect();
+            entries.sort_by_cached_key(|(k, _)| k.encode_utf16().collect::<Vec<u16>>());
+            out.push('{');
+            for (i, (k, v)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(k)?);
+                out.push(':');
+                write_canonical_json(out, v)?;
+            }
+            out.push('}');
+        }
+        // NOTE
+        // serde_json already renders strings, booleans, and null in the canonical form.
+        v => out.push_str(&serde_json::to_string(v)?),
+    }
+    return Ok(());
+}
+
+// Format the number the same way as Number.prototype.toString in ECMAScript, which is required
+// by RFC 8785.
+fn es_number(f: f64) -> Result<String> {
+    if !f.is_finite() {
+        return Err(anyhow!("{f} can not be represented in json"));
+    }
+    if f == 0.0 {
+        return Ok(String::from("0"));
+    }
+
+    // The LowerExp format of f64 renders the shortest digits that round trip, as required by
+    // ECMAScript (e.g., `1.2345e-7`).
+    let sign = if f < 0.0 { "-" } else { "" };
+    let exp_str = format!("{:e}", f.abs());
+    let (mantissa, exp) = exp_str.split_once('e').unwrap();
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let k = digits.len() as i32;
+    let n = exp.parse::<i32>()? + 1;
+
+    let s = if k <= n && n <= 21 {
+        format!("{digits}{}", "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{digits}", "0".repeat((-n) as usize))
+    } else {
+        let e = n - 1;
+        let e_sign = if e < 0 { "-" } else { "+" };
+        if k == 1 {
+            format!("{digits}e{e_sign}{}", e.abs())
+        } else {
+            format!("{}.{}e{e_sign}{}", &digits[..1], &digits[1..], e.abs())
+        }
+    };
+    return Ok(format!("{sign}{s}"));
+}
+
+// Escape all the non ASCII characters as `\uXXXX`, using surrogate pairs for characters outside
+// the basic multilingual plane. This is only safe for JSON, where non ASCII characters can only
+// appear in strings.
+fn escape_non_ascii(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c.is_ascii() {
+            out.push(c);
+            continue;
+        }
+        let mut buf = [0u16; 2];
+        for unit in c.encode_utf16(&mut buf) {
+            out.push_str(&format!("\\u{:04x}", unit));
+        }
+    }
+    out
+}
+
+// Serialize the data as YAML with the given format options, rendering the anchors and comments
+// attached to the data. With the default options and no annotations, this is the same as
+// serde_yaml::to_string.
+//
+// NOTE
+// serde_yaml does not have any options to control the output, so the YAML is emitted here when any
+// of the YAML options are set. The emitted YAML follows the same style as serde_yaml (e.g.,
+// sequences in mappings are not indented), and uses serde_yaml to render plain scalars so that
+// strings are quoted in the same cases.
+pub fn to_yaml(
+    data: &serde_json::Value,
+    opts: &FormatOptions,
+    annotations: &Annotations,
+) -> Result<String> {
+    let quote = opts.yaml_quote.unwrap_or(YAMLQuote::Auto);
+    let block_strings = opts.yaml_block_strings.unwrap_or(false);
+    let auto_anchors = opts.yaml_anchors.unwrap_or(false);
+    if opts.indent.is_none()
+        && quote == YAMLQuote::Auto
+        && !block_strings
+        && !auto_anchors
+        && annotations.is_empty()
+    {
+        return Ok(serde_yaml::to_string(data)?);
+    }
+
+    let mut emitter = YAMLEmitter {
+        // The `-` of sequence items needs at least one space after it.
+        indent: opts.indent_width().max(2),
+        quote,
+        block_strings,
+        anchors: yamlanchors::Planner::new(data, annotations.anchors(), auto_anchors)?,
+        annotations,
+    };
+    let mut out = String::new();
+    if let Some(c) = annotations.comment("") {
+        out.push_str(&annotations::comment_lines(c, "", "#"));
+    }
+    match emitter.anchors.anchor(data, "", "")? {
+        Some(yamlanchors::Anchor::Define(name)) if is_yaml_container(data) => {
+            out.push_str(&format!("&{name}\n"));
+        }
+        Some(yamlanchors::Anchor::Define(name)) => out.push_str(&format!("&{name} ")),
+        _ => {}
+    }
+    emitter.write_node(&mut out, data, 0, "", "")?;
+    return Ok(out);
+}
+
+// Whether the value is rendered as a block mapping or sequence, as opposed to a scalar.
+fn is_yaml_container(v: &serde_json::Value) -> bool {
+    match v {
+        serde_json::Value::Object(obj) => !obj.is_empty(),
+        serde_json::Value::Array(arr) => !arr.is_empty(),
+        _ => false,
+    }
+}
+
+struct YAMLEmitter<'a> {
+    indent: usize,
+    quote: YAMLQuote,
+    block_strings: bool,
+    anchors: yamlanchors::Planner<'a>,
+    annotations: &'a Annotations,
+}
+
+impl<'a> YAMLEmitter<'a> {
+    // Write the node on a new line, starting at the column |col|, where |p| is the JSON pointer of
+    // the node and |hint| is its key (used to name anchors).
+    fn write_node(
+        &mut self,
+        out: &mut String,
+        v: &serde_json::Value,
+        col: usize,
+        p: &str,
+        hint: &str,
+    ) -> Result<()> {
+        let pad = " ".repeat(col);
+        match v {
+            serde_json::Value::Object(obj) if !obj.is_empty() => {
+                for (k, item) in obj.iter() {
+                    let item_p = yamlanchors::child_pointer(p, k);
+                    self.write_comment(out, &item_p, &pad);
+                    out.push_str(&pad);
+                    out.push_str(&self.key(k)?);
+                    out.push(':');
+                    self.write_child(out, item, col, false, &item_p, k)?;
+                }
+            }
+            serde_json::Value::Array(arr) if !arr.is_empty() => {
+                for (i, item) in arr.iter().enumerate() {
+                    let item_p = format!("{p}/{i}");
+                    self.write_comment(out, &item_p, &pad);
+                    out.push_str(&pad);
+                    out.push('-');
+                    self.write_child(out, item, col, true, &item_p, hint)?;
+                }
+            }
+            v => {
+                out.push_str(&pad);
+                self.write_scalar(out, v, col)?;
+            }
+        }
+        return Ok(());
+    }
+
+    // Write the value of a mapping key or sequence item, where |out| ends with the `key:` or `-`
+    // of the parent at the column |col|.
+    fn write_child(
+        &mut self,
+        out: &mut String,
+        v: &serde_json::Value,
+        col: usize,
+        is_seq_item: bool,
+        p: &str,
+        hint: &str,
+    ) -> Result<()> {
+        let anchor = match self.anchors.anchor(v, p, hint)? {
+            Some(yamlanchors::Anchor::Alias(name)) => {
+                out.push_str(&format!(" *{name}\n"));
+                return Ok(());
+            }
+            Some(yamlanchors::Anchor::Define(name)) => format!(" &{name}"),
+            None => String::new(),
+        };
+        out.push_str(&anchor);
+        if !is_yaml_container(v) {
+            out.push(' ');
+            return self.write_scalar(out, v, col);
+        }
+
+        // Sequences in mappings are not indented, while everything else is.
+        let child_col = if v.is_array() && !is_seq_item {
+            col
+        } else {
+            col + self.indent
+        };
+        let mut child = String::new();
+        self.write_node(&mut child, v, child_col, p, hint)?;
+        // NOTE
+        // Anchored sequence items are rendered on the line after the `-`, since an anchor on the
+        // same line as the first key of a mapping (e.g., `- &a key: v`) anchors the key instead.
+        if is_seq_item && anchor.is_empty() {
+            // The first line of the item is rendered on the same line as the `-`, padded so that it
+            // lines up with the rest of the item.
+            out.push_str(&" ".repeat(self.indent - 1));
+            out.push_str(&child[child_col..]);
+        } else {
+            out.push('\n');
+            out.push_str(&child);
+        }
+        return Ok(());
+    }
+
+    // Write the comment attached to the node at |p| (if any) as line comments above the node.
+    fn write_comment(&self, out: &mut String, p: &str, pad: &str) {
+        if let Some(c) = self.annotations.comment(p) {
+            out.push_str(&annotations::comment_lines(c, pad, "#"));
+        }
+    }
+
+    // Write the scalar (or empty container) value followed by a newline, where |col| is the column
+    // of the parent node, used to indent block scalars.
+    fn write_scalar(&self, out: &mut String, v: &serde_json::Value, col: usize) -> Result<()> {
+        match v {
+            serde_json::Value::Object(_) => out.push_str("{}"),
+            serde_json::Value::Array(_) => out.push_str("[]"),
+            serde_json::Value::String(s) => {
+                if self.block_strings && self.write_block_string(out, s, col) {
+                    return Ok(());
+                }
+                out.push_str(&self.string(s)?);
+            }
+            v => out.push_str(serde_yaml::to_string(v)?.trim_end()),
+        }
+        out.push('\n');
+        return Ok(());
+    }
+
+    // Write the string as a literal block scalar, returning false if the string can not be
+    // represented as one (e.g., it has no newlines, or has leading whitespace).
+    fn write_block_string(&self, out: &mut String, s: &str, col: usize) -> bool {
+        let body = s.trim_end_matches('\n');
+        let has_unsupported_chars = s
+            .chars()
+            .any(|c| c != '\n' && c != '\t' && (c.is_control() || c == '\u{feff}'));
+        if !body.contains('\n') || body.starts_with([' ', '\t']) || has_unsupported_chars {
+            return false;
+        }
+
+        let chomping = match s.len() - body.len() {
+            0 => "-",
+            1 => "",
+            _ => "+",
+        };
+        out.push('|');
+        out.push_str(chomping);
+        out.push('\n');
+        let pad = " ".repeat(col + self.indent);
+        for line in body.split('\n') {
+            if !line.is_empty() {
+                out.push_str(&pad);
+                out.push_str(line);
+            }
+            out.push('\n');
+        }
+        // Keep the extra trailing newlines for the `+` chomping indicator.
+        for _ in 1..(s.len() - body.len()) {
+            out.push('\n');
+        }
+        return true;
+    }
+
+    fn string(&self, s: &str) -> Result<String> {
+        // Single quoted strings can not represent newlines or other control characters, so
+        // those are always double quoted.
+        let needs_escapes = s.chars().any(|c| c.is_control());
+        let quoted = match self.quote {
+            YAMLQuote::Single if !needs_escapes => format!("'{}'", s.replace('\'', "''")),
+            YAMLQuote::Double | YAMLQuote::Single => serde_json::to_string(s)?,
+            YAMLQuote::Auto if needs_escapes => serde_json::to_string(s)?,
+            YAMLQuote::Auto => serde_yaml::to_string(s)?.trim_end().to_string(),
+        };
+        return Ok(quoted);
+    }
+
+    // Keys are always quoted only when needed, regardless of the quote option.
+    fn key(&self, k: &str) -> Result<String> {
+        if k.chars().any(|c| c.is_control()) {
+            return Ok(serde_json::to_string(k)?);
+        }
+        return Ok(serde_yaml::to_string(k)?.trim_end().to_string());
+    }
+}
+
+// Apply the trailing newline and line ending options to the rendered output.
+pub fn finish(data: String, opts: &FormatOptions) -> String {
+    let mut data = data;
+    match opts.trailing_newline {
+        Some(true) if !data.ends_with('\n') => data.push('\n'),
+        Some(false) => data.truncate(data.trim_end_matches(['\r', '\n']).len()),
+        _ => {}
+    }
+    apply_line_ending(data, opts)
+}
+
+// Apply the line ending option to the rendered output. This is also used for the prefix of the
+// output, so that the file does not have mixed line endings.
+pub fn apply_line_ending(data: String, opts: &FormatOptions) -> String {
+    if opts.line_ending == Some(LineEnding::CRLF) {
+        return data.replace("\r\n", "\n").replace('\n', "\r\n");
+    }
+    data
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_formatting_json_options() {
+        let data = json!({"name": "café", "ports": [80, 443]});
+        let opts = FormatOptions {
+            indent: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(
+            to_json(&data, &opts).unwrap(),
+            "{\n    \"name\": \"café\",\n    \"ports\": [\n        80,\n        443\n    ]\n}"
+        );
+
+        let opts = FormatOptions {
+            compact: Some(true),
+            ascii: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            to_json(&data, &opts).unwrap(),
+            "{\"name\":\"caf\\u00e9\",\"ports\":[80,443]}"
+        );
+
+        let data =
+            json!({"b": [1.0, 1e21, 1e-7, 0.000001, -2.5], "a": "\u{20ac}", "\u{e9}": 1, "z": 1});
+        let opts = FormatOptions {
+            canonical: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            to_json(&data, &opts).unwrap(),
+            "{\"a\":\"\u{20ac}\",\"b\":[1,1e+21,1e-7,0.000001,-2.5],\"z\":1,\"\u{e9}\":1}"
+        );
+    }
+
+    #[test]
+    fn test_formatting_yaml_options() {
+        let data = json!({
+            "script": "npm ci\nnpm test\n",
+            "steps": [{"name": "build", "version": "1.0"}],
+        });
+        let opts = FormatOptions {
+            yaml_quote: Some(YAMLQuote::Double),
+            yaml_block_strings: Some(true),
+            ..Default::default()
+        };
+        let expected = r#"script: |
+  npm ci
+  npm test
+steps:
+- name: "build"
+  version: "1.0"
+"#;
+        assert_eq!(
+            to_yaml(&data, &opts, &Default::default()).unwrap(),
+            expected
+        );
+
+        let opts = FormatOptions {
+            indent: Some(4),
+            ..Default::default()
+        };
+        let data = json!({"a": {"b": [{"c": 1}, [true, null]]}, "msg": "it's\nok"});
+        let expected =
+            "a:\n    b:\n    -   c: 1\n    -   - true\n        - null\nmsg: \"it's\\nok\"\n";
+        assert_eq!(
+            to_yaml(&data, &opts, &Default::default()).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_formatting_yaml_anchors() {
+        let checkout = json!({"name": "checkout", "uses": "actions/checkout"});
+        let data = json!({
+            "build": {"steps": [checkout.clone(), {"run": "make"}]},
+            "test": {"steps": [checkout, {"run": "make test"}]},
+        });
+        let opts = FormatOptions {
+            yaml_anchors: Some(true),
+            ..Default::default()
+        };
+        let expected = r#"build:
+  steps:
+  - &steps
+    name: checkout
+    uses: actions/checkout
+  - run: make
+test:
+  steps:
+  - *steps
+  - run: make test
+"#;
+        assert_eq!(
+            to_yaml(&data, &opts, &Default::default()).unwrap(),
+            expected
+        );
+
+        let env = json!({"__senc_yaml_anchor": {"name": "env", "value": {"CI": "true"}}});
+        let mut data = json!({"a": env.clone(), "b": env});
+        let annotations = Annotations::extract(&mut data).unwrap();
+        assert_eq!(
+            to_yaml(&data, &Default::default(), &annotations).unwrap(),
+            "a: &env\n  CI: 'true'\nb: *env\n"
+        );
+    }
+
+    #[test]
+    fn test_formatting_yaml_comments() {
+        let step =
+            json!({"__senc_comment": {"comment": "Build the\nproject", "value": {"run": "make"}}});
+        let mut data = json!({
+            "jobs": {"__senc_comment": {"comment": "CI jobs", "value": {"build": {"steps": [step]}}}},
+        });
+        let annotations = Annotations::extract(&mut data).unwrap();
+        let expected = r#"# CI jobs
+jobs:
+  build:
+    steps:
+    # Build the
+    # project
+    - run: make
+"#;
+        assert_eq!(
+            to_yaml(&data, &Default::default(), &annotations).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_formatting_finish_applies_newlines() {
+        let opts = FormatOptions {
+            trailing_newline: Some(true),
+            line_ending: Some(LineEnding::CRLF),
+            ..Default::default()
+        };
+        assert_eq!(finish(String::from("a\nb"), &opts), "a\r\nb\r\n");
+
+        let opts = FormatOptions {
+            trailing_newline: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(finish(String::from("a: 1\n\n"), &opts), "a: 1");
+    }
+}
@@ -0,0 +1,469 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::annotations::{comment_lines, Annotations};
+use crate::yamlanchors::child_pointer;
+
+lazy_static! {
+    static ref IDENTIFIER_RE: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_-]*$").unwrap();
+}
+
+// The keys of the marker objects created by `senc.hcl.expr` and `senc.hcl.block`.
+const EXPR_MARKER: &str = "__senc_hcl_expr";
+const BLOCK_MARKER: &str = "__senc_hcl_block";
+
+const INDENT: &str = "  ";
+
+// Arrays of scalars are rendered on a single line if they fit within this width.
+const MAX_INLINE_ARRAY_WIDTH: usize = 80;
+
+// Serialize the data as formatted HCL2 (e.g., Terraform .tf files). The data must be an object,
+// which is rendered as the top level body. Within a body:
+// - Values created with `senc.hcl.block(labels, body)` (or arrays of them) are rendered as blocks,
+//   with the key as the block type.
+// - All other values are rendered as attributes. Attributes are rendered before the blocks, with
+//   the `=` aligned in the same way as `terraform fmt`.
+// - Values created with `senc.hcl.expr("var.x")` are rendered as raw expressions.
+//
+// Strings are always rendered as literals, so template sequences (`${` and `%{`) are escaped. Use
+// `senc.hcl.expr` for template strings.
+//
+// The comments attached with senc.comment are rendered as `#` comments above the attributes,
+// blocks, and multiline array items they are attached to.
+//
+// The rendered HCL is parsed again to validate that it is well formed, which catches invalid raw
+// expressions.
+pub fn to_hcl(data: &serde_json::Value, annotations: &Annotations) -> Result<String> {
+    let body = match data {
+        serde_json::Value::Object(obj) if !is_marker(obj) => obj,
+        _ => return Err(anyhow!("hcl output data must be an object")),
+    };
+
+    let mut out = String::new();
+    if let Some(c) = annotations.comment("") {
+        out.push_str(&comment_lines(c, "", "#"));
+    }
+    write_body(&mut out, body, 0, "", "", annotations)?;
+    if let Err(e) = hcl::parse(&out) {
+        return Err(anyhow!("rendered hcl is invalid: {e}"));
+    }
+    return Ok(out);
+}
+
+// A rendered attribute in a body or object, kept so that the `=` of consecutive attributes can be
+// aligned.
+struct Attribute {
+    key: String,
+    value: String,
+    comment: Option<String>,
+}
+
+// NOTE
+// The paths of the nodes are tracked both as |p|, which is used in error messages, and as |cp|, the
+// JSON pointer of the node in the data used to look up the comments. These differ within blocks,
+// since the block bodies are nested in the block markers.
+fn write_body(
+    out: &mut String,
+    body: &serde_json::Map<String, serde_json::Value>,
+    depth: usize,
+    p: &str,
+    cp: &str,
+    annotations: &Annotations,
+) -> Result<()> {
+    let mut attrs = Vec::new();
+    let mut blocks = Vec::new();
+    for (k, v) in body.iter() {
+        let item_p = format!("{p}/{k}");
+        let item_cp = child_pointer(cp, k);
+        if as_block(v).is_some() || is_block_array(v) {
+            if !IDENTIFIER_RE.is_match(k) {
+                return Err(anyhow!(
+                    "[{item_p}] block type {k} is not a valid hcl identifier"
+                ));
+            }
+            blocks.push((k, v, item_p, item_cp));
+        } else {
+            if !IDENTIFIER_RE.is_match(k) {
+                return Err(anyhow!(
+                    "[{item_p}] attribute name {k} is not a valid hcl identifier"
+                ));
+            }
+            attrs.push(Attribute {
+                key: k.clone(),
+                value: render_expr(v, depth, &item_p, &item_cp, annotations)?,
+                comment: annotations.comment(&item_cp).map(String::from),
+            });
+        }
+    }
+
+    write_attributes(out, &attrs, depth);
+    let mut first = attrs.is_empty();
+    for (k, v, item_p, item_cp) in blocks {
+        let items: Vec<&serde_json::Value> = match v {
+            serde_json::Value::Array(arr) => arr.iter().collect(),
+            _ => vec![v],
+        };
+        for (i, item) in items.into_iter().enumerate() {
+            let (block_p, block_cp) = if v.is_array() {
+                (format!("{item_p}/{i}"), format!("{item_cp}/{i}"))
+            } else {
+                (item_p.clone(), item_cp.clone())
+            };
+            if !first {
+                out.push('\n');
+            }
+            first = false;
+            // The comment of the key is rendered above the first block of an array of blocks.
+            let indent = INDENT.repeat(depth);
+            if i == 0 && v.is_array() {
+                if let Some(c) = annotations.comment(&item_cp) {
+                    out.push_str(&comment_lines(c, &indent, "#"));
+                }
+            }
+            if let Some(c) = annotations.comment(&block_cp) {
+                out.push_str(&comment_lines(c, &indent, "#"));
+            }
+            let body_cp = child_pointer(&child_pointer(&block_cp, BLOCK_MARKER), "body");
+            write_block(out, k, item, depth, &block_p, &body_cp, annotations)?;
+        }
+    }
+    return Ok(());
+}
+
+fn write_block(
+    out: &mut String,
+    block_type: &str,
+    block: &serde_json::Value,
+    depth: usize,
+    p: &str,
+    cp: &str,
+    annotations: &Annotations,
+) -> Result<()> {
+    let (labels, body) = as_block(block).unwrap();
+    let indent = INDENT.repeat(depth);
+    out.push_str(&indent);
+    out.push_str(block_type);
+
+    let labels = match labels {
+        serde_json::Value::Array(arr) => arr,
+        _ => return Err(anyhow!("[{p}] block labels must be an array of strings")),
+    };
+    for l in labels {
+        match l {
+            serde_json::Value::String(s) => {
+                out.push(' ');
+                out.push_str(&quote(s));
+            }
+            _ => return Err(anyhow!("[{p}] block labels must be an array of strings")),
+        }
+    }
+
+    let body = match body {
+        serde_json::Value::Object(obj) if !is_marker(obj) => obj,
+        _ => return Err(anyhow!("[{p}] block body must be an object")),
+    };
+    if body.is_empty() {
+        out.push_str(" {}\n");
+        return Ok(());
+    }
+    out.push_str(" {\n");
+    write_body(out, body, depth + 1, p, cp, annotations)?;
+    out.push_str(&indent);
+    out.push_str("}\n");
+    return Ok(());
+}
+
+// Write the attributes, aligning the `=` of consecutive attributes.
+fn write_attributes(out: &mut String, attrs: &[Attribute], depth: usize) {
+    let indent = INDENT.repeat(depth);
+    let mut i = 0;
+    while i < attrs.len() {
+        // Find the group of attributes starting at i to align. Like `terraform fmt`, a multiline
+        // attribute is aligned with the attributes before it, and ends the group. Comment lines
+        // also end the group.
+        let mut end = i + 1;
+        while end < attrs.len()
+            && !attrs[end - 1].value.contains('\n')
+            && attrs[end].comment.is_none()
+        {
+            end += 1;
+        }
+        let width = attrs[i..end]
+            .iter()
+            .map(|a| a.key.chars().count())
+            .max()
+            .unwrap();
+        for a in attrs[i..end].iter() {
+            if let Some(c) = &a.comment {
+                out.push_str(&comment_lines(c, &indent, "#"));
+            }
+            let padding = " ".repeat(width - a.key.chars().count());
+            out.push_str(&format!("{indent}{}{padding} = {}\n", a.key, a.value));
+        }
+        i = end;
+    }
+}
+
+// Render the value as an HCL expression, where |depth| is the indentation level of the attribute
+// the expression is assigned to.
+fn render_expr(
+    v: &serde_json::Value,
+    depth: usize,
+    p: &str,
+    cp: &str,
+    annotations: &Annotations,
+) -> Result<String> {
+    let rendered = match v {
+        serde_json::Value::Null => String::from("null"),
+        serde_json::Value::Bool(b) => b.to_string(),
+        serde_json::Value::Number(n) => n.to_string(),
+        serde_json::Value::String(s) => quote(s),
+        serde_json::Value::Array(arr) => {
+            if arr.is_empty() {
+                return Ok(String::from("[]"));
+            }
+            let mut items = Vec::with_capacity(arr.len());
+            let mut comments = Vec::with_capacity(arr.len());
+            for (i, item) in arr.iter().enumerate() {
+                let item_cp = format!("{cp}/{i}");
+                items.push(render_expr(
+                    item,
+                    depth + 1,
+                    &format!("{p}/{i}"),
+                    &item_cp,
+                    annotations,
+                )?);
+                comments.push(annotations.comment(&item_cp));
+            }
+            let inline = format!("[{}]", items.join(", "));
+            let is_scalar = |v: &serde_json::Value| match v {
+                serde_json::Value::Array(_) => false,
+                serde_json::Value::Object(obj) => obj.contains_key(EXPR_MARKER),
+                _ => true,
+            };
+            // Arrays with commented items are always multiline, so that the comments can be
+            // rendered above the items.
+            if arr.iter().all(is_scalar)
+                && inline.len() <= MAX_INLINE_ARRAY_WIDTH
+                && comments.iter().all(|c| c.is_none())
+            {
+                inline
+            } else {
+                let item_indent = INDENT.repeat(depth + 1);
+                let mut s = String::from("[\n");
+                for (item, comment) in items.into_iter().zip(comments) {
+                    if let Some(c) = comment {
+                        s.push_str(&comment_lines(c, &item_indent, "#"));
+                    }
+                    s.push_str(&format!("{item_indent}{item},\n"));
+                }
+                s.push_str(&INDENT.repeat(depth));
+                s.push(']');
+                s
+            }
+        }
+        serde_json::Value::Object(obj) => {
+            if let Some(serde_json::Value::String(e)) = obj.get(EXPR_MARKER) {
+                return Ok(e.clone());
+            }
+            if obj.contains_key(BLOCK_MARKER) {
+                return Err(anyhow!(
+                    "[{p}] blocks can only be used as the value of a key in a body"
+                ));
+            }
+            if obj.is_empty() {
+                return Ok(String::from("{}"));
+            }
+            let mut attrs = Vec::with_capacity(obj.len());
+            for (k, item) in obj.iter() {
+                // Object keys that are not identifiers are rendered as quoted strings.
+                let key = if IDENTIFIER_RE.is_match(k) {
+                    k.clone()
+                } else {
+                    quote(k)
+                };
+                let item_cp = child_pointer(cp, k);
+                attrs.push(Attribute {
+                    key,
+                    value: render_expr(
+                        item,
+                        depth + 1,
+                        &format!("{p}/{k}"),
+                        &item_cp,
+                        annotations,
+                    )?,
+                    comment: annotations.comment(&item_cp).map(String::from),
+                });
+            }
+            let mut s = String::from("{\n");
+            write_attributes(&mut s, &attrs, depth + 1);
+            s.push_str(&INDENT.repeat(depth));
+            s.push('}');
+            s
+        }
+    };
+    return Ok(rendered);
+}
+
+// Quote the string as an HCL string literal, escaping template sequences so that the string is
+// rendered verbatim.
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '$' | '%' if chars.peek() == Some(&'{') => {
+                out.push(c);
+                out.push(c);
+            }
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn is_marker(obj: &serde_json::Map<String, serde_json::Value>) -> bool {
+    obj.contains_key(EXPR_MARKER) || obj.contains_key(BLOCK_MARKER)
+}
+
+// Returns the labels and body of the block if the value is a block marker.
+fn as_block(v: &serde_json::Value) -> Option<(&serde_json::Value, &serde_json::Value)> {
+    let block = v.as_object()?.get(BLOCK_MARKER)?;
+    Some((&block["labels"], &block["body"]))
+}
+
+fn is_block_array(v: &serde_json::Value) -> bool {
+    match v {
+        serde_json::Value::Array(arr) => {
+            !arr.is_empty() && arr.iter().all(|i| as_block(i).is_some())
+        }
+        _ => false,
+    }
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyorder::{self, KeyOrder};
+    use serde_json::json;
+
+    fn block(labels: serde_json::Value, body: serde_json::Value) -> serde_json::Value {
+        json!({ BLOCK_MARKER: { "labels": labels, "body": body } })
+    }
+
+    fn expr(e: &str) -> serde_json::Value {
+        json!({ EXPR_MARKER: e })
+    }
+
+    #[test]
+    fn test_hclwriter_renders_blocks_and_attributes() {
+        let mut data = json!({
+            "terraform": block(json!([]), json!({"required_version": ">= 1.5"})),
+            "resource": [
+                block(json!(["aws_instance", "web"]), json!({
+                    "ami": expr("data.aws_ami.ubuntu.id"),
+                    "instance_type": "t3.micro",
+                    "tags": {"Name": "web", "kubernetes.io/role": "${literal}"},
+                    "root_block_device": block(json!([]), json!({"volume_size": 20})),
+                })),
+                block(json!(["aws_eip", "web"]), json!({"instance": expr("aws_instance.web.id")})),
+            ],
+        });
+        keyorder::apply(&mut data, KeyOrder::Sorted, None).unwrap();
+        let expected = r#"resource "aws_instance" "web" {
+  ami           = data.aws_ami.ubuntu.id
+  instance_type = "t3.micro"
+  tags          = {
+    Name                 = "web"
+    "kubernetes.io/role" = "$${literal}"
+  }
+
+  root_block_device {
+    volume_size = 20
+  }
+}
+
+resource "aws_eip" "web" {
+  instance = aws_instance.web.id
+}
+
+terraform {
+  required_version = ">= 1.5"
+}
+"#;
+        assert_eq!(
+            to_hcl(&data, &Default::default()).expect("error rendering hcl"),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_hclwriter_renders_comments() {
+        let comment = |value: serde_json::Value, c: &str| json!({"__senc_comment": {"comment": c, "value": value}});
+        let mut data = json!({
+            "locals": block(json!([]), json!({
+                "env": "prod",
+                "region": comment(json!("us-east-1"), "Pinned for latency"),
+                "zones": [comment(json!("a"), "Primary"), "b"],
+            })),
+            "module": comment(block(json!(["vpc"]), json!({"source": "./vpc"})), "Shared network"),
+        });
+        let annotations = Annotations::extract(&mut data).unwrap();
+        let expected = r#"locals {
+  env = "prod"
+  # Pinned for latency
+  region = "us-east-1"
+  zones  = [
+    # Primary
+    "a",
+    "b",
+  ]
+}
+
+# Shared network
+module "vpc" {
+  source = "./vpc"
+}
+"#;
+        assert_eq!(
+            to_hcl(&data, &annotations).expect("error rendering hcl"),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_hclwriter_rejects_invalid_hcl() {
+        let err = to_hcl(&json!({"a": expr("var.x +")}), &Default::default()).unwrap_err();
+        assert!(err.to_string().starts_with("rendered hcl is invalid"));
+
+        let err = to_hcl(&json!({"not valid": 1}), &Default::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[/not valid] attribute name not valid is not a valid hcl identifier"
+        );
+
+        let err = to_hcl(
+            &json!({"a": {"b": block(json!([]), json!({}))}}),
+            &Default::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[/a/b] blocks can only be used as the value of a key in a body"
+        );
+    }
+}
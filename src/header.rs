@@ -0,0 +1,122 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::fs;
+use std::path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::bundle;
+
+// The key that the header is added as to JSON outputs, since JSON has no comments. This is the key
+// that JSON Schema reserves for comments, so it is ignored by most tools that consume JSON configs.
+pub const JSON_COMMENT_KEY: &str = "$comment";
+
+// The header that is prepended to the rendered outputs to mark them as generated, so that readers
+// know to edit the entrypoint instead of the output. The header is rendered with the comment syntax
+// of each output type.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GeneratedHeader {
+    // Whether the header includes the hash of the inputs of the entrypoint, so that stale outputs
+    // can be detected.
+    pub include_hash: bool,
+
+    // Whether the header is added to JSON outputs as the JSON_COMMENT_KEY key of the top level
+    // object. When false, JSON outputs have no header.
+    pub json_comment: bool,
+}
+
+impl GeneratedHeader {
+    // The text of the header for the outputs of the entrypoint |in_file|, where |inputs| are the
+    // files that were read to render it. When rendering a bundle, the hash is the hash of the
+    // bundle instead.
+    pub fn message(
+        &self,
+        projectroot: &path::Path,
+        in_file: &path::Path,
+        inputs: &[path::PathBuf],
+        bundle: Option<&bundle::Bundle>,
+    ) -> Result<String> {
+        let entrypoint = match bundle {
+            Some(b) => b.entrypoint.clone(),
+            None => relative_path(projectroot, in_file),
+        };
+        let mut msg = format!("Generated by senc from {entrypoint} — DO NOT EDIT.");
+        if self.include_hash {
+            let hash = match bundle {
+                Some(b) => format!("{:x}", Sha256::digest(serde_json::to_vec(b)?)),
+                None => hash_inputs(projectroot, inputs)?,
+            };
+            msg.push_str(&format!("\nInput hash: sha256:{hash}"));
+        }
+        return Ok(msg);
+    }
+}
+
+// Hash the contents of the input files, along with their paths relative to the project root so
+// that the hash does not depend on where the project is checked out.
+fn hash_inputs(projectroot: &path::Path, inputs: &[path::PathBuf]) -> Result<String> {
+    let mut inputs = inputs.to_vec();
+    inputs.sort();
+    inputs.dedup();
+
+    let mut hasher = Sha256::new();
+    for p in inputs.iter() {
+        hasher.update(relative_path(projectroot, p).as_bytes());
+        hasher.update([0]);
+        hasher.update(fs::read(p)?);
+        hasher.update([0]);
+    }
+    return Ok(format!("{:x}", hasher.finalize()));
+}
+
+// The path relative to the project root, with `/` as the separator on all platforms.
+fn relative_path(projectroot: &path::Path, p: &path::Path) -> String {
+    let rel = p.strip_prefix(projectroot).unwrap_or(p);
+    rel.to_string_lossy().replace('\\', "/")
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_message_includes_hash() {
+        let projectroot = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let in_file = projectroot.join("import_json.js");
+        let inputs = vec![projectroot.join("someconfig.json"), in_file.clone()];
+
+        let header = GeneratedHeader::default();
+        assert_eq!(
+            header
+                .message(&projectroot, &in_file, &inputs, None)
+                .unwrap(),
+            "Generated by senc from import_json.js — DO NOT EDIT."
+        );
+
+        let header = GeneratedHeader {
+            include_hash: true,
+            ..Default::default()
+        };
+        let msg = header
+            .message(&projectroot, &in_file, &inputs, None)
+            .unwrap();
+        assert_eq!(
+            msg,
+            "Generated by senc from import_json.js — DO NOT EDIT.\nInput hash: sha256:aae9603fae9d7169166a20c422176cd23028d3db30378871c2adf90e5d70db11"
+        );
+
+        // The hash does not depend on the order of the inputs.
+        let reversed: Vec<path::PathBuf> = inputs.iter().rev().cloned().collect();
+        assert_eq!(
+            header
+                .message(&projectroot, &in_file, &reversed, None)
+                .unwrap(),
+            msg
+        );
+    }
+}
@@ -0,0 +1,244 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::io;
+
+use anyhow::Result;
+use serde_json::json;
+
+// Standard JSON-RPC 2.0 error codes.
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+// The maximum size of the body of a single message. Larger messages are skipped and responded to
+// with a parse error, so that a client can not make the server allocate arbitrary amounts of memory.
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+// A single JSON-RPC request or notification. Notifications do not have an id, and must not be
+// responded to.
+pub struct Request {
+    pub id: Option<serde_json::Value>,
+    pub method: String,
+    pub params: serde_json::Value,
+}
+
+impl Request {
+    pub fn from_value(mut msg: serde_json::Value) -> Result<Request, RpcError> {
+        let method = match msg.get("method").and_then(|m| m.as_str()) {
+            Some(m) => String::from(m),
+            None => {
+                return Err(RpcError::new(
+                    INVALID_REQUEST,
+                    "request is missing the method",
+                ))
+            }
+        };
+        let id = msg.get_mut("id").map(|id| id.take());
+        let params = msg
+            .get_mut("params")
+            .map(|p| p.take())
+            .unwrap_or(serde_json::Value::Null);
+        Ok(Request { id, method, params })
+    }
+
+    // Parse the params of the request into the given type, returning an invalid params error if
+    // the params do not match.
+    pub fn parse_params<T: serde::de::DeserializeOwned>(&self) -> Result<T, RpcError> {
+        serde_json::from_value(self.params.clone())
+            .map_err(|e| RpcError::new(INVALID_PARAMS, &format!("invalid params: {e}")))
+    }
+}
+
+// An error that is returned to the client in a JSON-RPC error response.
+#[derive(Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: &str) -> RpcError {
+        RpcError {
+            code,
+            message: String::from(message),
+        }
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> RpcError {
+        RpcError::new(INTERNAL_ERROR, &format!("{e:#}"))
+    }
+}
+
+// Read the body of a single message from the reader. Messages are framed with a `Content-Length`
+// header, following the base protocol of the Language Server Protocol. Returns None when the reader
+// is closed.
+//
+// NOTE
+// Messages with malformed framing (e.g., a missing or invalid `Content-Length` header, or a body
+// larger than MAX_MESSAGE_SIZE) are returned as a parse error instead of failing, so that the
+// server can respond to the client and keep serving. Only errors reading from the reader are
+// returned as errors.
+pub fn read_message<R: io::BufRead>(reader: &mut R) -> Result<Option<Result<Vec<u8>, RpcError>>> {
+    let mut content_length: Option<Result<usize, RpcError>> = None;
+    let mut has_headers = false;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            // An empty line marks the end of the headers. Ignore stray empty lines before the
+            // headers start.
+            if has_headers {
+                break;
+            }
+            continue;
+        }
+        has_headers = true;
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                content_length = Some(value.trim().parse().map_err(|_| {
+                    RpcError::new(
+                        PARSE_ERROR,
+                        &format!("invalid Content-Length header {:?}", value.trim()),
+                    )
+                }));
+            }
+        }
+    }
+
+    let content_length = match content_length {
+        Some(Ok(l)) => l,
+        Some(Err(e)) => return Ok(Some(Err(e))),
+        None => {
+            return Ok(Some(Err(RpcError::new(
+                PARSE_ERROR,
+                "message is missing the Content-Length header",
+            ))))
+        }
+    };
+    if content_length > MAX_MESSAGE_SIZE {
+        // Skip the body, so that the next message can be read.
+        io::copy(&mut reader.take(content_length as u64), &mut io::sink())?;
+        return Ok(Some(Err(RpcError::new(
+            PARSE_ERROR,
+            &format!("message of {content_length} bytes is larger than the maximum of {MAX_MESSAGE_SIZE} bytes"),
+        ))));
+    }
+
+    let mut buf = vec![0; content_length];
+    reader.read_exact(&mut buf)?;
+    Ok(Some(Ok(buf)))
+}
+
+// Parse the body of a message into a request.
+pub fn parse_request(body: &[u8]) -> Result<Request, RpcError> {
+    let msg: serde_json::Value = serde_json::from_slice(body)
+        .map_err(|e| RpcError::new(PARSE_ERROR, &format!("could not parse message: {e}")))?;
+    Request::from_value(msg)
+}
+
+// Write a single message to the writer, framed with a `Content-Length` header.
+pub fn write_message<W: io::Write>(writer: &mut W, msg: &serde_json::Value) -> Result<()> {
+    let body = serde_json::to_string(msg)?;
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Construct the response message for a request.
+pub fn response(
+    id: serde_json::Value,
+    result: Result<serde_json::Value, RpcError>,
+) -> serde_json::Value {
+    match result {
+        Ok(r) => json!({"jsonrpc": "2.0", "id": id, "result": r}),
+        Err(e) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": e.code, "message": e.message},
+        }),
+    }
+}
+
+// Construct a notification message, which is a message that is not responded to.
+pub fn notification(method: &str, params: serde_json::Value) -> serde_json::Value {
+    json!({"jsonrpc": "2.0", "method": method, "params": params})
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jsonrpc_message_roundtrip() {
+        let msg = json!({"jsonrpc": "2.0", "id": 1, "method": "render", "params": {"path": "."}});
+        let mut buf: Vec<u8> = Vec::new();
+        write_message(&mut buf, &msg).expect("error writing message");
+        write_message(&mut buf, &msg).expect("error writing message");
+
+        let mut reader = io::BufReader::new(buf.as_slice());
+        for _i in 0..2 {
+            let body = read_message(&mut reader)
+                .expect("error reading message")
+                .expect("missing message")
+                .expect("malformed message");
+            let req = parse_request(&body).expect("error parsing request");
+            assert_eq!(req.id, Some(json!(1)));
+            assert_eq!(req.method, "render");
+            assert_eq!(req.params, json!({"path": "."}));
+        }
+        assert!(read_message(&mut reader)
+            .expect("error reading message")
+            .is_none());
+    }
+
+    #[test]
+    fn test_jsonrpc_malformed_framing() {
+        let big = MAX_MESSAGE_SIZE + 1;
+        let input = format!(
+            "Content-Length: abc\r\n\r\nX-Other: 1\r\n\r\nContent-Length: {big}\r\n\r\n{}Content-Length: 2\r\n\r\n{{}}",
+            " ".repeat(big)
+        );
+        let mut reader = io::BufReader::new(input.as_bytes());
+        let expected_errors = [
+            String::from("invalid Content-Length header \"abc\""),
+            String::from("message is missing the Content-Length header"),
+            format!(
+                "message of {big} bytes is larger than the maximum of {MAX_MESSAGE_SIZE} bytes"
+            ),
+        ];
+        for expected in expected_errors {
+            let err = read_message(&mut reader)
+                .expect("error reading message")
+                .expect("missing message")
+                .err()
+                .expect("expected malformed message");
+            assert_eq!(err.code, PARSE_ERROR);
+            assert_eq!(err.message, expected);
+        }
+
+        // The server keeps reading messages after the malformed ones.
+        let body = read_message(&mut reader)
+            .expect("error reading message")
+            .expect("missing message")
+            .expect("malformed message");
+        assert_eq!(body, b"{}");
+    }
+
+    #[test]
+    fn test_jsonrpc_parse_error() {
+        let err = parse_request(b"{not json")
+            .err()
+            .expect("expected parse error");
+        assert_eq!(err.code, PARSE_ERROR);
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::str;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+// The order of the object keys in the rendered outputs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyOrder {
+    // Keep the order in which the keys were inserted in the JS object.
+    Insertion,
+    // Sort the keys alphabetically. This is the default.
+    #[default]
+    Sorted,
+    // Follow the order of the `properties` of the schema the output is validated against. Keys that
+    // are not in the schema are sorted, after the keys that are.
+    Schema,
+}
+
+impl str::FromStr for KeyOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "insertion" => Ok(KeyOrder::Insertion),
+            "sorted" => Ok(KeyOrder::Sorted),
+            "schema" => Ok(KeyOrder::Schema),
+            s => Err(anyhow!(
+                "key order {s} is not supported. Must be one of: insertion, sorted, schema."
+            )),
+        }
+    }
+}
+
+// Reorder the keys of all the objects in the data. The |schema| is required for KeyOrder::Schema.
+pub fn apply(
+    data: &mut serde_json::Value,
+    order: KeyOrder,
+    schema: Option<&serde_json::Value>,
+) -> Result<()> {
+    match order {
+        KeyOrder::Insertion => {}
+        KeyOrder::Sorted => order_by_schema(data, None, None),
+        KeyOrder::Schema => match schema {
+            Some(s) => order_by_schema(data, Some(s), Some(s)),
+            None => return Err(anyhow!("key_order schema requires schema_path to be set")),
+        },
+    }
+    return Ok(());
+}
+
+// Reorder the keys of the objects in |data| to follow the `properties` of the given subschema,
+// where |root| is the root schema used to resolve local `$ref`s. Keys that are not listed in the
+// subschema (or all keys when there is no subschema) are sorted.
+fn order_by_schema(
+    data: &mut serde_json::Value,
+    schema: Option<&serde_json::Value>,
+    root: Option<&serde_json::Value>,
+) {
+    let schema = schema.and_then(|s| resolve_ref(s, root));
+    match data {
+        serde_json::Value::Object(obj) => {
+            let properties = schema
+                .and_then(|s| s.get("properties"))
+                .and_then(|p| p.as_object());
+            let additional = schema
+                .and_then(|s| s.get("additionalProperties"))
+                .filter(|s| s.is_object());
+
+            let mut entries: Vec<(String, serde_json::Value)> =
+                std::mem::take(obj).into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| {
+                let rank = |k: &str| properties.and_then(|p| p.keys().position(|pk| pk == k));
+                match (rank(a), rank(b)) {
+                    (Some(ra), Some(rb)) => ra.cmp(&rb),
+                    (Some(_), None) => std::cmp::Ordering::Less,
+                    (None, Some(_)) => std::cmp::Ordering::Greater,
+                    (None, None) => a.cmp(b),
+                }
+            });
+            for (k, mut v) in entries {
+                let subschema = properties.and_then(|p| p.get(&k)).or(additional);
+                order_by_schema(&mut v, subschema, root);
+                obj.insert(k, v);
+            }
+        }
+        serde_json::Value::Array(arr) => {
+            let prefix_items = schema
+                .and_then(|s| s.get("prefixItems"))
+                .and_then(|p| p.as_array());
+            let items = schema
+                .and_then(|s| s.get("items"))
+                .filter(|s| s.is_object());
+            for (i, v) in arr.iter_mut().enumerate() {
+                let subschema = prefix_items.and_then(|p| p.get(i)).or(items);
+                order_by_schema(v, subschema, root);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Resolve the subschema if it is a local reference (e.g., `{"$ref": "#/$defs/foo"}`). References
+// to other documents are not followed.
+fn resolve_ref<'a>(
+    schema: &'a serde_json::Value,
+    root: Option<&'a serde_json::Value>,
+) -> Option<&'a serde_json::Value> {
+    let mut schema = schema;
+    // Limit the number of references that are followed, to guard against cycles.
+    for _ in 0..32 {
+        let r = match schema.get("$ref").and_then(|r| r.as_str()) {
+            Some(r) => r,
+            None => return Some(schema),
+        };
+        schema = root?.pointer(r.strip_prefix('#')?)?;
+    }
+    None
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn keys(v: &serde_json::Value) -> Vec<&str> {
+        v.as_object().unwrap().keys().map(|k| k.as_str()).collect()
+    }
+
+    #[test]
+    fn test_keyorder_sorts_and_preserves_insertion() {
+        let mut data = json!({"version": 2.1, "jobs": {"test": {}, "build": {}}});
+        apply(&mut data, KeyOrder::Insertion, None).unwrap();
+        assert_eq!(keys(&data), vec!["version", "jobs"]);
+        assert_eq!(keys(&data["jobs"]), vec!["test", "build"]);
+
+        apply(&mut data, KeyOrder::Sorted, None).unwrap();
+        assert_eq!(keys(&data), vec!["jobs", "version"]);
+        assert_eq!(keys(&data["jobs"]), vec!["build", "test"]);
+    }
+
+    #[test]
+    fn test_keyorder_follows_schema_properties() {
+        let schema = json!({
+            "properties": {
+                "version": {"type": "number"},
+                "jobs": {"additionalProperties": {"$ref": "#/$defs/job"}},
+            },
+            "$defs": {
+                "job": {"properties": {"docker": {}, "steps": {}}},
+            },
+        });
+        let mut data = json!({
+            "workflows": {},
+            "jobs": {"build": {"steps": [], "docker": [], "environment": {}}},
+            "version": 2.1,
+        });
+        apply(&mut data, KeyOrder::Schema, Some(&schema)).unwrap();
+        assert_eq!(keys(&data), vec!["version", "jobs", "workflows"]);
+        assert_eq!(
+            keys(&data["jobs"]["build"]),
+            vec!["docker", "steps", "environment"]
+        );
+
+        let err = apply(&mut data, KeyOrder::Schema, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "key_order schema requires schema_path to be set"
+        );
+    }
+}
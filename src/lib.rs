@@ -0,0 +1,50 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+// senc is a hermetic TypeScript interpreter for generating Infrastructure as Code (IaC).
+//
+// This library exposes the senc engine so that it can be embedded in other Rust tools. Use
+// `Context::builder` to configure the runtime, and then `Context::render` or `Context::run` to
+// render entrypoints into in-memory outputs. For example:
+//
+//   let ctx = senc::Context::builder("./project")
+//       .tla(&serde_json::json!({"env": "prod"}))
+//       .write_to_disk(false)
+//       .build()?;
+//   let outputs = tokio::runtime::Builder::new_current_thread()
+//       .enable_all()
+//       .build()?
+//       .block_on(ctx.render(path::Path::new("./project/ci")))?;
+
+pub mod annotations;
+pub mod bundle;
+pub mod daemon;
+pub mod engine;
+pub mod extensions;
+pub mod files;
+pub mod formatting;
+pub mod hclwriter;
+pub mod header;
+pub mod jsonrpc;
+pub mod keyorder;
+pub mod lsp;
+pub mod module_loader;
+mod ops;
+pub mod plugins;
+pub mod serializer;
+pub mod server;
+pub mod snapshot;
+pub mod staging;
+pub mod standalone;
+pub mod threadpool;
+pub mod timings;
+pub mod validator;
+pub mod xmlwriter;
+pub mod yamlanchors;
+
+pub use engine::{Context, ContextBuilder, OutData, RunOutput, RunRequest};
+pub use extensions::CustomExtension;
+
+// Re-export deno_core so that embedders can define ops for custom extensions against the same
+// version of deno_core that senc uses.
+pub use deno_core;
@@ -0,0 +1,387 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections;
+use std::fs;
+use std::io;
+use std::path;
+
+use anyhow::Result;
+use deno_core::error::JsError;
+use deno_core::url::Url;
+use log::*;
+use serde_json::json;
+
+use crate::engine;
+use crate::files;
+use crate::jsonrpc;
+use crate::validator;
+use crate::yamlanchors;
+
+// LSP diagnostic severity for errors.
+const SEVERITY_ERROR: i64 = 1;
+
+// A language server that renders entrypoints when they are opened or saved, and publishes the
+// errors from rendering as diagnostics. This includes:
+// - Exceptions thrown while running the entrypoint, mapped to the location in the stack trace.
+// - Schema validation failures from `schema_path`, mapped to the key in the entrypoint source
+//   that corresponds to the instance path of the failure.
+//
+// When a file that is not an entrypoint is saved (e.g., a shared module), all the open entrypoints
+// are rendered again.
+pub struct LanguageServer {
+    ctx: engine::Context,
+    runtime: tokio::runtime::Runtime,
+
+    // The open entrypoints, mapped to the URIs that diagnostics were last published to when
+    // rendering the entrypoint. This is used to clear stale diagnostics.
+    entrypoints: collections::BTreeMap<path::PathBuf, collections::HashSet<String>>,
+}
+
+impl LanguageServer {
+    pub fn new(ctx: engine::Context) -> Result<LanguageServer> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(LanguageServer {
+            ctx,
+            runtime,
+            entrypoints: collections::BTreeMap::new(),
+        })
+    }
+
+    // Serve the language server protocol over stdin and stdout until the client sends the exit
+    // notification or closes stdin.
+    pub fn serve(&mut self) -> Result<()> {
+        let stdin = io::stdin();
+        let mut reader = stdin.lock();
+        let mut stdout = io::stdout();
+
+        while let Some(msg) = jsonrpc::read_message(&mut reader)? {
+            let req = match msg.and_then(|body| jsonrpc::parse_request(&body)) {
+                Ok(r) => r,
+                Err(e) => {
+                    let resp = jsonrpc::response(serde_json::Value::Null, Err(e));
+                    jsonrpc::write_message(&mut stdout, &resp)?;
+                    continue;
+                }
+            };
+            debug!("language server received {}", req.method);
+
+            match req.method.as_str() {
+                "initialize" => {
+                    let result = json!({
+                        "capabilities": {
+                            "textDocumentSync": {
+                                "openClose": true,
+                                "change": 0,
+                                "save": { "includeText": false },
+                            },
+                        },
+                        "serverInfo": { "name": "senc" },
+                    });
+                    respond(&mut stdout, req.id, Ok(result))?;
+                }
+                "shutdown" => {
+                    respond(&mut stdout, req.id, Ok(serde_json::Value::Null))?;
+                }
+                "exit" => break,
+                "textDocument/didOpen" | "textDocument/didSave" => {
+                    if let Some(p) = document_path(&req.params) {
+                        self.on_save(&mut stdout, &p)?;
+                    }
+                }
+                "textDocument/didClose" => {
+                    if let Some(p) = document_path(&req.params) {
+                        self.on_close(&mut stdout, &p)?;
+                    }
+                }
+                m => {
+                    // Requests must be responded to, but unknown notifications (e.g., initialized
+                    // and didChange) are ignored.
+                    let err = jsonrpc::RpcError::new(
+                        jsonrpc::METHOD_NOT_FOUND,
+                        &format!("method {m} is not supported"),
+                    );
+                    respond(&mut stdout, req.id, Err(err))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn on_save<W: io::Write>(&mut self, writer: &mut W, p: &path::Path) -> Result<()> {
+        if let Some(cache) = &self.ctx.module_cache {
+            cache.invalidate(&[p.to_path_buf()]);
+        }
+
+        if files::is_entrypoint(p) {
+            self.entrypoints.entry(p.to_path_buf()).or_default();
+            return self.render_and_publish(writer, p);
+        }
+
+        let entrypoints: Vec<path::PathBuf> = self.entrypoints.keys().cloned().collect();
+        for e in entrypoints {
+            self.render_and_publish(writer, &e)?;
+        }
+        Ok(())
+    }
+
+    fn on_close<W: io::Write>(&mut self, writer: &mut W, p: &path::Path) -> Result<()> {
+        if let Some(uris) = self.entrypoints.remove(p) {
+            for uri in uris {
+                publish_diagnostics(writer, &uri, Vec::new())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Render the entrypoint in memory and publish the diagnostics for the result. This also clears
+    // the diagnostics that were published for the entrypoint in the previous render.
+    fn render_and_publish<W: io::Write>(
+        &mut self,
+        writer: &mut W,
+        entrypoint: &path::Path,
+    ) -> Result<()> {
+        let entrypoint_uri = path_to_uri(entrypoint);
+        let mut diagnostics: collections::BTreeMap<String, Vec<serde_json::Value>> =
+            collections::BTreeMap::new();
+        diagnostics.insert(entrypoint_uri.clone(), Vec::new());
+
+        let run_result = match self.ctx.run_requests_from_path(entrypoint) {
+            Ok(reqs) => {
+                let mut result = Ok(());
+                for req in reqs {
+                    if let Err(e) = self.runtime.block_on(self.ctx.run(&req)) {
+                        result = Err(e);
+                        break;
+                    }
+                }
+                result
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = run_result {
+            for (uri, d) in error_to_diagnostics(entrypoint, &e) {
+                diagnostics.entry(uri).or_default().push(d);
+            }
+        }
+
+        let previous_uris = self
+            .entrypoints
+            .insert(
+                entrypoint.to_path_buf(),
+                diagnostics.keys().cloned().collect(),
+            )
+            .unwrap_or_default();
+        for uri in previous_uris {
+            if !diagnostics.contains_key(&uri) {
+                publish_diagnostics(writer, &uri, Vec::new())?;
+            }
+        }
+        for (uri, d) in diagnostics {
+            publish_diagnostics(writer, &uri, d)?;
+        }
+        Ok(())
+    }
+}
+
+fn respond<W: io::Write>(
+    writer: &mut W,
+    id: Option<serde_json::Value>,
+    result: Result<serde_json::Value, jsonrpc::RpcError>,
+) -> Result<()> {
+    match id {
+        Some(id) => jsonrpc::write_message(writer, &jsonrpc::response(id, result)),
+        None => Ok(()),
+    }
+}
+
+fn publish_diagnostics<W: io::Write>(
+    writer: &mut W,
+    uri: &str,
+    diagnostics: Vec<serde_json::Value>,
+) -> Result<()> {
+    let params = json!({"uri": uri, "diagnostics": diagnostics});
+    let msg = jsonrpc::notification("textDocument/publishDiagnostics", params);
+    jsonrpc::write_message(writer, &msg)
+}
+
+// Extract the file path of the text document from the params of a textDocument notification.
+fn document_path(params: &serde_json::Value) -> Option<path::PathBuf> {
+    let uri = params["textDocument"]["uri"].as_str()?;
+    Url::parse(uri).ok()?.to_file_path().ok()
+}
+
+fn path_to_uri(p: &path::Path) -> String {
+    match Url::from_file_path(p) {
+        Ok(u) => u.to_string(),
+        Err(_) => p.to_string_lossy().to_string(),
+    }
+}
+
+// Convert an error from rendering an entrypoint to diagnostics, keyed by the URI of the file that
+// the diagnostic should be reported on.
+fn error_to_diagnostics(
+    entrypoint: &path::Path,
+    e: &anyhow::Error,
+) -> Vec<(String, serde_json::Value)> {
+    let entrypoint_uri = path_to_uri(entrypoint);
+
+    if let Some(verr) = e.downcast_ref::<validator::ValidationError>() {
+        let source = fs::read_to_string(entrypoint).unwrap_or_default();
+        return verr
+            .failures
+            .iter()
+            .map(|f| {
+                let (line, col, len) = locate_instance_path(&source, &f.instance_path);
+                let instance_path = yamlanchors::display_pointer(&f.instance_path);
+                let msg = format!("[{}] {}", instance_path, f.message);
+                (entrypoint_uri.clone(), new_diagnostic(line, col, len, &msg))
+            })
+            .collect();
+    }
+
+    if let Some(jserr) = e.downcast_ref::<JsError>() {
+        // Report the error on the first frame in the stack trace that points to a file, since that
+        // is where the exception was thrown.
+        let frame = jserr.frames.iter().find(|f| {
+            f.file_name
+                .as_deref()
+                .map(|n| n.starts_with("file://"))
+                .unwrap_or(false)
+        });
+        if let Some(frame) = frame {
+            let uri = frame.file_name.clone().unwrap();
+            // Stack frame locations are 1-indexed, while LSP positions are 0-indexed.
+            let line = frame.line_number.unwrap_or(1).max(1) - 1;
+            let col = frame.column_number.unwrap_or(1).max(1) - 1;
+            let d = new_diagnostic(line as usize, col as usize, 1, &jserr.exception_message);
+            return vec![(uri, d)];
+        }
+        let d = new_diagnostic(0, 0, 0, &jserr.exception_message);
+        return vec![(entrypoint_uri, d)];
+    }
+
+    vec![(entrypoint_uri, new_diagnostic(0, 0, 0, &format!("{e:#}")))]
+}
+
+fn new_diagnostic(line: usize, col: usize, len: usize, msg: &str) -> serde_json::Value {
+    json!({
+        "range": {
+            "start": {"line": line, "character": col},
+            "end": {"line": line, "character": col + len},
+        },
+        "severity": SEVERITY_ERROR,
+        "source": "senc",
+        "message": msg,
+    })
+}
+
+// Find the location in the source that most likely corresponds to the given JSON pointer in the
+// rendered data. Since the data is computed, this can only be a best effort: each object key in
+// the pointer is searched for in the source in order, starting from where the previous key was
+// found. Array indexes are skipped.
+//
+// Returns the (0-indexed) line and column, and the length of the matched key. If no key could be
+// found, this points to the start of the file.
+fn locate_instance_path(source: &str, instance_path: &str) -> (usize, usize, usize) {
+    let mut offset = 0;
+    let mut found: Option<(usize, usize)> = None;
+    for segment in instance_path.split('/').skip(1) {
+        // Unescape the JSON pointer segment.
+        let key = segment.replace("~1", "/").replace("~0", "~");
+        if key.is_empty() || key.chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+        if let Some((idx, len)) = find_key(&source[offset..], &key) {
+            found = Some((offset + idx, len));
+            offset += idx + len;
+        }
+    }
+
+    match found {
+        None => (0, 0, 0),
+        Some((idx, len)) => {
+            let before = &source[..idx];
+            let line = before.matches('\n').count();
+            let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+            // NOTE
+            // LSP positions count UTF-16 code units by default, so the column and length are
+            // converted from byte offsets accordingly.
+            let col = source[line_start..idx].encode_utf16().count();
+            let len = source[idx..idx + len].encode_utf16().count();
+            (line, col, len)
+        }
+    }
+}
+
+// Find the first occurrence of the given object key in the source, either as a bare identifier or
+// a quoted string, followed by a `:`. Returns the byte index and length of the key.
+fn find_key(source: &str, key: &str) -> Option<(usize, usize)> {
+    let candidates = [key.to_string(), format!("\"{key}\""), format!("'{key}'")];
+    let mut best: Option<(usize, usize)> = None;
+    for c in candidates.iter() {
+        let mut search_from = 0;
+        while let Some(i) = source[search_from..].find(c.as_str()) {
+            let idx = search_from + i;
+            let end = idx + c.len();
+            let is_word_start = idx == 0
+                || !source[..idx]
+                    .chars()
+                    .last()
+                    .map(|ch| ch.is_alphanumeric() || ch == '_' || ch == '$')
+                    .unwrap_or(false);
+            let is_key = source[end..].trim_start().starts_with(':');
+            if is_word_start && is_key {
+                if best.map(|(b, _)| idx < b).unwrap_or(true) {
+                    best = Some((idx, c.len()));
+                }
+                break;
+            }
+            search_from = end;
+        }
+    }
+    best
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SOURCE: &str = "export function main() {
+  return new senc.OutData({
+    schema_path: \"schema.json\",
+    data: {
+      productId: 5,
+      \"tags\": [{ name: 5 }],
+    },
+  });
+}
+";
+
+    #[test]
+    fn test_locate_instance_path_finds_key() {
+        assert_eq!(locate_instance_path(SOURCE, "/productId"), (4, 6, 9));
+    }
+
+    #[test]
+    fn test_locate_instance_path_skips_indexes() {
+        assert_eq!(locate_instance_path(SOURCE, "/tags/0/name"), (5, 17, 4));
+    }
+
+    #[test]
+    fn test_locate_instance_path_counts_utf16_columns() {
+        let source = "const data = { emoji: \"😀\", \"ünïcode\": 1 };\n";
+        // The emoji is 2 UTF-16 code units, and the key after it is counted with its quotes.
+        assert_eq!(locate_instance_path(source, "/ünïcode"), (0, 28, 9));
+    }
+
+    #[test]
+    fn test_locate_instance_path_defaults_to_start() {
+        assert_eq!(locate_instance_path(SOURCE, ""), (0, 0, 0));
+        assert_eq!(locate_instance_path(SOURCE, "/missing"), (0, 0, 0));
+    }
+}
@@ -1,32 +1,205 @@
 // Copyright (c) Fensak, LLC.
 // SPDX-License-Identifier: MPL-2.0
 
-mod engine;
-mod files;
 mod logger;
-mod module_loader;
-mod ops;
-mod threadpool;
-mod validator;
 
+use std::env::consts::EXE_SUFFIX;
 use std::fs;
 use std::path;
 use std::process;
 use std::sync::{atomic, Arc};
 
-use anyhow::{Context, Result};
-use clap::Parser;
+use anyhow::{anyhow, Context, Result};
+use clap::{Args, Parser, Subcommand};
 use log::*;
 
+use senc::{
+    bundle, daemon, engine, formatting, header, lsp, plugins, server, snapshot, standalone,
+    threadpool, timings,
+};
+
 // senc is a hermetic TypeScript interpreter for generating Infrastructure as Code (IaC).
 //
 // Use a familiar, type-safe programming language to define and provision infrastructure, with
 // protections that make your code easy to debug and test.
 #[derive(Parser)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    // The path to a .sen file or folder containing .sen files for generating IaC.
-    pub path: path::PathBuf,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    #[clap(flatten)]
+    pub render: RenderArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    // Bundle an entrypoint with all of its dependencies into a single file.
+    #[clap(
+        about = "Bundle an entrypoint and all the modules, data, and schemas it loads into a single ES module that senc can render without the project tree."
+    )]
+    Bundle(BundleArgs),
+
+    // Compile an entrypoint into a standalone executable.
+    #[clap(
+        about = "Compile an entrypoint into a standalone executable that renders it without senc, the project tree, or node_modules."
+    )]
+    Compile(CompileArgs),
+
+    // Run senc as a long running daemon that renders entrypoints on request.
+    #[clap(
+        about = "Run a long running daemon that serves render requests over JSON-RPC on stdin and stdout."
+    )]
+    Daemon(DaemonArgs),
+
+    // Run senc as a language server that publishes render and schema diagnostics.
+    #[clap(
+        about = "Run a language server over stdin and stdout that publishes the errors from rendering entrypoints as diagnostics."
+    )]
+    Lsp(LspArgs),
+
+    // Run senc as a local HTTP server that renders outputs on request.
+    #[clap(
+        about = "Run a local HTTP server that renders the entrypoint owning the requested output file on demand."
+    )]
+    Serve(ServeArgs),
+
+    // Manage the project snapshot.
+    #[clap(
+        about = "Manage the project snapshot, which preloads prelude modules into every runtime."
+    )]
+    Snapshot(SnapshotArgs),
+}
+
+#[derive(Args)]
+struct SnapshotArgs {
+    #[clap(subcommand)]
+    pub command: SnapshotCommand,
+}
+
+#[derive(Subcommand)]
+enum SnapshotCommand {
+    // Build the project snapshot.
+    #[clap(
+        about = "Build a V8 snapshot with the builtins and the prelude modules loaded, which is used to start each runtime while it is fresh."
+    )]
+    Build(SnapshotBuildArgs),
+}
+
+// The args for the default command, which renders the given path.
+#[derive(Args)]
+struct RenderArgs {
+    // The path to a .sen file or folder containing .sen files for generating IaC. This can also be
+    // the path to a bundle created with `senc bundle`.
+    #[clap(required = true)]
+    pub path: Option<path::PathBuf>,
+
+    // The path to a directory where V8 CPU profiles should be written, one per entrypoint.
+    #[clap(
+        long,
+        help = "The path to a directory where a V8 CPU profile (.cpuprofile) is written for each entrypoint."
+    )]
+    pub profile_cpu: Option<path::PathBuf>,
+
+    // Whether a timing breakdown of each entrypoint should be printed after running.
+    #[clap(
+        long,
+        default_value_t = false,
+        help = "When passed in, print a table with the timing breakdown of each entrypoint, sorted by the slowest entrypoint."
+    )]
+    pub timings: bool,
+
+    // Whether the outputs should be staged and only moved in to place once all the entrypoints
+    // rendered successfully.
+    #[clap(
+        long,
+        default_value_t = false,
+        help = "When passed in, all the outputs are written to a staging directory first, and only moved in to place once every entrypoint rendered successfully. Files that are unchanged are left untouched to preserve their mtime."
+    )]
+    pub atomic: bool,
+
+    #[clap(flatten)]
+    pub common: CommonArgs,
+}
+
+#[derive(Args)]
+struct BundleArgs {
+    // The path to the .sen file to bundle.
+    pub entrypoint: path::PathBuf,
+
+    // The path to write the bundle to.
+    #[clap(
+        long,
+        help = "The path to write the bundle to. Defaults to NAME.bundle.js in the current directory, where NAME is the name of the entrypoint without the .sen.js or .sen.ts extension."
+    )]
+    pub output: Option<path::PathBuf>,
+
+    #[clap(flatten)]
+    pub common: CommonArgs,
+}
+
+#[derive(Args)]
+struct CompileArgs {
+    // The path to the .sen file to compile, or a bundle created with `senc bundle`.
+    pub entrypoint: path::PathBuf,
 
+    // The path to write the executable to.
+    #[clap(
+        long,
+        help = "The path to write the executable to. Defaults to NAME in the current directory, where NAME is the name of the entrypoint without the .sen.js or .sen.ts extension."
+    )]
+    pub output: Option<path::PathBuf>,
+
+    #[clap(flatten)]
+    pub common: CommonArgs,
+}
+
+#[derive(Args)]
+struct DaemonArgs {
+    #[clap(flatten)]
+    pub common: CommonArgs,
+}
+
+#[derive(Args)]
+struct LspArgs {
+    #[clap(flatten)]
+    pub common: CommonArgs,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    // The port to listen on.
+    #[clap(long, default_value_t = 8080, help = "The port to listen on.")]
+    pub port: u16,
+
+    // The address to listen on.
+    #[clap(
+        long,
+        default_value_t=String::from("127.0.0.1"),
+        help="The address to listen on.",
+    )]
+    pub host: String,
+
+    #[clap(flatten)]
+    pub common: CommonArgs,
+}
+
+#[derive(Args)]
+struct SnapshotBuildArgs {
+    // The prelude modules to load into the snapshot.
+    #[clap(
+        long,
+        help = "A module to load into the snapshot, either as a path relative to the project root or the name of an npm package. Can be repeated. When omitted, the preludes of the existing snapshot are used."
+    )]
+    pub prelude: Vec<String>,
+
+    #[clap(flatten)]
+    pub common: CommonArgs,
+}
+
+// The args that are shared across all the commands.
+#[derive(Args)]
+struct CommonArgs {
     // Top-level arg (encoded as json) to be passed as an arg to the main function.
     #[clap(
         long,
@@ -34,6 +207,52 @@ struct Cli {
     )]
     pub tla: Option<Vec<String>>,
 
+    // WebAssembly plugins (encoded as name=path) to expose to scripts under senc.plugins.
+    #[clap(
+        long,
+        help = "WebAssembly plugins to expose to scripts under senc.plugins.<name>. Must be of the form name=path, where the path is relative to the project root."
+    )]
+    pub plugin: Option<Vec<String>>,
+
+    // The order of the object keys in the rendered outputs (one of insertion, sorted, schema).
+    #[clap(
+        long,
+        default_value_t=String::from("sorted"),
+        help="The order of the object keys in the rendered outputs. Must be one of: insertion, sorted, schema. Can be overridden per output with the key_order option of OutData.",
+    )]
+    pub key_order: String,
+
+    // The formatting options (encoded as json) of the rendered outputs.
+    #[clap(
+        long,
+        help = "The formatting options of the rendered outputs (e.g., '{\"indent\": 4, \"line_ending\": \"crlf\"}'). Can be overridden per output with the format option of OutData. Not applied to text outputs. Must be JSON encoded."
+    )]
+    pub format: Option<String>,
+
+    // Whether a header marking the outputs as generated should be prepended to the rendered outputs.
+    #[clap(
+        long,
+        default_value_t = false,
+        help = "When passed in, a header marking the outputs as generated from the entrypoint (e.g., \"Generated by senc from main.sen.ts — DO NOT EDIT.\") is prepended to the rendered outputs, using the comment syntax of each output type. JSON outputs have no header unless --generated-header-json-key is passed in."
+    )]
+    pub generated_header: bool,
+
+    // Whether the generated header should include the hash of the inputs of the entrypoint.
+    #[clap(
+        long,
+        default_value_t = false,
+        help = "When passed in, the generated header includes the sha256 hash of the files that were read to render the entrypoint. Implies --generated-header."
+    )]
+    pub generated_header_hash: bool,
+
+    // Whether the generated header should be added to JSON outputs as the $comment key.
+    #[clap(
+        long,
+        default_value_t = false,
+        help = "When passed in, the generated header is added to JSON outputs as the $comment key of the top level object. Implies --generated-header."
+    )]
+    pub generated_header_json_key: bool,
+
     // The logging level (one of trace, debug, info, warn, error).
     #[clap(
         short='l',
@@ -82,59 +301,306 @@ struct Cli {
     pub parallelism: usize,
 }
 
+// The CLI of a standalone executable created with `senc compile`, which renders the bundle embedded
+// in the executable.
+#[derive(Parser)]
+#[clap(about = "Render the config bundled into this executable.")]
+struct StandaloneCli {
+    // Top-level arg (encoded as json) to be passed as an arg to the main function.
+    #[clap(
+        long,
+        help = "Top-level arguments to pass to the main function. Each arg will be passed to the main function as a positional arg. Must be JSON encoded."
+    )]
+    pub tla: Option<Vec<String>>,
+
+    // The logging level (one of trace, debug, info, warn, error).
+    #[clap(
+        short='l',
+        long,
+        default_value_t=String::from("info"),
+        help="The logging level. Must be one of: trace, debug, info, warn, error.",
+    )]
+    pub loglevel: String,
+
+    // Whether log output should never output in color.
+    #[clap(
+        long,
+        default_value_t = false,
+        help = "When passed in, log output will never output in color."
+    )]
+    pub no_color: bool,
+
+    // The path to a directory where the IaC files should be generated.
+    #[clap(
+        short='o',
+        long,
+        default_value_t=String::from("generated"),
+        help="The path to a directory where the IaC files should be generated.",
+    )]
+    pub outdir: String,
+}
+
 fn main() -> Result<()> {
+    // Executables created with `senc compile` only render the embedded bundle.
+    if let Some(b) = standalone::current_exe_bundle()? {
+        return run_standalone(StandaloneCli::parse(), b);
+    }
+
     let args = Cli::parse();
-    logger::init(&args.loglevel, args.no_color);
+    match args.command {
+        Some(Command::Bundle(bundle_args)) => run_bundle(bundle_args),
+        Some(Command::Compile(compile_args)) => run_compile(compile_args),
+        Some(Command::Daemon(daemon_args)) => run_daemon(daemon_args),
+        Some(Command::Lsp(lsp_args)) => run_lsp(lsp_args),
+        Some(Command::Serve(serve_args)) => run_serve(serve_args),
+        Some(Command::Snapshot(snapshot_args)) => match snapshot_args.command {
+            SnapshotCommand::Build(build_args) => run_snapshot_build(build_args),
+        },
+        None => run_render(args.render),
+    }
+}
+
+// Render all the entrypoints in the given path in parallel.
+fn run_render(args: RenderArgs) -> Result<()> {
+    logger::init(&args.common.loglevel, args.common.no_color);
+
+    let p = args.path.unwrap();
+    let mut ctx_builder = new_context_builder(&args.common)?
+        .profile_cpu_dir(args.profile_cpu)
+        .atomic(args.atomic);
+    if bundle::is_bundle(&p) {
+        ctx_builder = ctx_builder.bundle(&p);
+    }
+    let ctx = ctx_builder.build()?;
+
+    let requests = ctx
+        .run_requests_from_path(&p)
+        .with_context(|| format!("could not collect files to execute"))?;
+
+    let has_quit = Arc::new(atomic::AtomicBool::new(false));
+    let mut pool = threadpool::ThreadPool::new(ctx, args.common.parallelism, has_quit.clone());
+    set_ctrlc_handler(has_quit);
+
+    for r in requests {
+        pool.run(r)?;
+    }
+    let result = pool.wait().and_then(|_| pool.write_outputs());
+    if args.timings {
+        let all_timings: Vec<timings::Timings> =
+            pool.take_outputs().into_iter().map(|o| o.timings).collect();
+        eprint!("{}", timings::render_summary(&all_timings));
+    }
+    result.with_context(|| format!("could not run all files"))?;
+
+    return Ok(());
+}
 
-    let fpath = fs::canonicalize(&args.path)?;
-    let projectroot = fs::canonicalize(&args.projectroot)?;
-    let out_dir = match fs::canonicalize(&args.outdir) {
-        Ok(d) => d,
-        Err(_e) => {
-            fs::create_dir_all(&args.outdir)?;
-            fs::canonicalize(&args.outdir)?
+// Bundle the entrypoint into a single file.
+fn run_bundle(args: BundleArgs) -> Result<()> {
+    logger::init(&args.common.loglevel, args.common.no_color);
+
+    let ctx = new_context_builder(&args.common)?
+        .write_to_disk(false)
+        .build()?;
+    let b = bundle::create(&ctx, &args.entrypoint)
+        .with_context(|| format!("could not bundle {}", args.entrypoint.display()))?;
+
+    let out = match args.output {
+        Some(out) => out,
+        None => path::PathBuf::from(format!("{}.bundle.js", entrypoint_name(&args.entrypoint)?)),
+    };
+    fs::write(&out, b.to_module()?)?;
+    info!("wrote bundle of {} to {}", b.entrypoint, out.display());
+
+    return Ok(());
+}
+
+// Compile the entrypoint into a standalone executable.
+fn run_compile(args: CompileArgs) -> Result<()> {
+    logger::init(&args.common.loglevel, args.common.no_color);
+
+    let b = if bundle::is_bundle(&args.entrypoint) {
+        // NOTE
+        // The render settings and plugins are recorded in the bundle when it is created, so they
+        // can not be changed when compiling an existing bundle.
+        let c = &args.common;
+        if c.plugin.is_some()
+            || c.format.is_some()
+            || c.key_order != "sorted"
+            || c.generated_header
+            || c.generated_header_hash
+            || c.generated_header_json_key
+        {
+            return Err(anyhow!(
+                "--plugin, --key-order, --format and --generated-header* can not be passed in when compiling a bundle. Pass them to senc bundle instead."
+            ));
         }
+        bundle::Bundle::load(&args.entrypoint)?
+    } else {
+        let ctx = new_context_builder(&args.common)?
+            .write_to_disk(false)
+            .build()?;
+        bundle::create(&ctx, &args.entrypoint)
+            .with_context(|| format!("could not bundle {}", args.entrypoint.display()))?
+    };
+
+    let out = match args.output {
+        Some(out) => out,
+        None => path::PathBuf::from(entrypoint_name(&args.entrypoint)? + EXE_SUFFIX),
     };
+    standalone::write_executable(&std::env::current_exe()?, &b, &out)?;
+    info!("compiled {} to {}", b.entrypoint, out.display());
+
+    return Ok(());
+}
 
-    // Find the node_modules directory, if it exists. Otherwise, set to None.
-    let node_modules_dir = match files::find_node_modules_dir(projectroot.as_path()) {
-        Err(e) => {
-            warn!("{}", e);
-            None
+// Render the bundle embedded in a standalone executable.
+fn run_standalone(args: StandaloneCli, b: bundle::Bundle) -> Result<()> {
+    logger::init(&args.loglevel, args.no_color);
+
+    // The bundle is set on the builder (instead of on the built Context), so that the plugins are
+    // loaded from the bundle and the project snapshot of the current directory is not used.
+    let settings = b.settings.clone();
+    let ctx = engine::Context::builder(".")
+        .node_modules_dir(None)
+        .out_dir(&args.outdir)
+        .tla_jsons(args.tla)
+        .key_order(settings.key_order)
+        .format(settings.format)
+        .generated_header(settings.generated_header)
+        .loaded_bundle(b)
+        .build()?;
+
+    let req = ctx.run_requests_from_path(path::Path::new("."))?.remove(0);
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(ctx.run(&req))
+        .with_context(|| format!("could not render {}", req.in_file))?;
+
+    return Ok(());
+}
+
+// The name of the entrypoint without the .sen.js or .sen.ts extension (or the .bundle.js extension
+// for bundles), in the current directory.
+//
+// NOTE
+// Only the known extensions are dropped, so that other dots in the name are kept (e.g., the name of
+// `app.v2.sen.ts` is `app.v2`). The artifacts are named by appending to the name for the same
+// reason, instead of with Path::with_extension.
+fn entrypoint_name(entrypoint: &path::Path) -> Result<String> {
+    let fname = match entrypoint.file_name() {
+        Some(fname) => fname.to_string_lossy().to_string(),
+        None => {
+            return Err(anyhow!(
+                "{} is not a path to an entrypoint file",
+                entrypoint.display()
+            ))
         }
-        Ok(p) => Some(p),
     };
+    for ext in [".sen.js", ".sen.mjs", ".sen.ts", ".bundle.js"] {
+        if let Some(name) = fname.strip_suffix(ext) {
+            return Ok(name.to_string());
+        }
+    }
+    return Ok(match fname.rsplit_once('.') {
+        Some((name, _)) if !name.is_empty() => name.to_string(),
+        _ => fname,
+    });
+}
 
-    engine::init_v8();
+// Run the JSON-RPC daemon until the client shuts it down.
+fn run_daemon(args: DaemonArgs) -> Result<()> {
+    logger::init(&args.common.loglevel, args.common.no_color);
 
-    let requests = files::get_run_requests_from_path(&fpath, &out_dir, &projectroot)
-        .with_context(|| format!("could not collect files to execute"))?;
+    let ctx = new_context_builder(&args.common)?
+        .module_cache(true)
+        .project_snapshot(false)
+        .build()?;
 
     let has_quit = Arc::new(atomic::AtomicBool::new(false));
-    let ctx = engine::Context {
-        node_modules_dir,
-        projectroot,
-        out_dir,
-        tla_jsons: args.tla,
-    };
-    let mut pool = threadpool::ThreadPool::new(ctx, args.parallelism, has_quit.clone());
-    let hq = has_quit.clone();
+    let mut d = daemon::Daemon::new(ctx, args.common.parallelism, has_quit.clone());
+    set_ctrlc_handler(has_quit);
+    d.serve()
+}
+
+// Run the language server until the client exits.
+fn run_lsp(args: LspArgs) -> Result<()> {
+    logger::init(&args.common.loglevel, args.common.no_color);
+
+    // The language server only reports diagnostics, so the outputs are never written to disk.
+    let ctx = new_context_builder(&args.common)?
+        .write_to_disk(false)
+        .module_cache(true)
+        .project_snapshot(false)
+        .build()?;
+
+    let mut server = lsp::LanguageServer::new(ctx)?;
+    server.serve()
+}
+
+// Run the HTTP server until the process is stopped.
+fn run_serve(args: ServeArgs) -> Result<()> {
+    logger::init(&args.common.loglevel, args.common.no_color);
+
+    // The outputs are served over HTTP, so they are never written to disk.
+    let ctx = new_context_builder(&args.common)?
+        .write_to_disk(false)
+        .module_cache(true)
+        .project_snapshot(false)
+        .build()?;
+
+    let mut s = server::Server::new(ctx)?;
+    s.serve(&format!("{}:{}", args.host, args.port))
+}
+
+// Build the project snapshot with the prelude modules.
+fn run_snapshot_build(args: SnapshotBuildArgs) -> Result<()> {
+    logger::init(&args.common.loglevel, args.common.no_color);
+
+    let ctx = new_context_builder(&args.common)?
+        .write_to_disk(false)
+        .project_snapshot(false)
+        .build()?;
+    let snapshot_path = snapshot::build(&ctx, &args.prelude)
+        .with_context(|| format!("could not build project snapshot"))?;
+    info!("wrote project snapshot to {}", snapshot_path.display());
+
+    return Ok(());
+}
+
+// Start building the runtime context from the args that are shared across the commands.
+fn new_context_builder(args: &CommonArgs) -> Result<engine::ContextBuilder> {
+    let mut ctx_builder = engine::Context::builder(&args.projectroot)
+        .out_dir(&args.outdir)
+        .tla_jsons(args.tla.clone())
+        .key_order(args.key_order.parse()?);
+    if let Some(f) = &args.format {
+        ctx_builder = ctx_builder.format(formatting::FormatOptions::from_json(f)?);
+    }
+    if args.generated_header || args.generated_header_hash || args.generated_header_json_key {
+        ctx_builder = ctx_builder.generated_header(Some(header::GeneratedHeader {
+            include_hash: args.generated_header_hash,
+            json_comment: args.generated_header_json_key,
+        }));
+    }
+    for p in args.plugin.iter().flatten() {
+        let (name, plugin_path) = plugins::parse_plugin_arg(p)?;
+        ctx_builder = ctx_builder.wasm_plugin(&name, plugin_path);
+    }
+    Ok(ctx_builder)
+}
+
+// Gracefully shutdown on the first SIGINT by setting |has_quit|, and immediately on the second.
+fn set_ctrlc_handler(has_quit: Arc<atomic::AtomicBool>) {
     ctrlc::set_handler(move || {
-        if hq.load(atomic::Ordering::SeqCst) {
+        if has_quit.load(atomic::Ordering::SeqCst) {
             warn!("Received second SIGINT. Shutting down immediately.");
             process::exit(1);
         }
 
         warn!("Shutting down gracefully...");
-        hq.store(true, atomic::Ordering::SeqCst);
+        has_quit.store(true, atomic::Ordering::SeqCst);
     })
     .expect("Error setting Ctrl-C handler");
-
-    for r in requests {
-        pool.run(r)?;
-    }
-    pool.wait()
-        .with_context(|| format!("could not run all files"))?;
-
-    return Ok(());
 }
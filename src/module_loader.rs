@@ -1,9 +1,14 @@
 // Copyright (c) Fensak, LLC.
 // SPDX-License-Identifier: MPL-2.0
 
+use std::cell::{Cell, RefCell};
+use std::collections;
 use std::fs;
 use std::path;
 use std::pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time;
 
 use anyhow::{anyhow, Result as AnyhowResult};
 use deno_ast::MediaType;
@@ -12,6 +17,9 @@ use deno_ast::SourceTextInfo;
 use deno_core::futures::FutureExt;
 use deno_core::*;
 use log::*;
+use serde::Deserialize;
+
+use crate::bundle;
 
 // The transpile type. Determines how the code should be transpiled before loading.
 enum TranspileType {
@@ -20,25 +28,113 @@ enum TranspileType {
     YAML,       // Transpile yaml files to json.
 }
 
+// A cache of loaded (and transpiled) module code that can be shared across runtimes, so that only
+// files that changed run through transpile. Entries are keyed by the module specifier, and are
+// reused as long as the modification time and size of the underlying file are unchanged.
+#[derive(Default)]
+pub struct ModuleCache {
+    entries: Mutex<collections::HashMap<String, CachedModule>>,
+}
+
+struct CachedModule {
+    path: path::PathBuf,
+    modified: time::SystemTime,
+    len: u64,
+    code: String,
+}
+
+impl ModuleCache {
+    pub fn new() -> ModuleCache {
+        ModuleCache::default()
+    }
+
+    fn get(&self, specifier: &str, meta: &fs::Metadata) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(specifier)?;
+        match meta.modified() {
+            Ok(modified) if modified == entry.modified && meta.len() == entry.len => {
+                Some(entry.code.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn insert(&self, specifier: &str, p: &path::Path, meta: &fs::Metadata, code: &str) {
+        let modified = match meta.modified() {
+            Ok(m) => m,
+            // Don't cache if the platform doesn't support modification times, since there is no
+            // way to tell if the entry is stale.
+            Err(_) => return,
+        };
+        let entry = CachedModule {
+            path: p.to_path_buf(),
+            modified,
+            len: meta.len(),
+            code: String::from(code),
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(String::from(specifier), entry);
+    }
+
+    // Remove the entries for the given paths. Directories invalidate all the entries for files
+    // within them. Returns the number of entries that were removed.
+    pub fn invalidate(&self, paths: &[path::PathBuf]) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, e| !paths.iter().any(|p| e.path.starts_with(p)));
+        before - entries.len()
+    }
+
+    // Remove all the entries in the cache. Returns the number of entries that were removed.
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.clear();
+        before
+    }
+}
+
 // The TypeScript module loader.
 // This will check to see if the file is a TypeScript file, and run those through swc to transpile
 // to JS.
-//
-// TODO:
-// - Implement caching so only files that changed run through transpile.
 pub struct TsModuleLoader {
     projectroot: path::PathBuf,
     node_modules_dir: Option<path::PathBuf>,
+
+    // The total time spent transpiling modules loaded through this loader. This is shared with the
+    // engine so that it can be reported in the timing breakdown.
+    transpile_time: Rc<Cell<time::Duration>>,
+
+    // The cache of transpiled modules, if enabled.
+    module_cache: Option<Arc<ModuleCache>>,
+
+    // The paths of all the files that were loaded through this loader. This is shared with the
+    // engine so that callers can tell when the inputs of an entrypoint change.
+    loaded_files: Rc<RefCell<Vec<path::PathBuf>>>,
+
+    // Records the loaded modules and node_modules resolutions, if set. This is used to bundle an
+    // entrypoint with all of its dependencies.
+    module_recorder: Option<Arc<bundle::ModuleRecorder>>,
 }
 
 impl TsModuleLoader {
     pub fn new(
         projectroot: path::PathBuf,
         node_modules_dir: Option<path::PathBuf>,
+        transpile_time: Rc<Cell<time::Duration>>,
+        module_cache: Option<Arc<ModuleCache>>,
+        loaded_files: Rc<RefCell<Vec<path::PathBuf>>>,
+        module_recorder: Option<Arc<bundle::ModuleRecorder>>,
     ) -> TsModuleLoader {
         TsModuleLoader {
             projectroot,
             node_modules_dir,
+            transpile_time,
+            module_cache,
+            loaded_files,
+            module_recorder,
         }
     }
 
@@ -63,7 +159,11 @@ impl TsModuleLoader {
         let new_specifier_path = find_node_module_specifier(node_modules_path, specifier)?;
         let new_specifier = new_specifier_path.to_str().unwrap();
 
-        resolve_import(new_specifier, referrer).map_err(|e| e.into())
+        let resolved = resolve_import(new_specifier, referrer)?;
+        if let Some(recorder) = &self.module_recorder {
+            recorder.record_resolution(referrer, specifier, &resolved);
+        }
+        Ok(resolved)
     }
 }
 
@@ -99,6 +199,10 @@ impl ModuleLoader for TsModuleLoader {
         let module_specifier = module_specifier.clone();
         let node_modules_dir = self.node_modules_dir.clone();
         let projectroot = self.projectroot.clone();
+        let transpile_time = self.transpile_time.clone();
+        let module_cache = self.module_cache.clone();
+        let loaded_files = self.loaded_files.clone();
+        let module_recorder = self.module_recorder.clone();
         async move {
             let orig_path = module_specifier.to_file_path().unwrap();
 
@@ -163,8 +267,23 @@ impl ModuleLoader for TsModuleLoader {
                 }
             };
 
+            // Use the cached code if the file hasn't changed since it was last loaded.
+            let meta = fs::metadata(&path)?;
+            loaded_files.borrow_mut().push(path.clone());
+            if let Some(cache) = &module_cache {
+                if let Some(code) = cache.get(module_specifier.as_str(), &meta) {
+                    if let Some(recorder) = &module_recorder {
+                        recorder.record_module(&module_specifier, &path, &module_type, &code);
+                    }
+                    let module =
+                        ModuleSource::new(module_type, FastString::from(code), &module_specifier);
+                    return Ok(module);
+                }
+            }
+
             // Read the file, transpile if necessary.
             let code = fs::read_to_string(&path)?;
+            let transpile_start = time::Instant::now();
             let code = match transpile_type {
                 TranspileType::No => code,
                 TranspileType::Typescript => {
@@ -179,10 +298,17 @@ impl ModuleLoader for TsModuleLoader {
                     parsed.transpile(&Default::default())?.text
                 }
                 TranspileType::YAML => {
-                    let parsed: serde_json::Value = serde_yaml::from_str(&code)?;
-                    serde_json::to_string(&parsed)?
+                    let all_documents = module_specifier.query() == Some("all_documents");
+                    yaml_to_json(&path, &code, all_documents)?
                 }
             };
+            transpile_time.set(transpile_time.get() + transpile_start.elapsed());
+            if let Some(cache) = &module_cache {
+                cache.insert(module_specifier.as_str(), &path, &meta, &code);
+            }
+            if let Some(recorder) = &module_recorder {
+                recorder.record_module(&module_specifier, &path, &module_type, &code);
+            }
 
             // Load and return module.
             let module = ModuleSource::new(module_type, FastString::from(code), &module_specifier);
@@ -192,6 +318,34 @@ impl ModuleLoader for TsModuleLoader {
     }
 }
 
+// Convert the YAML file contents to JSON. When |all_documents| is set (with the all_documents option
+// of senc.import_yaml), the file is always loaded as an array with one element per document
+// (separated by `---`). Otherwise, the file must have a single document, which is loaded as is.
+//
+// NOTE
+// The shape of the import never depends on the number of documents in the file, so that callers
+// that expect an array do not break when the file happens to have a single document.
+fn yaml_to_json(path: &path::Path, code: &str, all_documents: bool) -> AnyhowResult<String> {
+    let mut docs = Vec::new();
+    for doc in serde_yaml::Deserializer::from_str(code) {
+        docs.push(serde_json::Value::deserialize(doc)?);
+    }
+    if all_documents {
+        return Ok(serde_json::to_string(&docs)?);
+    }
+    let parsed = match docs.len() {
+        0 => serde_json::Value::Null,
+        1 => docs.pop().unwrap(),
+        _ => {
+            return Err(anyhow!(
+                "{} has multiple YAML documents. Use senc.import_yaml(p, {{ all_documents: true }}) to import all of them as an array.",
+                path.to_string_lossy()
+            ))
+        }
+    };
+    return Ok(serde_json::to_string(&parsed)?);
+}
+
 fn find_node_module_specifier(
     node_modules_dir: &path::PathBuf,
     specifier: &str,
@@ -6,6 +6,8 @@ use std::path;
 use deno_core::*;
 use log::*;
 
+use crate::plugins;
+
 #[op2(fast)]
 pub fn op_log_trace(#[string] msg: &str) -> Result<(), error::AnyError> {
     trace!("{msg}");
@@ -46,3 +48,14 @@ pub fn op_path_relpath(
     let relp = p.strip_prefix(base_str)?;
     Ok(relp.to_string_lossy().to_string())
 }
+
+#[op2]
+pub fn op_wasm_plugin_module<'a>(
+    scope: &mut v8::HandleScope<'a>,
+    state: &OpState,
+    #[string] name: &str,
+) -> Result<v8::Local<'a, v8::Value>, error::AnyError> {
+    let store = state.borrow::<plugins::WasmPluginStore>();
+    let module = store.module(scope, name)?;
+    Ok(module.into())
+}
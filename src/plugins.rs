@@ -0,0 +1,292 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::borrow::Cow;
+use std::collections;
+use std::fs;
+use std::path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use deno_core::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::files;
+use crate::ops;
+
+lazy_static! {
+    static ref PLUGIN_NAME_RE: Regex = Regex::new(r"^[A-Za-z_$][A-Za-z0-9_$]*$").unwrap();
+}
+
+// A WebAssembly plugin declared for the project. The exports of the plugin are exposed to scripts
+// under `senc.plugins.<name>`.
+//
+// To preserve hermeticity, plugins are instantiated without any imports. This means that plugins
+// have no access to the filesystem, network, or any other host functionality, and plugins that
+// declare imports are rejected.
+#[derive(Clone)]
+pub struct WasmPlugin {
+    pub name: String,
+    pub path: path::PathBuf,
+    code: Arc<Vec<u8>>,
+
+    // The compiled module, which is shared across all the clones of the plugin so that the plugin is
+    // only compiled once, instead of once for every runtime.
+    compiled: Arc<Mutex<Option<v8::CompiledWasmModule>>>,
+}
+
+impl WasmPlugin {
+    // Load the plugin at the given path. Relative paths are resolved relative to the project root,
+    // and the plugin must be located within the project root.
+    pub fn load(name: &str, p: &path::Path, projectroot: &path::Path) -> Result<WasmPlugin> {
+        let plugin_path = fs::canonicalize(projectroot.join(p))?;
+        files::assert_file_path_in_projectroot(&plugin_path, projectroot)?;
+        let code = fs::read(&plugin_path)?;
+        WasmPlugin::from_code(name, &plugin_path, code)
+    }
+
+    // Create the plugin from the code of the module, for plugins that are not loaded from the
+    // project tree (e.g., the plugins embedded in a bundle). |p| is only used to identify the plugin.
+    pub fn from_code(name: &str, p: &path::Path, code: Vec<u8>) -> Result<WasmPlugin> {
+        if !PLUGIN_NAME_RE.is_match(name) {
+            return Err(anyhow!(
+                "plugin name {name} is not a valid JavaScript identifier"
+            ));
+        }
+        if count_imports(&code)? > 0 {
+            return Err(anyhow!("plugin {name} must not import any host functions"));
+        }
+        Ok(WasmPlugin {
+            name: String::from(name),
+            path: p.to_path_buf(),
+            code: Arc::new(code),
+            compiled: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    // The code of the WebAssembly module.
+    pub fn code(&self) -> &[u8] {
+        self.code.as_slice()
+    }
+
+    // Get the WebAssembly.Module object of the plugin for the isolate of the given scope, compiling
+    // the plugin the first time it is requested.
+    //
+    // NOTE
+    // Compiled modules are not tied to an isolate, so the module compiled for the first runtime is
+    // reused by all the other runtimes (across all the threads) without copying or compiling the
+    // code again.
+    fn module<'a>(
+        &self,
+        scope: &mut v8::HandleScope<'a>,
+    ) -> Result<v8::Local<'a, v8::WasmModuleObject>> {
+        let mut compiled = self.compiled.lock().unwrap();
+        if let Some(c) = compiled.as_ref() {
+            return v8::WasmModuleObject::from_compiled_module(scope, c)
+                .ok_or_else(|| anyhow!("could not load the compiled plugin {}", self.name));
+        }
+
+        let tc_scope = &mut v8::TryCatch::new(scope);
+        let module = match v8::WasmModuleObject::compile(tc_scope, self.code()) {
+            Some(m) => m,
+            None => {
+                let msg = match tc_scope.exception() {
+                    Some(e) => e.to_rust_string_lossy(tc_scope),
+                    None => String::from("unknown error"),
+                };
+                return Err(anyhow!("could not compile plugin {}: {msg}", self.name));
+            }
+        };
+        *compiled = Some(module.get_compiled_module());
+        return Ok(module);
+    }
+}
+
+// Count the imports declared by the WebAssembly module, by reading the import section of the
+// binary. This is checked when the plugin is loaded so that plugins with imports are rejected
+// before any runtime is created.
+fn count_imports(code: &[u8]) -> Result<u32> {
+    if code.len() < 8 || &code[..4] != b"\0asm" {
+        return Err(anyhow!("not a WebAssembly module"));
+    }
+    let mut pos = 8;
+    while pos < code.len() {
+        let id = code[pos];
+        pos += 1;
+        let size = read_leb_u32(code, &mut pos)? as usize;
+        if id == 2 {
+            return read_leb_u32(code, &mut pos);
+        }
+        pos += size;
+    }
+    return Ok(0);
+}
+
+// Read an unsigned LEB128 encoded integer at |pos|, advancing |pos| past it.
+fn read_leb_u32(code: &[u8], pos: &mut usize) -> Result<u32> {
+    let mut result: u32 = 0;
+    for shift in (0..35).step_by(7) {
+        let b = *code
+            .get(*pos)
+            .ok_or_else(|| anyhow!("truncated WebAssembly module"))?;
+        *pos += 1;
+        result |= ((b & 0x7f) as u32) << shift;
+        if b & 0x80 == 0 {
+            return Ok(result);
+        }
+    }
+    return Err(anyhow!("invalid integer in WebAssembly module"));
+}
+
+// Parse a plugin declaration from the CLI, which is of the form `name=path`.
+pub fn parse_plugin_arg(arg: &str) -> Result<(String, path::PathBuf)> {
+    match arg.split_once('=') {
+        Some((name, p)) if !name.is_empty() && !p.is_empty() => {
+            Ok((String::from(name), path::PathBuf::from(p)))
+        }
+        _ => Err(anyhow!(
+            "plugin {arg} must be of the form name=path/to/plugin.wasm"
+        )),
+    }
+}
+
+// The declared plugins, stored in the OpState so that the plugin loader script can fetch the
+// compiled modules.
+pub struct WasmPluginStore {
+    plugins: collections::HashMap<String, WasmPlugin>,
+}
+
+impl WasmPluginStore {
+    pub fn module<'a>(
+        &self,
+        scope: &mut v8::HandleScope<'a>,
+        name: &str,
+    ) -> Result<v8::Local<'a, v8::WasmModuleObject>> {
+        match self.plugins.get(name) {
+            Some(p) => p.module(scope),
+            None => Err(anyhow!("plugin {name} is not declared")),
+        }
+    }
+}
+
+// Construct the extension that instantiates the given plugins when the isolate is created.
+pub fn new_extension(plugins: &[WasmPlugin]) -> Result<Extension> {
+    let mut hbs = handlebars::Handlebars::new();
+    let tmpl = include_str!("templated_builtins/wasm_plugins.js.hbs");
+    hbs.register_template_string("t1", tmpl)?;
+
+    let names: Vec<&str> = plugins.iter().map(|p| p.name.as_str()).collect();
+    let mut hbdata = collections::BTreeMap::new();
+    hbdata.insert("plugins".to_string(), names);
+    let rendered = hbs.render("t1", &hbdata)?;
+
+    let store = WasmPluginStore {
+        plugins: plugins
+            .iter()
+            .map(|p| (p.name.clone(), p.clone()))
+            .collect(),
+    };
+
+    let specifier = "ext:builtins/wasm_plugins.js";
+    let code = ExtensionFileSourceCode::Computed(rendered.into());
+    let files = vec![ExtensionFileSource { specifier, code }];
+    let ext = Extension {
+        name: "wasmplugins",
+        ops: Cow::Borrowed(&[ops::op_wasm_plugin_module::DECL]),
+        esm_entry_point: Some(specifier),
+        esm_files: Cow::Owned(files),
+        op_state_fn: Some(Box::new(move |state: &mut OpState| state.put(store))),
+        ..Default::default()
+    };
+    Ok(ext)
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine;
+
+    #[test]
+    fn test_plugins_parse_plugin_arg() {
+        let (name, p) = parse_plugin_arg("math=plugins/add.wasm").expect("error parsing plugin");
+        assert_eq!(name, "math");
+        assert_eq!(p, path::PathBuf::from("plugins/add.wasm"));
+
+        for arg in ["math", "=plugins/add.wasm", "math=", "="] {
+            let err = parse_plugin_arg(arg).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("plugin {arg} must be of the form name=path/to/plugin.wasm")
+            );
+        }
+    }
+
+    #[test]
+    fn test_plugins_load() {
+        let projectroot = get_fixture_path("");
+        let plugin = WasmPlugin::load("math", path::Path::new("plugins/add.wasm"), &projectroot)
+            .expect("error loading plugin");
+        assert_eq!(plugin.path, get_fixture_path("plugins/add.wasm"));
+        assert_eq!(
+            plugin.code(),
+            fs::read(get_fixture_path("plugins/add.wasm")).unwrap()
+        );
+
+        let err = WasmPlugin::load("1math", path::Path::new("plugins/add.wasm"), &projectroot)
+            .err()
+            .expect("expected invalid name error");
+        assert_eq!(
+            err.to_string(),
+            "plugin name 1math is not a valid JavaScript identifier"
+        );
+        assert!(
+            WasmPlugin::load("math", path::Path::new("../../Cargo.toml"), &projectroot).is_err()
+        );
+    }
+
+    #[test]
+    fn test_plugins_rejects_imports() {
+        let projectroot = get_fixture_path("");
+        let err = WasmPlugin::load(
+            "host",
+            path::Path::new("plugins/imports.wasm"),
+            &projectroot,
+        )
+        .err()
+        .expect("expected import error");
+        assert_eq!(
+            err.to_string(),
+            "plugin host must not import any host functions"
+        );
+
+        let err = WasmPlugin::from_code("js", path::Path::new("js.wasm"), b"export {}".to_vec())
+            .err()
+            .expect("expected invalid module error");
+        assert_eq!(err.to_string(), "not a WebAssembly module");
+    }
+
+    #[test]
+    fn test_plugins_rejects_duplicate_names() {
+        let err = engine::Context::builder(get_fixture_path(""))
+            .node_modules_dir(None)
+            .write_to_disk(false)
+            .wasm_plugin("math", "plugins/add.wasm")
+            .wasm_plugin("math", "plugins/add.wasm")
+            .build()
+            .err()
+            .expect("expected duplicate plugin error");
+        assert_eq!(err.to_string(), "plugin math is declared more than once");
+    }
+
+    fn get_fixture_path(relpath: &str) -> path::PathBuf {
+        let mut p = path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("tests/fixtures");
+        if relpath != "" {
+            p.push(relpath);
+        }
+        return p;
+    }
+}
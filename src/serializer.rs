@@ -0,0 +1,569 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::annotations::{comment_lines, Annotations};
+use crate::formatting;
+use crate::yamlanchors::{child_pointer, display_pointer};
+
+lazy_static! {
+    static ref DOTENV_KEY_RE: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+    static ref DOTENV_BARE_VALUE_RE: Regex = Regex::new(r"^[A-Za-z0-9_./:@,+-]*$").unwrap();
+}
+
+// Serialize the data as TOML. The data must be an object, and can not contain values that TOML can
+// not represent:
+// - null values, since TOML has no null.
+// - Arrays that mix different types of values (e.g., strings and numbers, or tables and scalars).
+// - Integers that are too large to fit in a signed 64 bit integer.
+//
+// The keys of each table are rendered in the order of the data, with the plain key/value pairs of
+// each table before the sub tables. The comments attached with senc.comment are rendered above the
+// keys and table headers they are attached to. Comments on the items of inline arrays are dropped.
+pub fn to_toml(data: &serde_json::Value, annotations: &Annotations) -> Result<String> {
+    let v = match to_toml_value(data, "")? {
+        v @ toml::Value::Table(_) => v,
+        _ => return Err(anyhow!("toml output data must be an object")),
+    };
+    let out = toml::to_string(&v)?;
+    if annotations.is_empty() {
+        return Ok(out);
+    }
+
+    // NOTE
+    // The toml crate can not render comments, so the rendered TOML is parsed again with toml_edit,
+    // which keeps the formatting, and the comments are added to the decor of the keys and tables.
+    let mut doc = out.parse::<toml_edit::Document>()?;
+    add_toml_comments(doc.as_table_mut(), data, "", annotations);
+    let mut out = match annotations.comment("") {
+        Some(c) => comment_lines(c, "", "#"),
+        None => String::new(),
+    };
+    out.push_str(&doc.to_string());
+    return Ok(out);
+}
+
+fn add_toml_comments(
+    table: &mut toml_edit::Table,
+    data: &serde_json::Value,
+    p: &str,
+    annotations: &Annotations,
+) {
+    let obj = match data {
+        serde_json::Value::Object(obj) => obj,
+        _ => return,
+    };
+    for (k, v) in obj.iter() {
+        let item_p = child_pointer(p, k);
+        let comment = annotations
+            .comment(&item_p)
+            .map(|c| comment_lines(c, "", "#"))
+            .unwrap_or_default();
+        if let Some(toml_edit::Item::Value(_)) = table.get(k) {
+            if let Some(decor) = table.key_decor_mut(k).filter(|_| !comment.is_empty()) {
+                add_comment_to_decor(decor, &comment);
+            }
+            continue;
+        }
+
+        match table.get_mut(k) {
+            Some(toml_edit::Item::Table(t)) => {
+                if !comment.is_empty() {
+                    // Tables that only contain other tables have no header of their own, unless
+                    // there is something to attach to it.
+                    t.set_implicit(false);
+                    add_comment_to_decor(t.decor_mut(), &comment);
+                }
+                add_toml_comments(t, v, &item_p, annotations);
+            }
+            Some(toml_edit::Item::ArrayOfTables(arr)) => {
+                let items = match v {
+                    serde_json::Value::Array(items) => items,
+                    _ => continue,
+                };
+                for (i, item) in items.iter().enumerate() {
+                    let t = match arr.get_mut(i) {
+                        Some(t) => t,
+                        None => break,
+                    };
+                    let el_p = format!("{item_p}/{i}");
+                    // The comment of the key is rendered above the first table of the array.
+                    let mut c = if i == 0 {
+                        comment.clone()
+                    } else {
+                        String::new()
+                    };
+                    if let Some(el_comment) = annotations.comment(&el_p) {
+                        c.push_str(&comment_lines(el_comment, "", "#"));
+                    }
+                    if !c.is_empty() {
+                        add_comment_to_decor(t.decor_mut(), &c);
+                    }
+                    add_toml_comments(t, item, &el_p, annotations);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Add the comment lines to the end of the prefix of the decor, after the existing prefix (e.g., the
+// blank line before a table header), so that the comment is right above the key or header.
+fn add_comment_to_decor(decor: &mut toml_edit::Decor, comment: &str) {
+    let existing = decor
+        .prefix()
+        .and_then(|p| p.as_str())
+        .unwrap_or_default()
+        .to_string();
+    decor.set_prefix(format!("{existing}{comment}"));
+}
+
+// Convert the JSON value to a TOML value, where |p| is the JSON pointer to the value used in
+// error messages.
+fn to_toml_value(data: &serde_json::Value, p: &str) -> Result<toml::Value> {
+    let v = match data {
+        serde_json::Value::Null => {
+            return Err(anyhow!(
+                "[{}] null can not be represented in toml",
+                display_pointer(p)
+            ))
+        }
+        serde_json::Value::Bool(b) => toml::Value::Boolean(*b),
+        serde_json::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                toml::Value::Integer(i)
+            } else if n.is_u64() {
+                return Err(anyhow!(
+                    "[{}] integer {n} is too large to be represented in toml",
+                    display_pointer(p)
+                ));
+            } else {
+                toml::Value::Float(n.as_f64().unwrap())
+            }
+        }
+        serde_json::Value::String(s) => toml::Value::String(s.clone()),
+        serde_json::Value::Array(arr) => {
+            let mut items = Vec::with_capacity(arr.len());
+            for (i, item) in arr.iter().enumerate() {
+                items.push(to_toml_value(item, &format!("{p}/{i}"))?);
+            }
+            if let Some(first) = items.first() {
+                if let Some(other) = items.iter().find(|i| toml_type(i) != toml_type(first)) {
+                    return Err(anyhow!(
+                        "[{}] array mixes {} and {} values, which can not be represented in toml",
+                        display_pointer(p),
+                        toml_type(first),
+                        toml_type(other)
+                    ));
+                }
+            }
+            toml::Value::Array(items)
+        }
+        serde_json::Value::Object(obj) => {
+            let mut tbl = toml::map::Map::new();
+            for (k, item) in obj.iter() {
+                tbl.insert(k.clone(), to_toml_value(item, &format!("{p}/{k}"))?);
+            }
+            toml::Value::Table(tbl)
+        }
+    };
+    return Ok(v);
+}
+
+// Serialize the data as a stream of YAML documents, separated by `---`. The data must be an array,
+// with each element rendered as its own document (e.g., for Kubernetes manifests). Annotations are
+// scoped to each document, since YAML aliases can not refer to anchors in other documents.
+pub fn to_yaml_stream(
+    data: &serde_json::Value,
+    opts: &formatting::FormatOptions,
+    annotations: &Annotations,
+) -> Result<String> {
+    let docs = match data {
+        serde_json::Value::Array(arr) => arr,
+        _ => return Err(anyhow!("yaml_stream output data must be an array")),
+    };
+    let mut out = String::new();
+    for (i, doc) in docs.iter().enumerate() {
+        if i > 0 {
+            out.push_str("---\n");
+        }
+        out.push_str(&formatting::to_yaml(
+            doc,
+            opts,
+            &annotations.for_document(i),
+        )?);
+    }
+    return Ok(out);
+}
+
+// Serialize the data as a dotenv file. The data must be a flat object of scalar values, where the
+// keys are valid environment variable names. Values that contain characters other than the common
+// safe set are double quoted, with backslashes, quotes, `$` (to avoid variable expansion), and
+// newlines escaped.
+pub fn to_dotenv(data: &serde_json::Value) -> Result<String> {
+    let obj = match data {
+        serde_json::Value::Object(obj) => obj,
+        _ => return Err(anyhow!("dotenv output data must be an object")),
+    };
+    let mut out = String::new();
+    for (k, v) in obj.iter() {
+        let p = format!("/{k}");
+        if !DOTENV_KEY_RE.is_match(k) {
+            return Err(anyhow!(
+                "[{p}] key {k} is not a valid environment variable name"
+            ));
+        }
+        let v = scalar_to_string(v, &p, "dotenv")?;
+        if DOTENV_BARE_VALUE_RE.is_match(&v) {
+            out.push_str(&format!("{k}={v}\n"));
+            continue;
+        }
+        let mut quoted = String::with_capacity(v.len() + 2);
+        for c in v.chars() {
+            match c {
+                '\\' => quoted.push_str("\\\\"),
+                '"' => quoted.push_str("\\\""),
+                '$' => quoted.push_str("\\$"),
+                '\n' => quoted.push_str("\\n"),
+                '\r' => quoted.push_str("\\r"),
+                c => quoted.push(c),
+            }
+        }
+        out.push_str(&format!("{k}=\"{quoted}\"\n"));
+    }
+    return Ok(out);
+}
+
+// Serialize the data as a Java properties file. The data must be an object of scalar values, where
+// nested objects are flattened into dotted keys (e.g., `{"db": {"host": "x"}}` is rendered as
+// `db.host=x`). Keys and values are escaped following `java.util.Properties`, with non ASCII
+// characters escaped as `\uXXXX` so that the file can be read as ISO 8859-1.
+pub fn to_properties(data: &serde_json::Value) -> Result<String> {
+    if !data.is_object() {
+        return Err(anyhow!("properties output data must be an object"));
+    }
+    let mut entries = Vec::new();
+    flatten_properties(data, "", "", &mut entries)?;
+    let mut out = String::new();
+    for (k, v) in entries {
+        out.push_str(&escape_property(&k, true));
+        out.push('=');
+        out.push_str(&escape_property(&v, false));
+        out.push('\n');
+    }
+    return Ok(out);
+}
+
+// Flatten the nested objects into dotted keys, where |prefix| is the dotted key of |data| and |p|
+// is the JSON pointer used in error messages.
+fn flatten_properties(
+    data: &serde_json::Value,
+    prefix: &str,
+    p: &str,
+    entries: &mut Vec<(String, String)>,
+) -> Result<()> {
+    match data {
+        serde_json::Value::Object(obj) => {
+            for (k, v) in obj.iter() {
+                let key = if prefix.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{prefix}.{k}")
+                };
+                flatten_properties(v, &key, &format!("{p}/{k}"), entries)?;
+            }
+        }
+        v => entries.push((String::from(prefix), scalar_to_string(v, p, "properties")?)),
+    }
+    return Ok(());
+}
+
+fn escape_property(s: &str, is_key: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for (i, c) in s.chars().enumerate() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\x0c' => out.push_str("\\f"),
+            // Leading spaces are always escaped so that they are not dropped, and all spaces in keys
+            // are escaped so that they are not treated as the separator.
+            ' ' if is_key || i == 0 => out.push_str("\\ "),
+            '=' | ':' | '#' | '!' if is_key || i == 0 => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if (c as u32) < 0x20 || (c as u32) > 0x7e => {
+                let mut buf = [0u16; 2];
+                for unit in c.encode_utf16(&mut buf) {
+                    out.push_str(&format!("\\u{:04X}", unit));
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// Serialize the data as an INI file. The data must be an object, where scalar values are rendered
+// as global keys at the top of the file, and objects of scalar values are rendered as sections.
+// Values with leading or trailing whitespace, or with characters that start comments or quotes, are
+// double quoted. Values with newlines can not be represented.
+pub fn to_ini(data: &serde_json::Value) -> Result<String> {
+    let obj = match data {
+        serde_json::Value::Object(obj) => obj,
+        _ => return Err(anyhow!("ini output data must be an object")),
+    };
+
+    let mut out = String::new();
+    let mut sections = Vec::new();
+    for (k, v) in obj.iter() {
+        match v {
+            serde_json::Value::Object(section) => sections.push((k, section)),
+            v => write_ini_entry(&mut out, k, v, &format!("/{k}"))?,
+        }
+    }
+    for (name, section) in sections {
+        let p = format!("/{name}");
+        if name.contains(['[', ']', '\n']) {
+            return Err(anyhow!(
+                "[{p}] section name {name} can not be represented in ini"
+            ));
+        }
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&format!("[{name}]\n"));
+        for (k, v) in section.iter() {
+            write_ini_entry(&mut out, k, v, &format!("{p}/{k}"))?;
+        }
+    }
+    return Ok(out);
+}
+
+fn write_ini_entry(out: &mut String, k: &str, v: &serde_json::Value, p: &str) -> Result<()> {
+    if k.is_empty() || k.contains(['=', ';', '#', '[', '\n']) || k.trim() != k {
+        return Err(anyhow!("[{p}] key {k} can not be represented in ini"));
+    }
+    let v = scalar_to_string(v, p, "ini")?;
+    if v.contains(['\n', '\r']) {
+        return Err(anyhow!(
+            "[{p}] values with newlines can not be represented in ini"
+        ));
+    }
+    if v.trim() != v || v.contains([';', '#', '"']) {
+        let quoted = v.replace('\\', "\\\\").replace('"', "\\\"");
+        out.push_str(&format!("{k} = \"{quoted}\"\n"));
+    } else {
+        out.push_str(&format!("{k} = {v}\n"));
+    }
+    return Ok(());
+}
+
+// Convert the scalar value to a string for the flat output formats (dotenv, properties, and ini),
+// where |format| is the name of the output format used in error messages.
+fn scalar_to_string(v: &serde_json::Value, p: &str, format: &str) -> Result<String> {
+    let s = match v {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Bool(b) => b.to_string(),
+        serde_json::Value::Number(n) => n.to_string(),
+        serde_json::Value::Null => {
+            return Err(anyhow!("[{p}] null can not be represented in {format}"))
+        }
+        serde_json::Value::Array(_) => {
+            return Err(anyhow!("[{p}] arrays can not be represented in {format}"))
+        }
+        serde_json::Value::Object(_) => {
+            return Err(anyhow!(
+                "[{p}] nested objects can not be represented in {format}"
+            ))
+        }
+    };
+    return Ok(s);
+}
+
+// The type of the TOML value, for the purposes of checking for mixed arrays. Integers and floats
+// are both treated as numbers.
+fn toml_type(v: &toml::Value) -> &'static str {
+    match v {
+        toml::Value::String(_) => "string",
+        toml::Value::Integer(_) | toml::Value::Float(_) => "number",
+        toml::Value::Boolean(_) => "boolean",
+        toml::Value::Datetime(_) => "datetime",
+        toml::Value::Array(_) => "array",
+        toml::Value::Table(_) => "table",
+    }
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyorder::{self, KeyOrder};
+    use serde_json::json;
+
+    #[test]
+    fn test_serializer_toml_orders_tables() {
+        let mut data = json!({
+            "package": {"name": "senc", "version": "0.1.0"},
+            "workspace": true,
+            "bin": [{"name": "senc", "path": "src/main.rs"}],
+            "features": {"default": ["a", "b"]},
+        });
+        keyorder::apply(&mut data, KeyOrder::Sorted, None).unwrap();
+        let expected = r#"workspace = true
+
+[[bin]]
+name = "senc"
+path = "src/main.rs"
+
+[features]
+default = ["a", "b"]
+
+[package]
+name = "senc"
+version = "0.1.0"
+"#;
+        assert_eq!(
+            to_toml(&data, &Default::default()).expect("error serializing toml"),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_serializer_toml_renders_comments() {
+        let mut data = json!({
+            "dependencies": {
+                "__senc_comment": {"comment": "Keep in sync with the lock file", "value": {"serde": "1.0"}},
+            },
+            "package": {
+                "name": "senc",
+                "version": {"__senc_comment": {"comment": "Bumped by the release workflow", "value": "0.1.0"}},
+            },
+        });
+        let annotations = Annotations::extract(&mut data).unwrap();
+        let expected = r#"# Keep in sync with the lock file
+[dependencies]
+serde = "1.0"
+
+[package]
+name = "senc"
+# Bumped by the release workflow
+version = "0.1.0"
+"#;
+        assert_eq!(
+            to_toml(&data, &annotations).expect("error serializing toml"),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_serializer_toml_rejects_unrepresentable_values() {
+        let err = to_toml(&json!({"a": {"b": [1, null]}}), &Default::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[/a/b/1] null can not be represented in toml"
+        );
+
+        let err = to_toml(&json!({"a": [1, "two"]}), &Default::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[/a] array mixes number and string values, which can not be represented in toml"
+        );
+
+        let err = to_toml(&json!([1, 2]), &Default::default()).unwrap_err();
+        assert_eq!(err.to_string(), "toml output data must be an object");
+    }
+
+    #[test]
+    fn test_serializer_flat_formats_escape_values() {
+        let data = json!({
+            "DATABASE_URL": "postgres://db:5432/app",
+            "GREETING": "hello \"world\" $HOME",
+            "PORT": 8080,
+        });
+        let expected = "DATABASE_URL=postgres://db:5432/app\nGREETING=\"hello \\\"world\\\" \\$HOME\"\nPORT=8080\n";
+        assert_eq!(
+            to_dotenv(&data).expect("error serializing dotenv"),
+            expected
+        );
+
+        let mut data = json!({
+            "app": {"name": "my app", "greeting": "héllo"},
+            "server": {"port": 8080, "path:prefix": " /api"},
+        });
+        keyorder::apply(&mut data, KeyOrder::Sorted, None).unwrap();
+        let expected = "app.greeting=h\\u00E9llo\napp.name=my app\nserver.path\\:prefix=\\ /api\nserver.port=8080\n";
+        assert_eq!(
+            to_properties(&data).expect("error serializing properties"),
+            expected
+        );
+
+        let data = json!({
+            "root": "/srv",
+            "server": {"host": "localhost", "motd": " welcome; enjoy "},
+        });
+        let expected = "root = /srv\n\n[server]\nhost = localhost\nmotd = \" welcome; enjoy \"\n";
+        assert_eq!(to_ini(&data).expect("error serializing ini"), expected);
+    }
+
+    #[test]
+    fn test_serializer_flat_formats_reject_nested_values() {
+        let err = to_dotenv(&json!({"A": {"B": 1}})).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[/A] nested objects can not be represented in dotenv"
+        );
+
+        let err = to_dotenv(&json!({"not-valid": 1})).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[/not-valid] key not-valid is not a valid environment variable name"
+        );
+
+        let err = to_properties(&json!({"a": {"b": [1]}})).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[/a/b] arrays can not be represented in properties"
+        );
+
+        let err = to_ini(&json!({"s": {"t": {"u": 1}}})).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[/s/t] nested objects can not be represented in ini"
+        );
+    }
+
+    #[test]
+    fn test_serializer_yaml_stream_separates_documents() {
+        let mut data = json!([
+            {"kind": "Namespace", "metadata": {"name": "app"}},
+            {"kind": "ConfigMap", "data": {"a": "1"}},
+        ]);
+        keyorder::apply(&mut data, KeyOrder::Sorted, None).unwrap();
+        let expected =
+            "kind: Namespace\nmetadata:\n  name: app\n---\ndata:\n  a: '1'\nkind: ConfigMap\n";
+        assert_eq!(
+            to_yaml_stream(&data, &Default::default(), &Default::default())
+                .expect("error serializing yaml stream"),
+            expected
+        );
+        assert_eq!(
+            to_yaml_stream(&json!([]), &Default::default(), &Default::default()).unwrap(),
+            ""
+        );
+
+        let err = to_yaml_stream(
+            &json!({"kind": "Namespace"}),
+            &Default::default(),
+            &Default::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "yaml_stream output data must be an array");
+    }
+}
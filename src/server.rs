@@ -0,0 +1,427 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections;
+use std::fs;
+use std::io;
+use std::io::{BufRead, Write};
+use std::net;
+use std::path;
+use std::time;
+
+use anyhow::{anyhow, Result};
+use deno_core::url::Url;
+use log::*;
+
+use crate::engine;
+
+// A local HTTP server that serves the rendered outputs on demand. This is meant for local
+// development, where tools fetch the generated config over HTTP instead of reading it from the
+// output directory.
+//
+// Each URL path maps to the path of an output file relative to the output directory. For example,
+// `GET /aws/us-east-1/vpc/main.json` renders the entrypoint that produces
+// `<outdir>/aws/us-east-1/vpc/main.json` and returns its contents. Top-level args can be passed to
+// the entrypoint with the `tla` query parameter, which can be repeated to pass multiple positional
+// args (e.g., `?tla="prod"&tla={"region":"us-east-1"}`).
+//
+// Rendered outputs are cached per entrypoint and top-level args, and the cache entry is reused
+// until any of the files that were read to render the entrypoint change.
+//
+// NOTE
+// Requests are handled sequentially on the calling thread, since the server is only meant to serve
+// a single developer.
+pub struct Server {
+    ctx: engine::Context,
+    runtime: tokio::runtime::Runtime,
+
+    // Maps the URL path of each output (the output path relative to the output directory) to the
+    // entrypoint that renders it.
+    routes: collections::BTreeMap<String, engine::RunRequest>,
+
+    // The cached renders, keyed by the entrypoint path and the top-level args it was rendered with.
+    renders: collections::HashMap<(String, Vec<String>), CachedRender>,
+}
+
+struct CachedRender {
+    // The files that were read to render the entrypoint, with their modification time at the time
+    // of the render.
+    inputs: Vec<(path::PathBuf, Option<time::SystemTime>)>,
+
+    // The rendered outputs, keyed by the URL path.
+    outputs: collections::BTreeMap<String, Vec<u8>>,
+}
+
+impl CachedRender {
+    fn is_stale(&self) -> bool {
+        self.inputs
+            .iter()
+            .any(|(p, modified)| modified_time(p) != *modified)
+    }
+}
+
+// A parsed HTTP request. Only the parts that the server uses are retained.
+struct HttpRequest {
+    method: String,
+    path: String,
+    tla_jsons: Vec<String>,
+}
+
+// An HTTP response to send back to the client.
+struct HttpResponse {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> HttpResponse {
+        HttpResponse {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    fn text(status: u16, body: String) -> HttpResponse {
+        HttpResponse::new(status, "text/plain; charset=utf-8", body.into_bytes())
+    }
+}
+
+impl Server {
+    pub fn new(ctx: engine::Context) -> Result<Server> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(Server {
+            ctx,
+            runtime,
+            routes: collections::BTreeMap::new(),
+            renders: collections::HashMap::new(),
+        })
+    }
+
+    // Listen on the given address and serve the rendered outputs until the process is stopped.
+    pub fn serve(&mut self, addr: &str) -> Result<()> {
+        let listener = net::TcpListener::bind(addr)?;
+        self.refresh_routes()?;
+        info!(
+            "serving {} outputs on http://{}",
+            self.routes.len(),
+            listener.local_addr()?
+        );
+
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("could not accept connection: {e}");
+                    continue;
+                }
+            };
+            if let Err(e) = self.handle_connection(&mut stream) {
+                warn!("could not handle request: {e:#}");
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_connection(&mut self, stream: &mut net::TcpStream) -> Result<()> {
+        let req = {
+            let mut reader = io::BufReader::new(&mut *stream);
+            read_request(&mut reader)
+        };
+        let (resp, is_head) = match req {
+            Ok(req) => {
+                debug!("server received {} {}", req.method, req.path);
+                let is_head = req.method == "HEAD";
+                (self.handle(&req), is_head)
+            }
+            Err(e) => (HttpResponse::text(400, format!("{e:#}\n")), false),
+        };
+        write_response(stream, &resp, is_head)
+    }
+
+    fn handle(&mut self, req: &HttpRequest) -> HttpResponse {
+        if req.method != "GET" && req.method != "HEAD" {
+            return HttpResponse::text(405, format!("method {} is not supported\n", req.method));
+        }
+        for tla in req.tla_jsons.iter() {
+            if let Err(e) = serde_json::from_str::<serde_json::Value>(tla) {
+                return HttpResponse::text(400, format!("tla {tla} is not valid JSON: {e}\n"));
+            }
+        }
+
+        let url_path = req.path.trim_start_matches('/');
+        if url_path.is_empty() {
+            return self.index();
+        }
+
+        // Refresh the routes when the path is unknown, in case a new entrypoint was added or an
+        // existing entrypoint changed where it renders its outputs.
+        if !self.routes.contains_key(url_path) {
+            if let Err(e) = self.refresh_routes() {
+                return HttpResponse::text(500, format!("{e:#}\n"));
+            }
+        }
+        let run_req = match self.routes.get(url_path) {
+            Some(r) => r.clone(),
+            None => {
+                return HttpResponse::text(
+                    404,
+                    format!("{url_path} is not rendered by any entrypoint\n"),
+                )
+            }
+        };
+
+        let tla_jsons = if req.tla_jsons.is_empty() {
+            self.ctx.tla_jsons.clone().unwrap_or_default()
+        } else {
+            req.tla_jsons.clone()
+        };
+        let render = match self.render(&run_req, tla_jsons) {
+            Ok(r) => r,
+            Err(e) => return HttpResponse::text(500, format!("{e:#}\n")),
+        };
+        match render.outputs.get(url_path) {
+            Some(contents) => HttpResponse::new(200, content_type(url_path), contents.clone()),
+            None => HttpResponse::text(
+                404,
+                format!("{url_path} is not rendered by {}\n", run_req.in_file),
+            ),
+        }
+    }
+
+    // List all the known URL paths, along with the entrypoint that renders each one.
+    fn index(&self) -> HttpResponse {
+        let routes: serde_json::Map<String, serde_json::Value> = self
+            .routes
+            .iter()
+            .map(|(p, r)| (format!("/{p}"), serde_json::Value::from(r.in_file.clone())))
+            .collect();
+        let body = serde_json::to_string_pretty(&routes).unwrap();
+        HttpResponse::new(200, "application/json", body.into_bytes())
+    }
+
+    // Render every entrypoint in the project with the default top-level args to discover the
+    // outputs they produce. Entrypoints that have a fresh cached render are not rendered again.
+    fn refresh_routes(&mut self) -> Result<()> {
+        let projectroot = self.ctx.projectroot.clone();
+        let tla_jsons = self.ctx.tla_jsons.clone().unwrap_or_default();
+
+        let mut routes = collections::BTreeMap::new();
+        for r in self.ctx.run_requests_from_path(&projectroot)? {
+            let rendered = self
+                .render(&r, tla_jsons.clone())
+                .map(|render| render.outputs.keys().cloned().collect::<Vec<String>>());
+            match rendered {
+                Ok(url_paths) => {
+                    for p in url_paths {
+                        routes.insert(p, r.clone());
+                    }
+                }
+                // Keep serving the rest of the project when an entrypoint fails. The error is
+                // reported again when one of its outputs is requested.
+                Err(e) => {
+                    warn!("could not render {}: {e:#}", r.in_file);
+                    for (p, existing) in self.routes.iter() {
+                        if existing.in_file == r.in_file {
+                            routes.insert(p.clone(), r.clone());
+                        }
+                    }
+                }
+            }
+        }
+        self.routes = routes;
+        Ok(())
+    }
+
+    // Render the entrypoint with the given top-level args, reusing the cached render if none of its
+    // inputs changed.
+    fn render(
+        &mut self,
+        req: &engine::RunRequest,
+        tla_jsons: Vec<String>,
+    ) -> Result<&CachedRender> {
+        let key = (req.in_file.clone(), tla_jsons);
+        let is_fresh = match self.renders.get(&key) {
+            Some(cached) => !cached.is_stale(),
+            None => false,
+        };
+        if !is_fresh {
+            let render = self.render_uncached(req, &key.1)?;
+            self.renders.insert(key.clone(), render);
+        }
+        Ok(self.renders.get(&key).unwrap())
+    }
+
+    fn render_uncached(
+        &self,
+        req: &engine::RunRequest,
+        tla_jsons: &[String],
+    ) -> Result<CachedRender> {
+        let mut ctx = self.ctx.clone();
+        ctx.write_to_disk = false;
+        ctx.tla_jsons = if tla_jsons.is_empty() {
+            None
+        } else {
+            Some(tla_jsons.to_vec())
+        };
+
+        let run_output = self.runtime.block_on(ctx.run_with_inputs(req))?;
+
+        let mut outputs = collections::BTreeMap::new();
+        for d in run_output.out_data.iter() {
+            let p = d.out_file_path(&ctx.out_dir, &req.out_file_stem)?;
+            let url_path = url_path_for(&p, &ctx.out_dir)?;
+            outputs.insert(url_path, d.contents_bytes());
+        }
+        let inputs = run_output
+            .inputs
+            .into_iter()
+            .map(|p| {
+                let modified = modified_time(&p);
+                (p, modified)
+            })
+            .collect();
+        Ok(CachedRender { inputs, outputs })
+    }
+}
+
+fn modified_time(p: &path::Path) -> Option<time::SystemTime> {
+    fs::metadata(p).and_then(|m| m.modified()).ok()
+}
+
+// Convert the path of an output file to the URL path it is served on, which is the path relative
+// to the output directory with `/` as the separator.
+fn url_path_for(p: &path::Path, out_dir: &path::Path) -> Result<String> {
+    let rel = p.strip_prefix(out_dir)?;
+    let parts: Vec<String> = rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    Ok(parts.join("/"))
+}
+
+// The content type of the output, based on the extension of the URL path.
+fn content_type(url_path: &str) -> &'static str {
+    let ext = path::Path::new(url_path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+    match ext.as_deref() {
+        Some("json") => "application/json",
+        Some("yaml") | Some("yml") => "application/yaml",
+        Some("toml") => "application/toml",
+        Some("xml") | Some("plist") => "application/xml",
+        Some("bin") => "application/octet-stream",
+        Some("tf") => "text/x-hcl; charset=utf-8",
+        _ => "text/plain; charset=utf-8",
+    }
+}
+
+// Read the request line and headers of an HTTP/1.x request. The body, if any, is ignored since the
+// server only supports GET and HEAD.
+fn read_request<R: BufRead>(reader: &mut R) -> Result<HttpRequest> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(m), Some(t)) => (String::from(m), t),
+        _ => {
+            return Err(anyhow!(
+                "malformed request line {:?}",
+                request_line.trim_end()
+            ))
+        }
+    };
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    // The target is only the path and query, so resolve it against a dummy origin to parse it.
+    let url = Url::parse("http://localhost")?.join(target)?;
+    let path = percent_decode(url.path())?;
+    let tla_jsons = url
+        .query_pairs()
+        .filter(|(k, _)| k == "tla")
+        .map(|(_, v)| v.to_string())
+        .collect();
+    Ok(HttpRequest {
+        method,
+        path,
+        tla_jsons,
+    })
+}
+
+fn percent_decode(s: &str) -> Result<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3])?;
+            out.push(u8::from_str_radix(hex, 16)?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(String::from_utf8(out)?)
+}
+
+fn write_response<W: Write>(writer: &mut W, resp: &HttpResponse, is_head: bool) -> Result<()> {
+    let reason = match resp.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        resp.status,
+        reason,
+        resp.content_type,
+        resp.body.len()
+    )?;
+    if !is_head {
+        writer.write_all(&resp.body)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_reads_request_with_tla_query() {
+        let raw = "GET /aws/us%20east/main.json?tla=%22prod%22&tla={\"n\":1} HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let mut reader = io::BufReader::new(raw.as_bytes());
+        let req = read_request(&mut reader).expect("error reading request");
+        assert_eq!(req.method, "GET");
+        assert_eq!(req.path, "/aws/us east/main.json");
+        assert_eq!(req.tla_jsons, vec!["\"prod\"", "{\"n\":1}"]);
+    }
+
+    #[test]
+    fn test_server_content_type() {
+        assert_eq!(content_type("main.json"), "application/json");
+        assert_eq!(content_type("ci/main.yml"), "application/yaml");
+        assert_eq!(content_type("ci/main.YAML"), "application/yaml");
+        assert_eq!(content_type("main.tf"), "text/x-hcl; charset=utf-8");
+        assert_eq!(content_type("pom.xml"), "application/xml");
+        assert_eq!(content_type("Info.plist"), "application/xml");
+        assert_eq!(content_type("magic.bin"), "application/octet-stream");
+        assert_eq!(content_type("Dockerfile"), "text/plain; charset=utf-8");
+    }
+}
@@ -0,0 +1,316 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::cell::{Cell, RefCell};
+use std::collections;
+use std::fs;
+use std::path;
+use std::rc::Rc;
+use std::time;
+
+use anyhow::{anyhow, Result};
+use deno_core::url::Url;
+use deno_core::*;
+use log::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::engine;
+use crate::module_loader;
+
+// The directory in the project root where the project snapshot is stored.
+pub const SNAPSHOT_DIR: &str = ".senc";
+const SNAPSHOT_FILE: &str = "snapshot.bin";
+const MANIFEST_FILE: &str = "snapshot.json";
+
+// The manifest of the project snapshot, which records what went into the snapshot so that the
+// engine can tell if it is still fresh.
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    // The hash of the builtin runtime snapshot that the project snapshot was built on. This
+    // changes whenever senc (and thus the builtins or the ops) changes.
+    builtins_hash: String,
+
+    // The prelude modules, as passed to `senc snapshot build`.
+    preludes: Vec<String>,
+
+    // The hashes of all the files that were loaded into the snapshot, keyed by the absolute path.
+    // The paths are absolute since the modules in the snapshot are identified by their absolute
+    // path, so the snapshot is stale if the project is moved.
+    files: collections::BTreeMap<String, String>,
+}
+
+impl Manifest {
+    fn is_fresh(&self) -> bool {
+        if self.builtins_hash != hash_bytes(engine::RUNTIME_SNAPSHOT) {
+            return false;
+        }
+        self.files.iter().all(|(p, hash)| match fs::read(p) {
+            Ok(contents) => hash_bytes(&contents) == *hash,
+            Err(_) => false,
+        })
+    }
+}
+
+// Build the project snapshot, which is the builtin runtime snapshot with the given prelude modules
+// (and everything they import) loaded and evaluated. Entrypoints that import the prelude modules
+// then reuse the already evaluated modules instead of loading and parsing them again.
+//
+// Preludes can either be paths relative to the project root (e.g., `./lib/helpers.ts`) or npm
+// packages in the node_modules directory (e.g., `lodash-es`). When |preludes| is empty, the
+// preludes of the existing snapshot are used.
+//
+// The snapshot is written to `.senc/snapshot.bin` in the project root, along with the manifest in
+// `.senc/snapshot.json`. Returns the path of the snapshot.
+pub fn build(ctx: &engine::Context, preludes: &[String]) -> Result<path::PathBuf> {
+    let snapshot_dir = ctx.projectroot.join(SNAPSHOT_DIR);
+    let preludes = if preludes.is_empty() {
+        match read_manifest(&snapshot_dir) {
+            Some(m) => m.preludes,
+            None => return Err(anyhow!("no prelude modules to build the snapshot with")),
+        }
+    } else {
+        preludes.to_vec()
+    };
+
+    let loaded_files = Rc::new(RefCell::new(Vec::new()));
+    let snapshot = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(create_snapshot(ctx, &preludes, loaded_files.clone()))?;
+
+    let mut files = collections::BTreeMap::new();
+    for p in loaded_files.take().iter() {
+        files.insert(String::from(p.to_string_lossy()), hash_bytes(&fs::read(p)?));
+    }
+    let manifest = Manifest {
+        builtins_hash: hash_bytes(engine::RUNTIME_SNAPSHOT),
+        preludes,
+        files,
+    };
+
+    fs::create_dir_all(&snapshot_dir)?;
+    let snapshot_path = snapshot_dir.join(SNAPSHOT_FILE);
+    fs::write(&snapshot_path, snapshot)?;
+    fs::write(
+        snapshot_dir.join(MANIFEST_FILE),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+    return Ok(snapshot_path);
+}
+
+// Load the project snapshot if it exists and is fresh. A warning is logged if the snapshot exists
+// but is stale, in which case the builtin runtime snapshot should be used instead.
+//
+// NOTE
+// The snapshot is leaked so that it can be shared across all the runtimes as a static snapshot,
+// without copying it for each runtime. This is only done once per Context.
+pub fn load_if_fresh(projectroot: &path::Path) -> Result<Option<&'static [u8]>> {
+    let snapshot_dir = projectroot.join(SNAPSHOT_DIR);
+    let snapshot_path = snapshot_dir.join(SNAPSHOT_FILE);
+    if !snapshot_path.is_file() {
+        return Ok(None);
+    }
+    match read_manifest(&snapshot_dir) {
+        Some(m) if m.is_fresh() => {}
+        _ => {
+            warn!(
+                "ignoring stale project snapshot {}. Run `senc snapshot build` to rebuild it.",
+                snapshot_path.display()
+            );
+            return Ok(None);
+        }
+    }
+
+    let snapshot = fs::read(&snapshot_path)?;
+    debug!("using project snapshot {}", snapshot_path.display());
+    return Ok(Some(Box::leak(snapshot.into_boxed_slice())));
+}
+
+// The files that were loaded into the project snapshot (the prelude modules and everything they
+// import), as recorded in the manifest.
+pub fn loaded_files(projectroot: &path::Path) -> Vec<path::PathBuf> {
+    match read_manifest(&projectroot.join(SNAPSHOT_DIR)) {
+        Some(m) => m.files.keys().map(path::PathBuf::from).collect(),
+        None => Vec::new(),
+    }
+}
+
+fn read_manifest(snapshot_dir: &path::Path) -> Option<Manifest> {
+    let raw = fs::read_to_string(snapshot_dir.join(MANIFEST_FILE)).ok()?;
+    serde_json::from_str(&raw).ok()
+}
+
+// Create the snapshot by loading the prelude modules on top of the builtin runtime snapshot. The
+// files loaded by the module loader are recorded in |loaded_files|.
+async fn create_snapshot(
+    ctx: &engine::Context,
+    preludes: &[String],
+    loaded_files: Rc<RefCell<Vec<path::PathBuf>>>,
+) -> Result<Vec<u8>> {
+    let modloader = Rc::new(module_loader::TsModuleLoader::new(
+        ctx.projectroot.clone(),
+        ctx.node_modules_dir.clone(),
+        Rc::new(Cell::new(time::Duration::ZERO)),
+        None,
+        loaded_files,
+        None,
+    ));
+
+    // NOTE
+    // The runtimes that start from the snapshot must load the same ops in the same order, so only
+    // the builtin ops are loaded here. The templated builtins (e.g., __dirname) are specific to each
+    // entrypoint, so they are not available to the prelude modules while they are evaluated.
+    let mut js_runtime = JsRuntimeForSnapshot::new(RuntimeOptions {
+        module_loader: Some(modloader.clone()),
+        extensions: vec![engine::new_builtin_ops_extension()],
+        startup_snapshot: Some(Snapshot::Static(engine::RUNTIME_SNAPSHOT)),
+        ..Default::default()
+    });
+
+    let referrer = Url::from_directory_path(&ctx.projectroot)
+        .map_err(|_| anyhow!("invalid project root {}", ctx.projectroot.display()))?;
+    for prelude in preludes {
+        let specifier = modloader.resolve(prelude, referrer.as_str(), ResolutionKind::Import)?;
+        debug!("loading prelude module {specifier} into the snapshot");
+        let mod_id = js_runtime.load_side_module(&specifier, None).await?;
+        let result = js_runtime.mod_evaluate(mod_id);
+        let opts = PollEventLoopOptions {
+            wait_for_inspector: false,
+            pump_v8_message_loop: true,
+        };
+        js_runtime.run_event_loop(opts).await?;
+        result.await?;
+    }
+
+    let snapshot = js_runtime.snapshot();
+    return Ok(snapshot.to_vec());
+}
+
+// NOTE
+// The hashes are stored in the manifest and compared across runs, so they must be stable across
+// builds of senc, which DefaultHasher does not guarantee.
+fn hash_bytes(b: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(b))
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_snapshot_build_requires_preludes() {
+        let projectroot = new_project();
+        let ctx = get_context(&projectroot);
+        let err = build(&ctx, &[]).unwrap_err();
+        fs::remove_dir_all(&projectroot).unwrap();
+        assert_eq!(
+            err.to_string(),
+            "no prelude modules to build the snapshot with"
+        );
+    }
+
+    #[test]
+    fn test_snapshot_is_stale_when_files_change() {
+        let projectroot = new_project();
+        let ctx = get_context(&projectroot);
+        let snapshot_dir = ctx.projectroot.join(SNAPSHOT_DIR);
+
+        let snapshot_path =
+            build(&ctx, &[String::from("./prelude.js")]).expect("error building snapshot");
+        assert_eq!(snapshot_path, snapshot_dir.join(SNAPSHOT_FILE));
+        let manifest = read_manifest(&snapshot_dir).expect("missing manifest");
+        assert!(manifest.is_fresh());
+        // The modules imported by the prelude are tracked along with the prelude.
+        let mut expected_files = vec![
+            ctx.projectroot.join("helpers.js"),
+            ctx.projectroot.join("prelude.js"),
+        ];
+        expected_files.sort();
+        assert_eq!(loaded_files(&ctx.projectroot), expected_files);
+        let fresh = load_if_fresh(&ctx.projectroot).expect("error loading snapshot");
+        assert!(fresh.is_some());
+
+        // Changing a module that the prelude imports makes the snapshot stale.
+        fs::write(
+            ctx.projectroot.join("helpers.js"),
+            "export function shout(msg) { return msg; }\n",
+        )
+        .unwrap();
+        let manifest = read_manifest(&snapshot_dir).expect("missing manifest");
+        let stale = load_if_fresh(&ctx.projectroot).expect("error loading snapshot");
+        let is_fresh = manifest.is_fresh();
+
+        // Rebuilding with the preludes recorded in the manifest makes it fresh again.
+        build(&ctx, &[]).expect("error rebuilding snapshot");
+        let rebuilt = load_if_fresh(&ctx.projectroot).expect("error loading snapshot");
+        let rebuilt_preludes = read_manifest(&snapshot_dir)
+            .expect("missing manifest")
+            .preludes;
+
+        fs::remove_dir_all(&projectroot).unwrap();
+        assert!(!is_fresh);
+        assert!(stale.is_none());
+        assert!(rebuilt.is_some());
+        assert_eq!(rebuilt_preludes, vec![String::from("./prelude.js")]);
+    }
+
+    #[test]
+    fn test_snapshot_is_stale_when_builtins_change() {
+        let projectroot = new_project();
+        let ctx = get_context(&projectroot);
+        let snapshot_dir = ctx.projectroot.join(SNAPSHOT_DIR);
+        build(&ctx, &[String::from("./prelude.js")]).expect("error building snapshot");
+
+        // Simulate a snapshot built with a different version of senc.
+        let mut manifest = read_manifest(&snapshot_dir).expect("missing manifest");
+        manifest.builtins_hash = hash_bytes(b"some other builtins");
+        fs::write(
+            snapshot_dir.join(MANIFEST_FILE),
+            serde_json::to_string_pretty(&manifest).unwrap(),
+        )
+        .unwrap();
+        let is_fresh = manifest.is_fresh();
+        let stale = load_if_fresh(&ctx.projectroot).expect("error loading snapshot");
+
+        fs::remove_dir_all(&projectroot).unwrap();
+        assert!(!is_fresh);
+        assert!(stale.is_none());
+    }
+
+    // Copy the snapshot fixture to a new project root, so that the tests can change the files.
+    fn new_project() -> path::PathBuf {
+        let projectroot = env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&projectroot).unwrap();
+        for f in ["prelude.js", "helpers.js"] {
+            fs::copy(
+                get_fixture_path(&format!("snapshot/{f}")),
+                projectroot.join(f),
+            )
+            .unwrap();
+        }
+        return projectroot;
+    }
+
+    fn get_context(projectroot: &path::Path) -> engine::Context {
+        engine::Context::builder(projectroot)
+            .node_modules_dir(None)
+            .write_to_disk(false)
+            .project_snapshot(false)
+            .build()
+            .expect("error building context")
+    }
+
+    fn get_fixture_path(relpath: &str) -> path::PathBuf {
+        let mut p = path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("tests/fixtures");
+        if relpath != "" {
+            p.push(relpath);
+        }
+        return p;
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::fs;
+use std::path;
+
+use anyhow::{anyhow, Result};
+use log::*;
+use uuid::Uuid;
+
+use crate::files;
+
+// A staging directory that output files are written to before they are moved in to place in the
+// output directory, so that a failure halfway through rendering does not leave the output directory
+// with a mix of new and old files.
+//
+// NOTE
+// The staging directory is created within the output directory so that the files can be moved in
+// to place with a rename, which is atomic when the source and destination are on the same
+// filesystem. The staging directory is removed when the Stage is dropped, whether or not it was
+// committed.
+pub struct Stage {
+    out_dir: path::PathBuf,
+    dir: path::PathBuf,
+    files: Vec<StagedFile>,
+    unchanged: usize,
+}
+
+// A single file in the staging directory, and the path it is moved to on commit.
+struct StagedFile {
+    staged_path: path::PathBuf,
+    out_file_path: path::PathBuf,
+}
+
+impl Stage {
+    // Create a new staging directory for the files in |out_dir|.
+    pub fn new(out_dir: &path::Path) -> Result<Stage> {
+        let dir = out_dir.join(format!(".senc-staging-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir)?;
+        return Ok(Stage {
+            out_dir: out_dir.to_path_buf(),
+            dir,
+            files: Vec::new(),
+            unchanged: 0,
+        });
+    }
+
+    pub fn out_dir(&self) -> &path::Path {
+        &self.out_dir
+    }
+
+    // Write the contents of the file at |out_file_path| to the staging directory, setting the
+    // permission bits to |mode| if set. Files that already exist with the same contents and mode are
+    // not staged, so that they are left untouched on commit and keep their mtime.
+    pub fn add(
+        &mut self,
+        out_file_path: &path::Path,
+        contents: &[u8],
+        mode: Option<u32>,
+    ) -> Result<()> {
+        files::assert_file_path_in_projectroot(out_file_path, &self.out_dir)?;
+        if is_unchanged(out_file_path, contents, mode)? {
+            self.unchanged += 1;
+            return Ok(());
+        }
+
+        // NOTE
+        // The staged files are named by their index instead of mirroring the output directory, since
+        // they are only ever referenced through the list of staged files.
+        let staged_path = self.dir.join(self.files.len().to_string());
+        fs::write(&staged_path, contents)?;
+        if let Some(mode) = mode {
+            files::set_file_mode(&staged_path, mode)?;
+        }
+        self.files.push(StagedFile {
+            staged_path,
+            out_file_path: out_file_path.to_path_buf(),
+        });
+        return Ok(());
+    }
+
+    // Move all the staged files in to place. Each file is replaced atomically, so readers of the
+    // output directory see either the old or the new contents of each file, but never a partially
+    // written file.
+    pub fn commit(self) -> Result<()> {
+        for f in self.files.iter() {
+            let out_file_dir = f.out_file_path.parent().unwrap();
+            fs::create_dir_all(out_file_dir)?;
+            fs::rename(&f.staged_path, &f.out_file_path).map_err(|e| {
+                anyhow!(
+                    "could not move {} in to place: {e}",
+                    f.out_file_path.display()
+                )
+            })?;
+        }
+        debug!(
+            "wrote {} files to {}, skipped {} unchanged files",
+            self.files.len(),
+            self.out_dir.display(),
+            self.unchanged
+        );
+        return Ok(());
+    }
+}
+
+impl Drop for Stage {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            warn!(
+                "could not remove staging directory {}: {e}",
+                self.dir.display()
+            );
+        }
+    }
+}
+
+// Whether the file at |p| already exists with the given contents and mode.
+fn is_unchanged(p: &path::Path, contents: &[u8], mode: Option<u32>) -> Result<bool> {
+    let existing = match fs::read(p) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    if existing != contents {
+        return Ok(false);
+    }
+    return match mode {
+        Some(mode) => files::file_mode_is(p, mode),
+        None => Ok(true),
+    };
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::thread;
+    use std::time;
+
+    #[test]
+    fn test_staging_commits_changed_files_only() {
+        let out_dir = env::temp_dir().join(Uuid::new_v4().to_string());
+        let unchanged = out_dir.join("unchanged.json");
+        let changed = out_dir.join("ci/changed.json");
+        fs::create_dir_all(out_dir.join("ci")).unwrap();
+        fs::write(&unchanged, "{}").unwrap();
+        fs::write(&changed, "{}").unwrap();
+        let unchanged_mtime = fs::metadata(&unchanged).unwrap().modified().unwrap();
+        thread::sleep(time::Duration::from_millis(10));
+
+        let mut stage = Stage::new(&out_dir).unwrap();
+        stage.add(&unchanged, b"{}", None).unwrap();
+        stage.add(&changed, b"{\"a\": 1}", None).unwrap();
+        stage
+            .add(&out_dir.join("new/new.json"), b"[]", None)
+            .unwrap();
+
+        // Nothing is moved in to place until the stage is committed.
+        assert_eq!(fs::read_to_string(&changed).unwrap(), "{}");
+        assert!(!out_dir.join("new").exists());
+
+        stage.commit().unwrap();
+        assert_eq!(fs::read_to_string(&changed).unwrap(), "{\"a\": 1}");
+        assert_eq!(
+            fs::read_to_string(out_dir.join("new/new.json")).unwrap(),
+            "[]"
+        );
+        assert_eq!(
+            fs::metadata(&unchanged).unwrap().modified().unwrap(),
+            unchanged_mtime
+        );
+
+        // The staging directory is removed, along with the files of a stage that is not committed.
+        let mut stage = Stage::new(&out_dir).unwrap();
+        stage.add(&changed, b"{\"a\": 2}", None).unwrap();
+        drop(stage);
+        assert_eq!(fs::read_to_string(&changed).unwrap(), "{\"a\": 1}");
+        let mut entries: Vec<String> = fs::read_dir(&out_dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        entries.sort();
+        assert_eq!(entries, vec!["ci", "new", "unchanged.json"]);
+
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::env;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path;
+
+use anyhow::{anyhow, Result};
+use log::*;
+
+use crate::bundle;
+
+// The magic bytes at the end of a standalone executable, marking that a bundle is embedded in it.
+const TRAILER_MAGIC: &[u8; 8] = b"SENCBNDL";
+
+// The size of the trailer, which is the length of the embedded bundle (as a little endian u64)
+// followed by the magic bytes.
+const TRAILER_LEN: u64 = 16;
+
+// Write a standalone executable to |out| that renders the given bundle. The executable is a copy of
+// |exe| (normally the running senc binary) with the bundle appended to the end, followed by a
+// trailer that records the length of the bundle:
+//
+//   [exe][bundle module][bundle length (u64 LE)][SENCBNDL]
+//
+// If |exe| is itself a standalone executable, its embedded bundle is replaced.
+pub fn write_executable(exe: &path::Path, b: &bundle::Bundle, out: &path::Path) -> Result<()> {
+    let mut exe_file = fs::File::open(exe)?;
+    let exe_len = match read_trailer(&mut exe_file)? {
+        Some((bundle_offset, _)) => bundle_offset,
+        None => exe_file.metadata()?.len(),
+    };
+    let mut exe_bytes = Vec::with_capacity(exe_len as usize);
+    exe_file.seek(SeekFrom::Start(0))?;
+    exe_file.take(exe_len).read_to_end(&mut exe_bytes)?;
+
+    let payload = b.to_module()?;
+    let mut out_file = fs::File::create(out)?;
+    out_file.write_all(&exe_bytes)?;
+    out_file.write_all(payload.as_bytes())?;
+    out_file.write_all(&(payload.len() as u64).to_le_bytes())?;
+    out_file.write_all(TRAILER_MAGIC)?;
+    out_file.flush()?;
+
+    // Keep the permissions of the original executable, so that the output is also executable.
+    fs::set_permissions(out, fs::metadata(exe)?.permissions())?;
+    return Ok(());
+}
+
+// Load the bundle embedded in the running executable, if any.
+//
+// NOTE
+// This runs before the args of every senc command are parsed, so failing to read the running
+// executable (e.g., because it was replaced while running, or in a sandbox) is treated as the
+// executable not having an embedded bundle. Only an embedded bundle that can not be parsed is an
+// error.
+pub fn current_exe_bundle() -> Result<Option<bundle::Bundle>> {
+    let payload = match env::current_exe()
+        .map_err(anyhow::Error::from)
+        .and_then(|exe| read_embedded_payload(&exe))
+    {
+        Ok(Some(payload)) => payload,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            debug!(
+                "could not read the running executable, assuming it has no embedded bundle: {e}"
+            );
+            return Ok(None);
+        }
+    };
+    return parse_embedded_payload(payload).map(Some);
+}
+
+// Load the bundle embedded in the given executable, if any.
+pub fn read_embedded_bundle(exe: &path::Path) -> Result<Option<bundle::Bundle>> {
+    match read_embedded_payload(exe)? {
+        Some(payload) => parse_embedded_payload(payload).map(Some),
+        None => Ok(None),
+    }
+}
+
+// Read the raw bytes of the bundle embedded in the given executable, if it has a trailer.
+fn read_embedded_payload(exe: &path::Path) -> Result<Option<Vec<u8>>> {
+    let mut f = fs::File::open(exe)?;
+    let (bundle_offset, bundle_len) = match read_trailer(&mut f)? {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+
+    let mut payload = Vec::with_capacity(bundle_len as usize);
+    f.seek(SeekFrom::Start(bundle_offset))?;
+    f.take(bundle_len).read_to_end(&mut payload)?;
+    return Ok(Some(payload));
+}
+
+fn parse_embedded_payload(payload: Vec<u8>) -> Result<bundle::Bundle> {
+    let parsed = String::from_utf8(payload)
+        .map_err(anyhow::Error::from)
+        .and_then(|payload| bundle::Bundle::parse(&payload));
+    return parsed
+        .map_err(|e| anyhow!("could not load the bundle embedded in the executable: {e}"));
+}
+
+// Read the trailer at the end of the file, returning the offset and length of the embedded bundle
+// if the file has one.
+fn read_trailer(f: &mut fs::File) -> Result<Option<(u64, u64)>> {
+    let file_len = f.metadata()?.len();
+    if file_len < TRAILER_LEN {
+        return Ok(None);
+    }
+
+    let mut trailer = [0u8; TRAILER_LEN as usize];
+    f.seek(SeekFrom::Start(file_len - TRAILER_LEN))?;
+    f.read_exact(&mut trailer)?;
+    if &trailer[8..] != TRAILER_MAGIC {
+        return Ok(None);
+    }
+
+    let bundle_len = u64::from_le_bytes(trailer[..8].try_into()?);
+    if bundle_len > file_len - TRAILER_LEN {
+        return Err(anyhow!("executable has a corrupt bundle trailer"));
+    }
+    return Ok(Some((file_len - TRAILER_LEN - bundle_len, bundle_len)));
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standalone_executable_roundtrip() {
+        let temp_dir = env::temp_dir();
+        let exe = temp_dir.join(format!("{}.exe", uuid::Uuid::new_v4()));
+        let out = temp_dir.join(format!("{}.exe", uuid::Uuid::new_v4()));
+        let out2 = temp_dir.join(format!("{}.exe", uuid::Uuid::new_v4()));
+        let exe_bytes = b"\x7fELF not really an executable";
+        fs::write(&exe, exe_bytes).expect("could not write fake executable");
+
+        let do_steps = || -> Result<()> {
+            assert!(read_embedded_bundle(&exe)?.is_none());
+
+            let mut b = bundle::Bundle {
+                entrypoint: String::from("main.sen.js"),
+                modules: Default::default(),
+                resolutions: Default::default(),
+                files: Default::default(),
+            };
+            write_executable(&exe, &b, &out)?;
+            let embedded = read_embedded_bundle(&out)?.expect("missing embedded bundle");
+            assert_eq!(embedded.entrypoint, "main.sen.js");
+            assert!(fs::read(&out)?.starts_with(exe_bytes));
+
+            // Compiling from a standalone executable replaces the embedded bundle.
+            b.entrypoint = String::from("other.sen.js");
+            write_executable(&out, &b, &out2)?;
+            let embedded = read_embedded_bundle(&out2)?.expect("missing embedded bundle");
+            assert_eq!(embedded.entrypoint, "other.sen.js");
+            let payload_len = b.to_module()?.len() as u64;
+            assert_eq!(
+                fs::metadata(&out2)?.len(),
+                exe_bytes.len() as u64 + payload_len + TRAILER_LEN
+            );
+            Ok(())
+        };
+        let step_result = do_steps();
+        // Remove the temp files before checking result.
+        for p in [&exe, &out, &out2] {
+            let _ = fs::remove_file(p);
+        }
+        step_result.expect("error running standalone executable steps");
+    }
+
+    #[test]
+    fn test_standalone_rejects_corrupt_embedded_bundle() {
+        let exe = env::temp_dir().join(format!("{}.exe", uuid::Uuid::new_v4()));
+        let payload = b"not a bundle";
+        let mut contents = b"\x7fELF not really an executable".to_vec();
+        contents.extend_from_slice(payload);
+        contents.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        contents.extend_from_slice(TRAILER_MAGIC);
+        fs::write(&exe, contents).expect("could not write fake executable");
+
+        let result = read_embedded_bundle(&exe);
+        let _ = fs::remove_file(&exe);
+        let err = result.expect_err("corrupt bundle was loaded");
+        assert!(err
+            .to_string()
+            .starts_with("could not load the bundle embedded in the executable: "));
+
+        // Executables that can not be read have no embedded bundle.
+        assert!(read_embedded_payload(&exe).is_err());
+    }
+}
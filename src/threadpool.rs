@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use std::collections::HashSet;
+use std::mem;
 use std::sync::{atomic, mpsc, Arc, Mutex};
 use std::thread;
 use std::time;
@@ -11,14 +12,17 @@ use log::*;
 use uuid::Uuid;
 
 use crate::engine;
+use crate::timings;
 
 // A thread pool with a set number of threads to run tasks on.
 pub struct ThreadPool {
+    context: Arc<engine::Context>,
     workers: Vec<Worker>,
     tasks: HashSet<Uuid>,
     task_sender: Option<mpsc::Sender<Task>>,
     task_receiver: Arc<Mutex<mpsc::Receiver<Task>>>,
-    result_receiver: mpsc::Receiver<Uuid>,
+    result_receiver: mpsc::Receiver<TaskResult>,
+    outputs: Vec<TaskOutput>,
     has_quit: Arc<atomic::AtomicBool>,
 }
 
@@ -50,30 +54,40 @@ impl ThreadPool {
         let mut workers = Vec::with_capacity(thread_count);
         for _ in 0..thread_count {
             let result_sender_copy = result_sender.clone();
-            workers.push(Worker::new(
-                context.clone(),
-                task_mreceiver.clone(),
-                result_sender_copy,
-            ));
+            workers.push(Worker::new(task_mreceiver.clone(), result_sender_copy));
         }
 
         ThreadPool {
+            context: Arc::new(context),
             workers,
             tasks: HashSet::new(),
             task_sender: Some(task_sender),
             task_receiver: task_mreceiver.clone(),
             result_receiver,
+            outputs: Vec::new(),
             has_quit,
         }
     }
 
     // Send a single run request to the thread pool.
     pub fn run(&mut self, req: engine::RunRequest) -> Result<()> {
+        let ctx = self.context.clone();
+        self.run_with_context(ctx, req)
+    }
+
+    // Send a single run request to the thread pool, overriding the context the pool was created
+    // with (e.g., to use different top-level args).
+    pub fn run_with_context(
+        &mut self,
+        ctx: Arc<engine::Context>,
+        req: engine::RunRequest,
+    ) -> Result<()> {
         let task_id = Uuid::new_v4();
-        self.task_sender
-            .as_ref()
-            .unwrap()
-            .send(Task { id: task_id, req })?;
+        self.task_sender.as_ref().unwrap().send(Task {
+            id: task_id,
+            ctx,
+            req,
+        })?;
         self.tasks.insert(task_id);
         Ok(())
     }
@@ -86,8 +100,9 @@ impl ThreadPool {
         let timeout = time::Duration::from_millis(500);
         while !self.has_quit.load(atomic::Ordering::SeqCst) && !self.tasks.is_empty() {
             match self.result_receiver.recv_timeout(timeout) {
-                Ok(task_id) => {
-                    self.tasks.remove(&task_id);
+                Ok(result) => {
+                    self.tasks.remove(&result.id);
+                    self.outputs.push(result.output);
                 }
                 Err(_e) => {
                     continue;
@@ -100,6 +115,70 @@ impl ThreadPool {
             return Err(anyhow!("tasks are still remaining"));
         }
     }
+
+    // Write the outputs of all the tasks that have finished running to disk, if their contexts are
+    // configured to write to disk. The tasks only render the outputs, so that all the output paths
+    // can be checked for collisions before any of the files are written. Tasks that failed are
+    // skipped, and tasks whose outputs could not be written are marked as failed.
+    pub fn write_outputs(&mut self) -> Result<()> {
+        // NOTE
+        // The tasks finish in a nondeterministic order, so the outputs are sorted to report the
+        // collisions consistently.
+        let mut to_write: Vec<(&engine::Context, &engine::RunRequest, &[engine::OutData])> = self
+            .outputs
+            .iter()
+            .filter(|o| o.ctx.write_to_disk)
+            .filter_map(|o| match &o.result {
+                Ok(out_data) => Some((o.ctx.as_ref(), &o.request, out_data.as_slice())),
+                Err(_) => None,
+            })
+            .collect();
+        to_write.sort_by(|a, b| a.1.in_file.cmp(&b.1.in_file));
+
+        // NOTE
+        // In atomic mode, nothing is written unless all the tasks succeeded, so that the output
+        // directory is never left with a mix of new and old files.
+        if self.outputs.iter().any(|o| o.ctx.atomic) {
+            let mut failed: Vec<&str> = self
+                .outputs
+                .iter()
+                .filter(|o| o.result.is_err())
+                .map(|o| o.request.in_file.as_str())
+                .collect();
+            if !failed.is_empty() {
+                failed.sort();
+                return Err(anyhow!(
+                    "not writing any outputs since the following entrypoints failed:\n{}",
+                    failed.join("\n")
+                ));
+            }
+            let start = time::Instant::now();
+            engine::write_out_data_atomically(&to_write)?;
+            debug!("committed the staged outputs in {:?}", start.elapsed());
+            return Ok(());
+        }
+
+        engine::check_out_path_collisions(&to_write)?;
+
+        for o in self.outputs.iter_mut() {
+            let write_result = match &o.result {
+                Ok(out_data) => {
+                    engine::write_out_data(&o.ctx, &o.request, out_data, &mut o.timings)
+                }
+                Err(_) => continue,
+            };
+            if let Err(e) = write_result {
+                error!("could not write outputs of `{}`: {e}", o.request.in_file);
+                o.result = Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    // Take the outputs of all the tasks that have finished running since the last call.
+    pub fn take_outputs(&mut self) -> Vec<TaskOutput> {
+        mem::take(&mut self.outputs)
+    }
 }
 
 impl Drop for ThreadPool {
@@ -123,9 +202,24 @@ impl Drop for ThreadPool {
 // A single task for the worker.
 struct Task {
     id: Uuid,
+    ctx: Arc<engine::Context>,
     req: engine::RunRequest,
 }
 
+// The output of running a single task.
+pub struct TaskOutput {
+    ctx: Arc<engine::Context>,
+    pub request: engine::RunRequest,
+    pub timings: timings::Timings,
+    pub result: Result<Vec<engine::OutData>>,
+}
+
+// The result of a single task, sent back from the worker when the task finishes.
+struct TaskResult {
+    id: Uuid,
+    output: TaskOutput,
+}
+
 // A single thread pool worker that accepts files for interpretation and runs them through Deno to
 // generate the corresponding IaC.
 //
@@ -140,9 +234,8 @@ impl Worker {
     // On construction, spawn the thread for the worker which watches for incoming tasks on the
     // task_receiver channel.
     fn new(
-        context: engine::Context,
         task_receiver: Arc<Mutex<mpsc::Receiver<Task>>>,
-        result_sender: mpsc::Sender<Uuid>,
+        result_sender: mpsc::Sender<TaskResult>,
     ) -> Worker {
         let id = Uuid::new_v4();
         let thread = thread::spawn(move || {
@@ -161,18 +254,32 @@ impl Worker {
                         trace!("[{id}] Worker got request to run {}.", task.req);
                         debug!("executing {}", task.req.in_file);
 
-                        if let Err(e) =
-                            runtime.block_on(engine::run_js_and_write(&context, &task.req))
-                        {
-                            error!(
+                        let mut task_timings = timings::Timings::new(&task.req.in_file);
+                        let run_result = runtime.block_on(engine::run_js_timed(
+                            &task.ctx,
+                            &task.req,
+                            &mut task_timings,
+                        ));
+                        match &run_result {
+                            Err(e) => error!(
                                 "could not execute javascript file `{}`: {e}",
                                 task.req.in_file
-                            );
-                        } else {
-                            trace!("[{id}] successfully executed `{}`.", task.req.in_file);
+                            ),
+                            Ok(_) => {
+                                trace!("[{id}] successfully executed `{}`.", task.req.in_file)
+                            }
                         }
 
-                        if let Err(e) = result_sender.send(task.id) {
+                        let result = TaskResult {
+                            id: task.id,
+                            output: TaskOutput {
+                                ctx: task.ctx,
+                                request: task.req,
+                                timings: task_timings,
+                                result: run_result,
+                            },
+                        };
+                        if let Err(e) = result_sender.send(result) {
                             error!("could not mark task as done: {e}");
                         }
                     }
@@ -0,0 +1,154 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::time;
+
+// The phases of running a single entrypoint that are tracked in the timing breakdown.
+#[derive(Clone, Copy)]
+pub enum Phase {
+    RuntimeCreation,
+    ModuleLoad,
+    Transpile,
+    MainCall,
+    Serialize,
+    Validate,
+    Write,
+}
+
+const PHASES: [Phase; 7] = [
+    Phase::RuntimeCreation,
+    Phase::ModuleLoad,
+    Phase::Transpile,
+    Phase::MainCall,
+    Phase::Serialize,
+    Phase::Validate,
+    Phase::Write,
+];
+
+impl Phase {
+    fn header(&self) -> &'static str {
+        match self {
+            Phase::RuntimeCreation => "runtime",
+            Phase::ModuleLoad => "load",
+            Phase::Transpile => "transpile",
+            Phase::MainCall => "main",
+            Phase::Serialize => "serialize",
+            Phase::Validate => "validate",
+            Phase::Write => "write",
+        }
+    }
+}
+
+// The timing breakdown for running a single entrypoint.
+#[derive(Clone)]
+pub struct Timings {
+    pub in_file: String,
+    durations: [time::Duration; 7],
+}
+
+impl Timings {
+    pub fn new(in_file: &str) -> Timings {
+        Timings {
+            in_file: String::from(in_file),
+            durations: [time::Duration::ZERO; 7],
+        }
+    }
+
+    // Add the given duration to the running total for the phase. Phases can be recorded multiple
+    // times (e.g., serialize is recorded once per OutData).
+    pub fn add(&mut self, phase: Phase, d: time::Duration) {
+        self.durations[phase as usize] += d;
+    }
+
+    // Record the time elapsed since the given instant against the phase.
+    pub fn add_since(&mut self, phase: Phase, start: time::Instant) {
+        self.add(phase, start.elapsed());
+    }
+
+    pub fn get(&self, phase: Phase) -> time::Duration {
+        self.durations[phase as usize]
+    }
+
+    pub fn total(&self) -> time::Duration {
+        self.durations.iter().sum()
+    }
+}
+
+// Render the timing breakdown of all the entrypoints as a table, sorted by the slowest entrypoint
+// first. All durations are reported in milliseconds.
+pub fn render_summary(timings: &[Timings]) -> String {
+    let mut sorted: Vec<&Timings> = timings.iter().collect();
+    sorted.sort_by(|a, b| b.total().cmp(&a.total()));
+
+    let file_width = sorted
+        .iter()
+        .map(|t| t.in_file.len())
+        .chain(std::iter::once("entrypoint".len()))
+        .max()
+        .unwrap();
+
+    let mut out = String::new();
+    out.push_str(&format!("{:<file_width$}", "entrypoint"));
+    for p in PHASES {
+        out.push_str(&format!("  {:>10}", p.header()));
+    }
+    out.push_str(&format!("  {:>10}\n", "total"));
+
+    for t in sorted {
+        out.push_str(&format!("{:<file_width$}", t.in_file));
+        for p in PHASES {
+            out.push_str(&format!("  {:>10}", format_ms(t.get(p))));
+        }
+        out.push_str(&format!("  {:>10}\n", format_ms(t.total())));
+    }
+    return out;
+}
+
+fn format_ms(d: time::Duration) -> String {
+    format!("{:.2}", d.as_secs_f64() * 1000.0)
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings_accumulates_phases() {
+        let mut t = Timings::new("main.sen.ts");
+        t.add(Phase::Serialize, time::Duration::from_millis(2));
+        t.add(Phase::Serialize, time::Duration::from_millis(3));
+        t.add(Phase::Write, time::Duration::from_millis(1));
+        assert_eq!(t.get(Phase::Serialize), time::Duration::from_millis(5));
+        assert_eq!(t.get(Phase::MainCall), time::Duration::ZERO);
+        assert_eq!(t.total(), time::Duration::from_millis(6));
+    }
+
+    #[test]
+    fn test_timings_render_summary() {
+        let mut fast = Timings::new("fast.sen.ts");
+        fast.add(Phase::MainCall, time::Duration::from_micros(1500));
+        let mut slow = Timings::new("nested/slow.sen.ts");
+        slow.add(Phase::RuntimeCreation, time::Duration::from_millis(10));
+        slow.add(Phase::Write, time::Duration::from_micros(250));
+
+        let expected = "\
+entrypoint             runtime        load   transpile        main   serialize    validate       write       total
+nested/slow.sen.ts       10.00        0.00        0.00        0.00        0.00        0.00        0.25       10.25
+fast.sen.ts               0.00        0.00        0.00        1.50        0.00        0.00        0.00        1.50
+";
+        assert_eq!(render_summary(&[fast, slow]), expected);
+    }
+
+    #[test]
+    fn test_timings_render_summary_pads_to_header() {
+        let mut t = Timings::new("a.js");
+        t.add(Phase::Validate, time::Duration::from_millis(1));
+        let summary = render_summary(&[t]);
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("a.js      "));
+        assert_eq!(lines[0].len(), lines[1].len());
+    }
+}
@@ -12,6 +12,40 @@ pub trait DataSchema {
     fn validate(&self, data: &serde_json::Value) -> Result<()>;
 }
 
+// A single failure from validating data against a schema.
+#[derive(Debug)]
+pub struct ValidationFailure {
+    // The JSON pointer to the location in the data that failed validation (e.g., `/foo/0/bar`).
+    // This is empty for the root of the data.
+    pub instance_path: String,
+    pub message: String,
+}
+
+// The error returned when data fails to validate against a schema. This is returned wrapped in an
+// anyhow::Error, and can be recovered with downcast_ref to report the individual failures (e.g.,
+// as editor diagnostics).
+#[derive(Debug)]
+pub struct ValidationError {
+    pub failures: Vec<ValidationFailure>,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut err_strs = Vec::new();
+        for failure in self.failures.iter() {
+            let err_str = if failure.instance_path == "" {
+                format!("[.] {}", failure.message).to_string()
+            } else {
+                format!("[{}] {}\n", failure.instance_path, failure.message).to_string()
+            };
+            err_strs.push(err_str);
+        }
+        write!(f, "{}", err_strs.join("\n"))
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
 pub struct DataJSONSchema {
     schema: JSONSchema,
 }
@@ -20,17 +54,13 @@ impl DataSchema for DataJSONSchema {
     fn validate(&self, data: &serde_json::Value) -> Result<()> {
         match self.schema.validate(data) {
             Err(errs) => {
-                let mut err_strs = Vec::new();
-                for err in errs {
-                    let instance_path_str = err.instance_path.to_string();
-                    let err_str = if instance_path_str == "" {
-                        format!("[.] {}", err).to_string()
-                    } else {
-                        format!("[{}] {}\n", instance_path_str, err).to_string()
-                    };
-                    err_strs.push(err_str);
-                }
-                Err(anyhow!(err_strs.join("\n")))
+                let failures = errs
+                    .map(|err| ValidationFailure {
+                        instance_path: err.instance_path.to_string(),
+                        message: err.to_string(),
+                    })
+                    .collect();
+                Err(ValidationError { failures }.into())
             }
             Ok(result) => Ok(result),
         }
@@ -41,10 +71,17 @@ pub fn new_from_path(schema_path: &path::Path) -> Result<impl DataSchema> {
     let schema_file = fs::File::open(schema_path)?;
     let schema_reader = io::BufReader::new(schema_file);
     let raw_schema: serde_json::Value = serde_json::from_reader(schema_reader)?;
+    return new_from_value(&raw_schema, schema_path);
+}
 
+// Compile the already loaded schema. The |schema_path| is only used for error messages.
+pub fn new_from_value(
+    raw_schema: &serde_json::Value,
+    schema_path: &path::Path,
+) -> Result<impl DataSchema> {
     let maybe_jsonschema: Result<JSONSchema, _> = JSONSchema::options()
         .with_draft(Draft::Draft202012)
-        .compile(&raw_schema);
+        .compile(raw_schema);
     match maybe_jsonschema {
         Ok(jsonschema) => {
             return Ok(DataJSONSchema { schema: jsonschema });
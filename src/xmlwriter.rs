@@ -0,0 +1,363 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::yamlanchors;
+
+lazy_static! {
+    static ref NAME_RE: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_.:-]*$").unwrap();
+}
+
+const XML_DECLARATION: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n";
+const PLIST_DOCTYPE: &str = "<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n";
+
+// The prefix of object keys that are rendered as attributes, and the key of the text content.
+const ATTR_PREFIX: char = '@';
+const TEXT_KEY: &str = "#text";
+
+// Serialize the data as XML. The data must be an object with a single key, which is the root
+// element. The value of each element is converted following this convention:
+// - Scalars are rendered as the text content of the element, and null as an empty element.
+// - Objects are rendered as the children of the element, where:
+//   - Keys that start with `@` are rendered as attributes (e.g., `{"@id": "a"}` is `id="a"`). This
+//     includes namespace declarations (e.g., `@xmlns` and `@xmlns:xsi`).
+//   - The `#text` key is rendered as the text content of the element.
+//   - All other keys are rendered as child elements, where arrays are rendered as repeated child
+//     elements with the same name.
+//
+// Elements are indented with 2 spaces, and elements that only contain text are rendered on a
+// single line.
+pub fn to_xml(data: &serde_json::Value) -> Result<String> {
+    let (name, root) = match data {
+        serde_json::Value::Object(obj) if obj.len() == 1 => obj.iter().next().unwrap(),
+        _ => {
+            return Err(anyhow!(
+                "xml output data must be an object with a single key for the root element"
+            ))
+        }
+    };
+    if root.is_array() {
+        return Err(anyhow!("[/{name}] root element can not be an array"));
+    }
+
+    let mut out = String::from(XML_DECLARATION);
+    write_element(&mut out, name, root, 0, &format!("/{name}"))?;
+    return Ok(out);
+}
+
+fn write_element(
+    out: &mut String,
+    name: &str,
+    v: &serde_json::Value,
+    depth: usize,
+    p: &str,
+) -> Result<()> {
+    if !NAME_RE.is_match(name) {
+        return Err(anyhow!("[{p}] {name} is not a valid xml element name"));
+    }
+    let indent = "  ".repeat(depth);
+    out.push_str(&indent);
+    out.push('<');
+    out.push_str(name);
+
+    let obj = match v {
+        serde_json::Value::Object(obj) => obj,
+        serde_json::Value::Array(_) => {
+            return Err(anyhow!("[{p}] nested arrays can not be represented in xml"))
+        }
+        serde_json::Value::Null => {
+            out.push_str("/>\n");
+            return Ok(());
+        }
+        v => {
+            out.push('>');
+            out.push_str(&escape(&scalar_text(v), false, p)?);
+            out.push_str(&format!("</{name}>\n"));
+            return Ok(());
+        }
+    };
+
+    let mut text = None;
+    let mut children = Vec::new();
+    for (k, item) in obj.iter() {
+        let item_p = format!("{p}/{k}");
+        if let Some(attr) = k.strip_prefix(ATTR_PREFIX) {
+            if !NAME_RE.is_match(attr) {
+                return Err(anyhow!(
+                    "[{item_p}] {attr} is not a valid xml attribute name"
+                ));
+            }
+            if item.is_array() || item.is_object() {
+                return Err(anyhow!("[{item_p}] attribute values must be scalars"));
+            }
+            let value = escape(&scalar_text(item), true, &item_p)?;
+            out.push_str(&format!(" {attr}=\"{value}\""));
+        } else if k == TEXT_KEY {
+            if item.is_array() || item.is_object() {
+                return Err(anyhow!("[{item_p}] text content must be a scalar"));
+            }
+            text = Some(escape(&scalar_text(item), false, &item_p)?);
+        } else {
+            children.push((k, item, item_p));
+        }
+    }
+
+    if children.is_empty() {
+        match text {
+            Some(t) => out.push_str(&format!(">{t}</{name}>\n")),
+            None => out.push_str("/>\n"),
+        }
+        return Ok(());
+    }
+
+    out.push_str(">\n");
+    if let Some(t) = text {
+        out.push_str(&format!("{indent}  {t}\n"));
+    }
+    for (k, item, item_p) in children {
+        match item {
+            serde_json::Value::Array(arr) => {
+                for (i, el) in arr.iter().enumerate() {
+                    write_element(out, k, el, depth + 1, &format!("{item_p}/{i}"))?;
+                }
+            }
+            item => write_element(out, k, item, depth + 1, &item_p)?,
+        }
+    }
+    out.push_str(&format!("{indent}</{name}>\n"));
+    return Ok(());
+}
+
+// Serialize the data as an Apple property list (plist) in the XML format. Objects are rendered as
+// dicts, and integers and floats as integer and real respectively. Since plists have no null, null
+// values can not be represented. Elements are indented with tabs, as in the plists written by
+// macOS.
+pub fn to_plist(data: &serde_json::Value) -> Result<String> {
+    let mut out = String::from(XML_DECLARATION);
+    out.push_str(PLIST_DOCTYPE);
+    out.push_str("<plist version=\"1.0\">\n");
+    write_plist_value(&mut out, data, 0, "")?;
+    out.push_str("</plist>\n");
+    return Ok(out);
+}
+
+fn write_plist_value(out: &mut String, v: &serde_json::Value, depth: usize, p: &str) -> Result<()> {
+    let indent = "\t".repeat(depth);
+    match v {
+        serde_json::Value::Null => {
+            let p = yamlanchors::display_pointer(p);
+            return Err(anyhow!("[{p}] null can not be represented in plist"));
+        }
+        serde_json::Value::Bool(b) => out.push_str(&format!("{indent}<{b}/>\n")),
+        serde_json::Value::Number(n) => {
+            let tag = if n.is_f64() { "real" } else { "integer" };
+            out.push_str(&format!("{indent}<{tag}>{n}</{tag}>\n"));
+        }
+        serde_json::Value::String(s) => {
+            let s = escape(s, false, p)?;
+            out.push_str(&format!("{indent}<string>{s}</string>\n"))
+        }
+        serde_json::Value::Array(arr) => {
+            if arr.is_empty() {
+                out.push_str(&format!("{indent}<array/>\n"));
+                return Ok(());
+            }
+            out.push_str(&format!("{indent}<array>\n"));
+            for (i, item) in arr.iter().enumerate() {
+                write_plist_value(out, item, depth + 1, &format!("{p}/{i}"))?;
+            }
+            out.push_str(&format!("{indent}</array>\n"));
+        }
+        serde_json::Value::Object(obj) => {
+            if obj.is_empty() {
+                out.push_str(&format!("{indent}<dict/>\n"));
+                return Ok(());
+            }
+            out.push_str(&format!("{indent}<dict>\n"));
+            for (k, item) in obj.iter() {
+                let item_p = format!("{p}/{k}");
+                let key = escape(k, false, &item_p)?;
+                out.push_str(&format!("{indent}\t<key>{key}</key>\n"));
+                write_plist_value(out, item, depth + 1, &item_p)?;
+            }
+            out.push_str(&format!("{indent}</dict>\n"));
+        }
+    }
+    return Ok(());
+}
+
+fn scalar_text(v: &serde_json::Value) -> String {
+    match v {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+// Escape the special characters of XML text content, or attribute values if |is_attr| is true. |p|
+// is the JSON pointer to the value, which is used to report characters that can not be represented.
+//
+// NOTE
+// XML parsers normalize line endings and (in attribute values) whitespace, so carriage returns are
+// always escaped, and tabs and newlines are escaped in attribute values to preserve them. Control
+// characters other than tab, newline, and carriage return are not allowed in XML 1.0, even as
+// character references, so they are rejected.
+fn escape(s: &str, is_attr: bool, p: &str) -> Result<String> {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' => out.push_str("&#13;"),
+            '"' if is_attr => out.push_str("&quot;"),
+            '\n' if is_attr => out.push_str("&#10;"),
+            '\t' if is_attr => out.push_str("&#9;"),
+            '\t' | '\n' => out.push(c),
+            c if c.is_ascii_control() && c != '\u{7f}' => {
+                let p = yamlanchors::display_pointer(p);
+                return Err(anyhow!(
+                    "[{p}] control character U+{:04X} can not be represented in xml",
+                    c as u32
+                ));
+            }
+            c => out.push(c),
+        }
+    }
+    return Ok(out);
+}
+
+// Escape the text of an XML comment. `--` is not allowed within XML comments, so a space is inserted
+// between each pair of consecutive dashes (e.g., `---` is rendered as `- - -`).
+pub fn escape_comment(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut prev_is_dash = false;
+    for c in s.chars() {
+        if c == '-' && prev_is_dash {
+            out.push(' ');
+        }
+        out.push(c);
+        prev_is_dash = c == '-';
+    }
+    out
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyorder::{self, KeyOrder};
+    use serde_json::json;
+
+    #[test]
+    fn test_xmlwriter_renders_xml_convention() {
+        let mut data = json!({
+            "project": {
+                "@xmlns": "http://maven.apache.org/POM/4.0.0",
+                "modelVersion": "4.0.0",
+                "dependencies": {
+                    "dependency": [
+                        {"artifactId": "junit", "scope": "test"},
+                        {"artifactId": "guava", "optional": null},
+                    ],
+                },
+                "name": {"@lang": "en", "#text": "Tom & Jerry <3"},
+            },
+        });
+        keyorder::apply(&mut data, KeyOrder::Sorted, None).unwrap();
+        let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<project xmlns="http://maven.apache.org/POM/4.0.0">
+  <dependencies>
+    <dependency>
+      <artifactId>junit</artifactId>
+      <scope>test</scope>
+    </dependency>
+    <dependency>
+      <artifactId>guava</artifactId>
+      <optional/>
+    </dependency>
+  </dependencies>
+  <modelVersion>4.0.0</modelVersion>
+  <name lang="en">Tom &amp; Jerry &lt;3</name>
+</project>
+"#;
+        assert_eq!(to_xml(&data).expect("error rendering xml"), expected);
+
+        let err = to_xml(&json!({"a": 1, "b": 2})).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "xml output data must be an object with a single key for the root element"
+        );
+    }
+
+    #[test]
+    fn test_xmlwriter_escapes_whitespace_and_rejects_control_characters() {
+        let data = json!({"a": {"@v": "x\ty\r\nz", "#text": "1\t2\r\n3"}});
+        assert_eq!(
+            to_xml(&data).expect("error rendering xml"),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<a v=\"x&#9;y&#13;&#10;z\">1\t2&#13;\n3</a>\n"
+        );
+
+        let err = to_xml(&json!({"a": {"b": "bell\u{7}"}})).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[/a/b] control character U+0007 can not be represented in xml"
+        );
+        let err = to_xml(&json!({"a": {"@v": "\u{0}"}})).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[/a/@v] control character U+0000 can not be represented in xml"
+        );
+        let err = to_plist(&json!("\u{1b}[0m")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[.] control character U+001B can not be represented in xml"
+        );
+    }
+
+    #[test]
+    fn test_xmlwriter_escapes_comment() {
+        assert_eq!(escape_comment("a - b"), "a - b");
+        assert_eq!(escape_comment("a -- b"), "a - - b");
+        assert_eq!(escape_comment("a --- b ----"), "a - - - b - - - -");
+    }
+
+    #[test]
+    fn test_xmlwriter_renders_plist() {
+        let data = json!({
+            "Label": "com.example.agent",
+            "ProgramArguments": ["/usr/bin/agent", "--verbose"],
+            "RunAtLoad": true,
+            "ThrottleInterval": 30,
+        });
+        let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>Label</key>
+	<string>com.example.agent</string>
+	<key>ProgramArguments</key>
+	<array>
+		<string>/usr/bin/agent</string>
+		<string>--verbose</string>
+	</array>
+	<key>RunAtLoad</key>
+	<true/>
+	<key>ThrottleInterval</key>
+	<integer>30</integer>
+</dict>
+</plist>
+"#;
+        assert_eq!(to_plist(&data).expect("error rendering plist"), expected);
+
+        let err = to_plist(&json!({"a": [null]})).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[/a/0] null can not be represented in plist"
+        );
+    }
+}
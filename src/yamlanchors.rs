@@ -0,0 +1,336 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref NAME_RE: Regex = Regex::new(r"^[A-Za-z0-9_-]+$").unwrap();
+}
+
+// The key of the marker objects returned by senc.yaml.anchor.
+pub const ANCHOR_MARKER: &str = "__senc_yaml_anchor";
+
+// The minimum size (as compact JSON) of the subtrees that are aliased when detecting repeated
+// subtrees. Aliasing smaller subtrees (e.g., `{a: 1}`) makes the output harder to read, not easier.
+const MIN_AUTO_ANCHOR_SIZE: usize = 32;
+
+// The anchors explicitly marked with senc.yaml.anchor, keyed by the JSON pointer of the anchored
+// node.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Anchors {
+    names: BTreeMap<String, String>,
+}
+
+impl Anchors {
+    // Replace the senc.yaml.anchor markers in the data with their values, returning the anchors that
+    // were marked. This is done for all output types so that the markers never end up in the
+    // rendered outputs, and so that the data is validated without them.
+    pub fn extract(data: &mut serde_json::Value) -> Result<Anchors> {
+        let mut anchors = Anchors::default();
+        extract_at(data, "", &mut anchors.names)?;
+        return Ok(anchors);
+    }
+
+    // The anchors within the i-th element of the data, relative to that element (e.g., for the
+    // documents of a yaml_stream output).
+    pub fn for_document(&self, i: usize) -> Anchors {
+        let prefix = format!("/{i}");
+        let names = self
+            .names
+            .iter()
+            .filter_map(|(p, name)| {
+                let rest = p.strip_prefix(&prefix)?;
+                if rest.is_empty() || rest.starts_with('/') {
+                    Some((rest.to_string(), name.clone()))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        Anchors { names }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+fn extract_at(
+    v: &mut serde_json::Value,
+    p: &str,
+    names: &mut BTreeMap<String, String>,
+) -> Result<()> {
+    if let Some(marker) = v.get_mut(ANCHOR_MARKER) {
+        let marker = marker.take();
+        let name = match marker.get("name") {
+            Some(serde_json::Value::String(name)) if NAME_RE.is_match(name) => name.clone(),
+            name => {
+                let p = display_pointer(p);
+                return Err(anyhow!(
+                    "[{p}] yaml anchor name {} is not valid. Must only contain letters, digits, _ and -.",
+                    name.unwrap_or(&serde_json::Value::Null)
+                ));
+            }
+        };
+        *v = match marker {
+            serde_json::Value::Object(mut obj) => {
+                obj.remove("value").unwrap_or(serde_json::Value::Null)
+            }
+            _ => serde_json::Value::Null,
+        };
+        names.insert(p.to_string(), name);
+    }
+
+    match v {
+        serde_json::Value::Object(obj) => {
+            for (k, item) in obj.iter_mut() {
+                extract_at(item, &child_pointer(p, k), names)?;
+            }
+        }
+        serde_json::Value::Array(arr) => {
+            for (i, item) in arr.iter_mut().enumerate() {
+                extract_at(item, &format!("{p}/{i}"), names)?;
+            }
+        }
+        _ => {}
+    }
+    return Ok(());
+}
+
+// The JSON pointer of the child |k| of the node at |p|.
+pub fn child_pointer(p: &str, k: &str) -> String {
+    format!("{p}/{}", k.replace('~', "~0").replace('/', "~1"))
+}
+
+// Display the JSON pointer for error messages, using `.` for the root to match the schema
+// validation errors.
+pub fn display_pointer(p: &str) -> &str {
+    if p.is_empty() {
+        "."
+    } else {
+        p
+    }
+}
+
+// Whether a node is rendered with an anchor, or as an alias of a previously anchored node.
+#[derive(Debug, PartialEq)]
+pub enum Anchor {
+    Define(String),
+    Alias(String),
+}
+
+// Decides which nodes of a YAML document are anchored and aliased. The nodes must be passed to
+// |anchor| in the order they are emitted, and the children of aliased nodes must be skipped.
+//
+// NOTE
+// Repeated subtrees are detected by running the same walk over the document before it is emitted
+// (see |scan|). Only the first occurrence of a repeated subtree is descended into during the walk,
+// so subtrees that only repeat within other repeated subtrees are not anchored, since they end up
+// in the output only once.
+pub struct Planner<'a> {
+    explicit: &'a Anchors,
+    auto: bool,
+    scanning: bool,
+    // The canonical JSON of the subtrees that are seen more than once.
+    repeated: HashSet<String>,
+    seen: HashSet<String>,
+    // The anchors that are defined so far, by name and by the canonical JSON of the anchored node.
+    defined: HashMap<String, String>,
+    defined_by_value: HashMap<String, String>,
+    used_names: HashSet<String>,
+}
+
+impl<'a> Planner<'a> {
+    // Create a planner for the document, with the anchors explicitly marked in the data. When
+    // |auto| is true, repeated subtrees are anchored and aliased as well.
+    pub fn new(data: &serde_json::Value, explicit: &'a Anchors, auto: bool) -> Result<Planner<'a>> {
+        let mut planner = Planner {
+            explicit,
+            auto,
+            scanning: true,
+            repeated: HashSet::new(),
+            seen: HashSet::new(),
+            defined: HashMap::new(),
+            defined_by_value: HashMap::new(),
+            used_names: HashSet::new(),
+        };
+        if auto {
+            planner.scan(data, "", "")?;
+        }
+        planner.scanning = false;
+        planner.seen.clear();
+        planner.defined.clear();
+        planner.defined_by_value.clear();
+        planner.used_names = explicit.names.values().cloned().collect();
+        return Ok(planner);
+    }
+
+    fn scan(&mut self, v: &serde_json::Value, p: &str, hint: &str) -> Result<()> {
+        if let Some(Anchor::Alias(_)) = self.anchor(v, p, hint)? {
+            return Ok(());
+        }
+        match v {
+            serde_json::Value::Object(obj) => {
+                for (k, item) in obj.iter() {
+                    self.scan(item, &child_pointer(p, k), k)?;
+                }
+            }
+            serde_json::Value::Array(arr) => {
+                for (i, item) in arr.iter().enumerate() {
+                    self.scan(item, &format!("{p}/{i}"), hint)?;
+                }
+            }
+            _ => {}
+        }
+        return Ok(());
+    }
+
+    // Decide whether the node at the JSON pointer |p| is anchored or aliased, where |hint| is the
+    // key of the node (or of the sequence it is in), used to name the anchors of repeated subtrees.
+    pub fn anchor(&mut self, v: &serde_json::Value, p: &str, hint: &str) -> Result<Option<Anchor>> {
+        if let Some(name) = self.explicit.names.get(p) {
+            let value = serde_json::to_string(v)?;
+            if let Some(defined) = self.defined.get(name) {
+                if *defined != value {
+                    let p = display_pointer(p);
+                    return Err(anyhow!(
+                        "[{p}] yaml anchor {name} is used for different values"
+                    ));
+                }
+                return Ok(Some(Anchor::Alias(name.clone())));
+            }
+            if self.scanning && !self.seen.insert(value.clone()) {
+                self.repeated.insert(value.clone());
+            }
+            self.defined.insert(name.clone(), value.clone());
+            self.defined_by_value.entry(value).or_insert(name.clone());
+            return Ok(Some(Anchor::Define(name.clone())));
+        }
+
+        let is_container = match v {
+            serde_json::Value::Object(obj) => !obj.is_empty(),
+            serde_json::Value::Array(arr) => !arr.is_empty(),
+            _ => false,
+        };
+        if !self.auto || !is_container {
+            return Ok(None);
+        }
+        let value = serde_json::to_string(v)?;
+        if value.len() < MIN_AUTO_ANCHOR_SIZE {
+            return Ok(None);
+        }
+        if let Some(name) = self.defined_by_value.get(&value) {
+            return Ok(Some(Anchor::Alias(name.clone())));
+        }
+        if self.scanning {
+            if self.seen.insert(value.clone()) {
+                return Ok(None);
+            }
+            self.repeated.insert(value);
+            return Ok(Some(Anchor::Alias(String::new())));
+        }
+        if !self.repeated.contains(&value) {
+            return Ok(None);
+        }
+        let name = self.new_name(hint);
+        self.defined_by_value.insert(value, name.clone());
+        return Ok(Some(Anchor::Define(name)));
+    }
+
+    // Derive a unique anchor name from the key of the node.
+    fn new_name(&mut self, hint: &str) -> String {
+        let base: String = hint
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let base = if base.is_empty() {
+            String::from("anchor")
+        } else {
+            base
+        };
+        let mut name = base.clone();
+        let mut i = 2;
+        while self.used_names.contains(&name) {
+            name = format!("{base}_{i}");
+            i += 1;
+        }
+        self.used_names.insert(name.clone());
+        name
+    }
+}
+
+// Test cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_yamlanchors_extracts_markers() {
+        let step = json!({"__senc_yaml_anchor": {"name": "checkout", "value": {"uses": "actions/checkout@v4"}}});
+        let mut data =
+            json!({"jobs": {"build": {"steps": [step.clone()]}, "test": {"steps": [step]}}});
+        let anchors = Anchors::extract(&mut data).unwrap();
+        assert_eq!(
+            data,
+            json!({
+                "jobs": {
+                    "build": {"steps": [{"uses": "actions/checkout@v4"}]},
+                    "test": {"steps": [{"uses": "actions/checkout@v4"}]},
+                },
+            })
+        );
+        assert_eq!(
+            anchors.names.keys().collect::<Vec<_>>(),
+            vec!["/jobs/build/steps/0", "/jobs/test/steps/0"]
+        );
+        assert!(anchors.for_document(3).is_empty());
+
+        let mut data = json!([{"__senc_yaml_anchor": {"name": "not valid", "value": 1}}]);
+        let err = Anchors::extract(&mut data).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[/0] yaml anchor name \"not valid\" is not valid. Must only contain letters, digits, _ and -."
+        );
+    }
+
+    #[test]
+    fn test_yamlanchors_plans_repeated_subtrees() {
+        let step = json!({"name": "checkout", "uses": "actions/checkout@v4"});
+        let data = json!({
+            "build": [step.clone(), {"run": "make"}],
+            "test": [step, {"run": "make test"}],
+        });
+        let explicit = Anchors::default();
+        let mut planner = Planner::new(&data, &explicit, true).unwrap();
+        assert_eq!(
+            planner.anchor(&data["build"], "/build", "build").unwrap(),
+            None
+        );
+        assert_eq!(
+            planner
+                .anchor(&data["build"][0], "/build/0", "build")
+                .unwrap(),
+            Some(Anchor::Define(String::from("build")))
+        );
+        assert_eq!(
+            planner.anchor(&data["test"], "/test", "test").unwrap(),
+            None
+        );
+        assert_eq!(
+            planner.anchor(&data["test"][0], "/test/0", "test").unwrap(),
+            Some(Anchor::Alias(String::from("build")))
+        );
+    }
+}
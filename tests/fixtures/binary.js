@@ -0,0 +1,10 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+export function main() {
+  return new senc.OutData({
+    out_type: "binary",
+    out_path: "magic.bin",
+    data: new Uint8Array([0x1f, 0x8b, 0x00, 0xff]),
+  });
+}
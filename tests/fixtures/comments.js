@@ -0,0 +1,16 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+export function main() {
+  const data = {
+    jobs: {
+      build: {
+        "runs-on": senc.comment("ubuntu-latest", "Runs on every push"),
+      },
+    },
+  };
+  return new senc.OutDataArray(
+    new senc.OutData({ out_path: "ci.yml", out_type: "yaml", data: data }),
+    new senc.OutData({ out_path: "ci.json", out_type: "json", data: data }),
+  );
+}
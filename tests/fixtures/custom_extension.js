@@ -0,0 +1,6 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+export function main() {
+  return { doubled: senc.ext.testing.double(21) };
+}
@@ -0,0 +1,6 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+export function main(env) {
+  return { foo: "bar", env: env ?? "dev" };
+}
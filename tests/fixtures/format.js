@@ -0,0 +1,9 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+export function main() {
+  return new senc.OutData({
+    format: { tabs: true, trailing_newline: true, line_ending: "crlf" },
+    data: { name: "senc" },
+  });
+}
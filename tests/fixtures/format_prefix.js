@@ -0,0 +1,11 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+export function main() {
+  return new senc.OutData({
+    out_type: "yaml",
+    out_prefix: "# Managed by senc.\n# Do not edit.\n",
+    format: { line_ending: "crlf" },
+    data: { name: "senc" },
+  });
+}
@@ -0,0 +1,17 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+export function main() {
+  return new senc.OutData({
+    out_type: "hcl",
+    data: {
+      locals: senc.hcl.block([], {
+        bucket_name: senc.hcl.expr('"assets-${var.env}"'),
+      }),
+      resource: senc.hcl.block(["aws_s3_bucket", "assets"], {
+        bucket: senc.hcl.expr("local.bucket_name"),
+        tags: { ManagedBy: "senc" },
+      }),
+    },
+  });
+}
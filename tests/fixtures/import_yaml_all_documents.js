@@ -0,0 +1,12 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+// someconfig.yaml has a single document, which is still imported as an array.
+const docs = await senc.import_yaml(`${__dirname}/someconfig.yaml`, { all_documents: true });
+if (!Array.isArray(docs) || docs.length !== 1) {
+  throw new Error("expected an array with a single document");
+}
+
+export function main() {
+  return docs[0];
+}
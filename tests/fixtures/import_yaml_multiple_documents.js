@@ -0,0 +1,8 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+const manifests = await senc.import_yaml(`${__dirname}/manifests.yaml`);
+
+export function main() {
+  return manifests;
+}
@@ -0,0 +1,12 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+export function main() {
+  return new senc.OutData({
+    key_order: "insertion",
+    data: {
+      version: 2.1,
+      jobs: { test: {}, build: {} },
+    },
+  });
+}
@@ -0,0 +1,11 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+export function main() {
+  return new senc.OutData({
+    out_type: "text",
+    out_path: "pre-commit",
+    out_mode: "0755",
+    data: "#!/bin/sh\nnpm test\n",
+  });
+}
@@ -0,0 +1,9 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+export function main() {
+  return new senc.OutDataArray(
+    new senc.OutData({ out_path: "ci.yml", out_type: "yaml", data: { on: "push" } }),
+    new senc.OutData({ out_path: "ci.yml", out_type: "yaml", data: { on: "pull_request" } }),
+  );
+}
@@ -0,0 +1,6 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+import { shout } from "./helpers.js";
+
+export const greeting = shout("hello");
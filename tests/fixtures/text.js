@@ -0,0 +1,12 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+export function main() {
+  const steps = ["FROM node:20-alpine", "RUN npm ci", `CMD ["node", "index.js"]`];
+  return new senc.OutData({
+    out_type: "text",
+    out_path: "Dockerfile",
+    out_prefix: "# syntax=docker/dockerfile:1\n",
+    data: steps.join("\n") + "\n",
+  });
+}
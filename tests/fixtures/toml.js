@@ -0,0 +1,12 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+export function main() {
+  return new senc.OutData({
+    out_type: "toml",
+    data: {
+      build: { command: "npm run build", publish: "dist" },
+      redirects: [{ from: "/old", to: "/new", status: 301 }],
+    },
+  });
+}
@@ -0,0 +1,13 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+export function main() {
+  const env = senc.yaml.anchor("env", { CI: "true" });
+  return new senc.OutData({
+    out_type: "yaml",
+    data: {
+      build: { environment: env },
+      test: { environment: env },
+    },
+  });
+}
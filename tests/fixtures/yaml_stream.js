@@ -0,0 +1,13 @@
+// Copyright (c) Fensak, LLC.
+// SPDX-License-Identifier: MPL-2.0
+
+const manifests = await senc.import_yaml(`${__dirname}/manifests.yaml`, { all_documents: true });
+
+export function main() {
+  return new senc.OutData({
+    out_type: "yaml_stream",
+    data: manifests.map((m) =>
+      m.kind === "Namespace" ? m : { ...m, metadata: { ...m.metadata, namespace: "app" } },
+    ),
+  });
+}